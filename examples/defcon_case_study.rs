@@ -117,6 +117,7 @@ async fn extract_speakers(html_path: &Path, output_dir: &Path, use_llm: bool) ->
                 ChatMessage {
                     role: "system".to_string(),
                     content: "You are a data extraction assistant. Extract all speaker names and their affiliations from the HTML content.".to_string(),
+                    name: None,
                 },
                 ChatMessage {
                     role: "user".to_string(),
@@ -127,6 +128,7 @@ async fn extract_speakers(html_path: &Path, output_dir: &Path, use_llm: bool) ->
                          HTML:\n{}", 
                         html_content
                     ),
+                    name: None,
                 },
             ],
         },
@@ -137,8 +139,14 @@ async fn extract_speakers(html_path: &Path, output_dir: &Path, use_llm: bool) ->
             n: None,
             stop: None,
             seed: Some(42),
+            frequency_penalty: None,
+            presence_penalty: None,
+            logit_bias: None,
+            response_format: None,
         },
         timeout_seconds: 60,
+        max_retries: None,
+        extra_body: None,
     };
 
     println!("  Sending request to LLM...");
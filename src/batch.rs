@@ -0,0 +1,256 @@
+use anyhow::Result;
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use tokio::sync::Semaphore;
+
+/// How a batch reacts to a failing item
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BatchMode {
+    /// Process every item and report each one's outcome
+    #[default]
+    CollectAll,
+    /// Abort the remaining items on the first error and return it
+    FailFast,
+}
+
+/// A cap on the total number of retries across a batch run.
+///
+/// Clones share one counter, so a single budget can be handed to every
+/// worker. Once it is spent, retries stop and batches switch to failing fast,
+/// which keeps a systemic outage from turning into a retry storm.
+#[derive(Debug, Clone)]
+pub struct RetryBudget {
+    remaining: Arc<AtomicU32>,
+}
+
+impl RetryBudget {
+    /// Creates a budget allowing `retries` retries in total.
+    pub fn new(retries: u32) -> Self {
+        Self {
+            remaining: Arc::new(AtomicU32::new(retries)),
+        }
+    }
+
+    /// Spends one retry, returning `false` if the budget is exhausted.
+    pub fn try_acquire(&self) -> bool {
+        self.remaining
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+            .is_ok()
+    }
+
+    /// Returns how many retries are left.
+    pub fn remaining(&self) -> u32 {
+        self.remaining.load(Ordering::Acquire)
+    }
+
+    /// Returns whether every retry has been spent.
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining() == 0
+    }
+}
+
+/// Runs `f` over `items` in order, collecting each item's result.
+///
+/// In `CollectAll` mode every item is processed and failures are returned
+/// alongside successes. In `FailFast` mode processing stops at the first
+/// failure. A batch given a `budget` also stops at the first failure once the
+/// budget is exhausted.
+///
+/// # Errors
+///
+/// In `FailFast` mode, or once `budget` is exhausted, returns the first
+/// item's error.
+pub async fn run_batch<I, T, F, Fut>(
+    items: I,
+    mode: BatchMode,
+    budget: Option<&RetryBudget>,
+    mut f: F,
+) -> Result<Vec<Result<T>>>
+where
+    I: IntoIterator,
+    F: FnMut(I::Item) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut results = Vec::new();
+    for item in items {
+        match f(item).await {
+            Err(e) if mode == BatchMode::FailFast => return Err(e),
+            Err(e) if budget.is_some_and(RetryBudget::is_exhausted) => {
+                return Err(e.context("Retry budget exhausted"));
+            }
+            result => results.push(result),
+        }
+    }
+    Ok(results)
+}
+
+/// Like [`run_batch`], but with up to `concurrency` items in flight at
+/// once. Results are returned in item order, whatever order they finish in.
+///
+/// # Errors
+///
+/// In `FailFast` mode, or once `budget` is exhausted, returns the first
+/// error to finish and drops the items still in flight.
+pub async fn run_batch_concurrent<I, T, F, Fut>(
+    items: I,
+    concurrency: usize,
+    mode: BatchMode,
+    budget: Option<&RetryBudget>,
+    mut f: F,
+) -> Result<Vec<Result<T>>>
+where
+    I: IntoIterator,
+    F: FnMut(I::Item) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let slots = Semaphore::new(concurrency.max(1));
+    let slots = &slots;
+    let mut in_flight: FuturesUnordered<_> = items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let work = f(item);
+            async move {
+                let _permit = slots
+                    .acquire()
+                    .await
+                    .expect("batch semaphore is never closed");
+                (index, work.await)
+            }
+        })
+        .collect();
+
+    let mut results: Vec<Option<Result<T>>> = (0..in_flight.len()).map(|_| None).collect();
+    while let Some((index, result)) = in_flight.next().await {
+        match result {
+            Err(e) if mode == BatchMode::FailFast => return Err(e),
+            Err(e) if budget.is_some_and(RetryBudget::is_exhausted) => {
+                return Err(e.context("Retry budget exhausted"));
+            }
+            result => results[index] = Some(result),
+        }
+    }
+    Ok(results.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Runs a batch over items 1..=4 where item 2 fails, returning the batch
+    /// result and the items that were attempted.
+    async fn run_with_failure(mode: BatchMode) -> (Result<Vec<Result<u32>>>, Vec<u32>) {
+        let attempted = RefCell::new(Vec::new());
+        let result = run_batch(1..=4, mode, None, |item| {
+            attempted.borrow_mut().push(item);
+            async move {
+                if item == 2 {
+                    anyhow::bail!("item {} failed", item);
+                }
+                Ok(item * 10)
+            }
+        })
+        .await;
+        (result, attempted.into_inner())
+    }
+
+    #[tokio::test]
+    async fn test_fail_fast_stops_at_first_error() {
+        let (result, attempted) = run_with_failure(BatchMode::FailFast).await;
+        assert_eq!(result.unwrap_err().to_string(), "item 2 failed");
+        assert_eq!(attempted, [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_collect_all_continues_past_errors() {
+        let (result, attempted) = run_with_failure(BatchMode::CollectAll).await;
+        let results = result.unwrap();
+        assert_eq!(attempted, [1, 2, 3, 4]);
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].as_ref().unwrap(), &10);
+        assert!(results[1].is_err());
+        assert_eq!(results[3].as_ref().unwrap(), &40);
+    }
+
+    #[tokio::test]
+    async fn test_retry_budget_is_shared_across_items() {
+        let budget = RetryBudget::new(4);
+        let attempted = RefCell::new(Vec::new());
+        let retries = RefCell::new(0);
+
+        // Every item fails and retries up to 3 times while the budget allows
+        let result: Result<Vec<Result<()>>> =
+            run_batch(1..=5, BatchMode::CollectAll, Some(&budget), |item| {
+                attempted.borrow_mut().push(item);
+                let budget = budget.clone();
+                let retries = &retries;
+                async move {
+                    for _ in 0..3 {
+                        if !budget.try_acquire() {
+                            break;
+                        }
+                        *retries.borrow_mut() += 1;
+                    }
+                    anyhow::bail!("item {} failed", item)
+                }
+            })
+            .await;
+
+        assert_eq!(*retries.borrow(), 4);
+        assert!(budget.is_exhausted());
+        assert!(!budget.try_acquire());
+        // Item 2 spent the last retry, so the batch failed fast after it
+        assert_eq!(attempted.into_inner(), [1, 2]);
+        let err = result.unwrap_err();
+        assert_eq!(err.to_string(), "Retry budget exhausted");
+        assert_eq!(err.root_cause().to_string(), "item 2 failed");
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_batch_is_bounded_and_ordered() {
+        let running = AtomicU32::new(0);
+        let peak = AtomicU32::new(0);
+        let (running, peak) = (&running, &peak);
+
+        // Later items finish first, so completion order is reversed
+        let results = run_batch_concurrent(
+            1..=6u64,
+            3,
+            BatchMode::CollectAll,
+            None,
+            |item| async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(std::time::Duration::from_millis(60 - item * 10)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                if item == 4 {
+                    anyhow::bail!("item {} failed", item);
+                }
+                Ok(item * 10)
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+        let values: Vec<_> = results.iter().map(|r| r.as_ref().ok().copied()).collect();
+        assert_eq!(
+            values,
+            [Some(10), Some(20), Some(30), None, Some(50), Some(60)]
+        );
+
+        let result =
+            run_batch_concurrent(1..=6u64, 2, BatchMode::FailFast, None, |item| async move {
+                if item == 2 {
+                    anyhow::bail!("item {} failed", item);
+                }
+                Ok(item)
+            })
+            .await;
+        assert_eq!(result.unwrap_err().to_string(), "item 2 failed");
+    }
+}
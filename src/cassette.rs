@@ -0,0 +1,191 @@
+use crate::openai_client::OpenAIError;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Whether a cassette records new interactions or replays recorded ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Send requests as usual and save each successful response
+    Record,
+    /// Answer requests from the recording without touching the network
+    Replay,
+}
+
+/// A recorded request and the response body it got
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Interaction {
+    /// `request_key` of the URL and body, for quick lookup
+    key: String,
+    url: String,
+    request: Value,
+    response: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+/// LLM request/response pairs stored in a JSON file, for running enrichment
+/// deterministically without a server.
+///
+/// Requests are matched on their endpoint URL and JSON body; API keys and
+/// other headers are neither matched nor recorded.
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    interactions: Mutex<Vec<Interaction>>,
+}
+
+impl Cassette {
+    /// Opens the cassette at `path`. Recording adds to an existing file or
+    /// starts a new one; replaying requires the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read or parsed, or is missing
+    /// in replay mode.
+    pub fn open(path: impl Into<PathBuf>, mode: CassetteMode) -> Result<Self> {
+        let path = path.into();
+        let file = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse cassette {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && mode == CassetteMode::Record => {
+                CassetteFile::default()
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read cassette {}", path.display()));
+            }
+        };
+        Ok(Self {
+            path,
+            mode,
+            interactions: Mutex::new(file.interactions),
+        })
+    }
+
+    /// Whether the cassette records or replays
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    /// Path of the cassette file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of recorded interactions
+    pub fn len(&self) -> usize {
+        self.interactions.lock().unwrap().len()
+    }
+
+    /// Whether nothing has been recorded
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the recorded response to `request` sent to `url`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `OpenAIError::Config` if the request wasn't recorded.
+    pub(crate) fn replay(&self, url: &str, request: &Value) -> Result<String> {
+        let key = request_key(url, request);
+        let interactions = self.interactions.lock().unwrap();
+        interactions
+            .iter()
+            .find(|i| i.key == key && i.url == url && i.request == *request)
+            .map(|i| i.response.clone())
+            .ok_or_else(|| {
+                OpenAIError::Config(format!(
+                    "no response to request {} recorded in cassette {}",
+                    key,
+                    self.path.display()
+                ))
+                .into()
+            })
+    }
+
+    /// Records `response` as the answer to `request` sent to `url`, replacing
+    /// any earlier recording of the same request, and saves the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cassette file can't be written.
+    pub(crate) fn record(&self, url: &str, request: &Value, response: &str) -> Result<()> {
+        let interaction = Interaction {
+            key: request_key(url, request),
+            url: url.to_string(),
+            request: request.clone(),
+            response: response.to_string(),
+        };
+        let mut interactions = self.interactions.lock().unwrap();
+        interactions.retain(|i| {
+            !(i.key == interaction.key && i.url == interaction.url && i.request == *request)
+        });
+        interactions.push(interaction);
+
+        let file = CassetteFile {
+            interactions: interactions.clone(),
+        };
+        let json = serde_json::to_string_pretty(&file)?;
+        std::fs::write(&self.path, json)
+            .with_context(|| format!("Failed to write cassette {}", self.path.display()))
+    }
+}
+
+/// Hashes `url` and `request` with 64-bit FNV-1a. Object keys serialize in
+/// sorted order, so equal requests always get the same key.
+fn request_key(url: &str, request: &Value) -> String {
+    let body = request.to_string();
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in url.bytes().chain([b'\n']).chain(body.bytes()) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("llm.json");
+        assert!(Cassette::open(&path, CassetteMode::Replay).is_err());
+
+        let request =
+            serde_json::json!({"model": "m", "messages": [{"role": "user", "content": "hi"}]});
+        let cassette = Cassette::open(&path, CassetteMode::Record).unwrap();
+        cassette
+            .record("http://llm/v1/chat/completions", &request, "first")
+            .unwrap();
+        cassette
+            .record("http://llm/v1/chat/completions", &request, "second")
+            .unwrap();
+        assert_eq!(cassette.len(), 1);
+
+        let cassette = Cassette::open(&path, CassetteMode::Replay).unwrap();
+        let reordered: Value = serde_json::from_str(
+            r#"{"messages": [{"content": "hi", "role": "user"}], "model": "m"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            cassette
+                .replay("http://llm/v1/chat/completions", &reordered)
+                .unwrap(),
+            "second"
+        );
+        let err = cassette
+            .replay("http://other/v1/chat/completions", &request)
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<OpenAIError>().unwrap().kind(), "config");
+    }
+}
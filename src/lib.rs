@@ -1,5 +1,40 @@
+pub mod batch;
+pub mod cassette;
 pub mod download;
+pub mod normalize;
 pub mod openai_client;
+pub mod pipeline;
+pub mod profile;
+pub mod rdf;
+pub mod softblock;
+pub mod sparql;
+pub mod speakers;
+pub mod spider;
+pub mod turtle;
+pub mod warc;
+pub mod wikidata;
 
-pub use download::TorDownloader;
-pub use openai_client::{ChatMessage, EnrichConfig, GenerationParams, OpenAIClient, PromptConfig};
+pub use batch::{BatchMode, RetryBudget};
+pub use cassette::{Cassette, CassetteMode};
+pub use download::{
+    CircuitHandle, Headers, HttpMethod, HttpResponse, HttpResponseMeta, HttpStatusError,
+    IsolationMode, Keepalive, RedirectAction, RedirectPolicy, RequestOptions, SelfTestReport,
+    StreamedResponse, TorDownloader,
+};
+pub use normalize::{UrlNormalizer, normalize_url};
+pub use openai_client::{
+    ChatMessage, CostEstimate, EnrichConfig, EnrichResult, GenerationParams, OpenAIClient,
+    OpenAIError, Preset, PromptConfig, ResponseFormat, count_tokens, estimate_cost, repair_json,
+    select_context,
+};
+pub use pipeline::{Pipeline, Step};
+pub use profile::Profile;
+pub use rdf::{Graph, Term};
+pub use softblock::{SoftBlockKind, detect_soft_block};
+pub use speakers::{
+    CompanySpeakers, Speaker, link_affiliations, parse_speakers, speakers_by_company,
+    speakers_to_foaf,
+};
+pub use spider::{Spider, extract_links, mirror_path};
+pub use turtle::{TurtleError, parse_turtle, split_rdf};
+pub use wikidata::{ConstructOutput, CsvMapping, WikidataDownloader, json_bindings_to_rdf};
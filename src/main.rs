@@ -1,14 +1,56 @@
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use decisym_defcon33::{EnrichConfig, OpenAIClient, TorDownloader};
-use std::path::PathBuf;
-use tracing::info;
+use decisym_defcon33::batch::run_batch_concurrent;
+use decisym_defcon33::download::validate_header;
+use decisym_defcon33::{
+    BatchMode, ChatMessage, EnrichConfig, HttpMethod, IsolationMode, OpenAIClient, OpenAIError,
+    Pipeline, Preset, Profile, PromptConfig, Spider, TorDownloader, UrlNormalizer,
+    WikidataDownloader, parse_speakers, parse_turtle, speakers_to_foaf, split_rdf,
+};
+use futures::TryStreamExt;
+use regex::Regex;
+use std::collections::HashSet;
+use std::future::Future;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
 use tracing_subscriber::EnvFilter;
 
+// native-tls delegates to the platform TLS library, so the backend is fixed per target
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+macro_rules! tls_backend {
+    () => {
+        "Security.framework (SecureTransport)"
+    };
+}
+#[cfg(target_os = "windows")]
+macro_rules! tls_backend {
+    () => {
+        "SChannel"
+    };
+}
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+macro_rules! tls_backend {
+    () => {
+        "OpenSSL"
+    };
+}
+
+/// Version details shown by `--version`, for bug reports.
+/// Keep the arti-client version in sync with Cargo.toml.
+const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    "\narti-client: 0.22 (static-sqlite, geoip, onion-service-client)",
+    "\nTLS backend: native-tls ",
+    tls_backend!(),
+    "\nOnion service support: yes",
+);
+
 /// A privacy-focused tool for collecting content through Tor and enriching it with local LLMs
 #[derive(Parser, Debug)]
 #[command(name = "decisym_defcon33")]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, long_version = LONG_VERSION, about, long_about = None)]
 struct Cli {
     /// Subcommand to execute
     #[command(subcommand)]
@@ -23,14 +65,44 @@ struct Cli {
     verbose: bool,
 }
 
+// Parsed once at startup, so the size difference between variants doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Download content from URLs through Tor for privacy
     Collect {
-        /// URL to download
-        url: String,
+        /// URLs to download, one after another over the same Tor client; `-`
+        /// reads more URLs from stdin, one per line
+        #[arg(value_name = "URL", required_unless_present = "url_file")]
+        urls: Vec<String>,
+
+        /// Also download the URLs in FILE, one per line (blank lines and
+        /// `#` comments are skipped)
+        #[arg(long = "url-file", value_name = "FILE")]
+        url_file: Option<PathBuf>,
+
+        /// Stop at the first failed URL instead of downloading the rest and
+        /// reporting every failure at the end
+        #[arg(long = "fail-fast")]
+        fail_fast: bool,
+
+        /// Download up to N URLs at once, each over its own circuit; requests
+        /// to the same host are still spaced by --wait
+        #[arg(long = "concurrency", value_name = "N", default_value = "1")]
+        concurrency: usize,
+
+        /// Let at most N connections build circuits at once (0 means
+        /// unlimited)
+        #[arg(
+            long = "max-concurrent-circuits",
+            value_name = "N",
+            default_value = "0"
+        )]
+        max_concurrent_circuits: usize,
 
         /// Write output to FILE instead of using the server-provided name
+        /// (with --spider, the directory to mirror pages into); only with a
+        /// single URL
         #[arg(short = 'o', long = "output", value_name = "FILE")]
         output: Option<PathBuf>,
 
@@ -38,18 +110,43 @@ enum Commands {
         #[arg(short = 'O', value_name = "FILE", conflicts_with = "output")]
         output_alt: Option<PathBuf>,
 
+        /// Save downloads under DIR, created if needed, with the
+        /// server-provided names (with --spider, the directory to mirror
+        /// pages into)
+        #[arg(
+            long = "output-dir",
+            value_name = "DIR",
+            conflicts_with_all = ["output", "output_alt"]
+        )]
+        output_dir: Option<PathBuf>,
+
+        /// Use the user agent, wait, headers, and connect retries of profile
+        /// NAME from the `--profiles` file; other flags override them
+        #[arg(long = "profile", value_name = "NAME")]
+        profile: Option<String>,
+
+        /// YAML file of named download profiles
+        #[arg(
+            long = "profiles",
+            value_name = "FILE",
+            default_value = "profiles.yaml",
+            requires = "profile"
+        )]
+        profiles: PathBuf,
+
         /// Set User-Agent header (default: Chrome)
         #[arg(short = 'A', long = "user-agent", value_name = "STRING")]
         user_agent: Option<String>,
 
-        /// Wait SECONDS between requests (rate limiting)
+        /// Wait DURATION between requests to the same host (rate limiting),
+        /// e.g. `30s` or `5m`; a bare number is seconds [default: 1]
         #[arg(
             short = 'w',
             long = "wait",
-            value_name = "SECONDS",
-            default_value = "1"
+            value_name = "DURATION",
+            value_parser = parse_seconds
         )]
-        wait: u64,
+        wait: Option<u64>,
 
         /// Maximum number of redirects to follow
         #[arg(long = "max-redirect", value_name = "NUM", default_value = "5")]
@@ -59,10 +156,66 @@ enum Commands {
         #[arg(short = 'k', long = "insecure")]
         insecure: bool,
 
-        /// Download buffer size in bytes
-        #[arg(long = "buffer-size", value_name = "BYTES", default_value = "8192")]
+        /// Prefer Tor exit relays in this country (two-letter code, best-effort)
+        #[arg(long = "exit-country", value_name = "CC")]
+        exit_country: Option<String>,
+
+        /// Send a unique X-Request-Id header with each request and log it
+        #[arg(long = "request-id")]
+        request_id: bool,
+
+        /// Use a fresh Tor circuit for every request so requests can't be
+        /// linked by exit relay (slower)
+        #[arg(long = "new-circuit-per-request")]
+        new_circuit_per_request: bool,
+
+        /// Download buffer size, e.g. `8192` or `64K`
+        #[arg(
+            long = "buffer-size",
+            value_name = "SIZE",
+            default_value = "8192",
+            value_parser = parse_size::<usize>
+        )]
         buffer_size: usize,
 
+        /// Grow the download buffer while reads keep filling it
+        #[arg(long = "adaptive-buffer")]
+        adaptive_buffer: bool,
+
+        /// Append a WARC record of the download (request and response) to FILE
+        #[arg(long = "warc", value_name = "FILE")]
+        warc: Option<PathBuf>,
+
+        /// Fail a download whose 200 response looks like a "not found" page,
+        /// a login wall, or a nearly empty page, instead of only warning
+        #[arg(long = "fail-on-softblock")]
+        fail_on_soft_block: bool,
+
+        /// Abort responses larger than SIZE, e.g. `10M` or `1G` (0 means
+        /// unlimited)
+        #[arg(
+            long = "max-size",
+            value_name = "SIZE",
+            default_value = "0",
+            value_parser = parse_size::<u64>
+        )]
+        max_size: u64,
+
+        /// Give up when connecting, the TLS handshake, or a single read
+        /// stalls for longer than DURATION, e.g. `30s` or `2m`
+        #[arg(
+            long = "timeout",
+            value_name = "DURATION",
+            default_value = "120",
+            value_parser = parse_seconds
+        )]
+        timeout: u64,
+
+        /// Retry a dropped, reset, or timed-out connection up to N times,
+        /// each on a new circuit [default: 2]
+        #[arg(long = "connect-retries", value_name = "N")]
+        connect_retries: Option<u32>,
+
         /// Default filename for URLs without a filename
         #[arg(
             long = "default-filename",
@@ -71,59 +224,576 @@ enum Commands {
         )]
         default_filename: String,
 
-        /// HTTP method to use (GET or POST)
+        /// HTTP method to use (GET, POST, PUT, DELETE, PATCH, HEAD, OPTIONS)
         #[arg(
             short = 'X',
             long = "method",
             value_name = "METHOD",
             default_value = "GET"
         )]
-        method: Option<String>,
+        method: HttpMethod,
+
+        /// Authenticate with HTTP Basic credentials
+        #[arg(short = 'u', long = "user", value_name = "USER:PASSWORD")]
+        basic_auth: Option<String>,
+
+        /// Authenticate with `Authorization: Bearer TOKEN`
+        #[arg(long = "bearer", value_name = "TOKEN", conflicts_with = "basic_auth")]
+        bearer: Option<String>,
 
         /// Add custom HTTP header (can be used multiple times)
         #[arg(short = 'H', long = "header", value_name = "HEADER")]
         headers: Vec<String>,
 
-        /// HTTP request body data (for POST requests)
+        /// Read additional headers from FILE, one `Name: Value` per line
+        #[arg(long = "headers-file", value_name = "FILE")]
+        headers_file: Option<PathBuf>,
+
+        /// HTTP request body data (for POST requests); `-` or `@-` reads it
+        /// from stdin
         #[arg(short = 'd', long = "data", value_name = "DATA")]
         data: Option<String>,
 
-        /// HTTP request body data from file (for POST requests)
+        /// HTTP request body data from file (for POST requests), streamed from
+        /// disk; `-` reads stdin like `--data -`
         #[arg(long = "data-file", value_name = "FILE", conflicts_with = "data")]
         data_file: Option<PathBuf>,
+
+        /// Gzip the request body and send it with `Content-Encoding: gzip`
+        #[arg(long = "compress-body")]
+        compress_body: bool,
+
+        /// Compression to request: `auto` (gzip, deflate, or br, decoded on
+        /// arrival) or `none` to send no Accept-Encoding and save compressed
+        /// responses exactly as received
+        #[arg(long = "accept-encoding", value_name = "MODE", default_value = "auto")]
+        accept_encoding: AcceptEncoding,
+
+        /// Print an equivalent curl command (via a local Tor SOCKS proxy) to
+        /// stderr, with auth headers redacted
+        #[arg(long = "print-curl")]
+        print_curl: bool,
+
+        /// Recursively download the pages the URL links to, saving each under
+        /// the output directory by host and path
+        #[arg(
+            long = "spider",
+            conflicts_with_all = ["data", "data_file", "headers", "headers_file", "print_curl"]
+        )]
+        spider: bool,
+
+        /// Follow links at most N hops from the start page
+        #[arg(
+            long = "max-depth",
+            value_name = "N",
+            default_value = "2",
+            requires = "spider"
+        )]
+        max_depth: u32,
+
+        /// Only follow links to the start page's host
+        #[arg(long = "same-host", requires = "spider")]
+        same_host: bool,
     },
 
     /// Enrich content using an OpenAI-compatible API
     Enrich {
-        /// Path to the configuration file (YAML or JSON)
-        #[arg(short = 'c', long = "config", value_name = "PATH")]
-        config_file: PathBuf,
+        /// Path to the configuration file (YAML, JSON, or markdown with YAML front-matter)
+        #[arg(
+            short = 'c',
+            long = "config",
+            value_name = "PATH",
+            required_unless_present_any = ["prompt", "user", "user_file"]
+        )]
+        config_file: Option<PathBuf>,
+
+        /// Completion prompt to send instead of using a configuration file
+        #[arg(
+            long = "prompt",
+            value_name = "TEXT",
+            conflicts_with_all = ["config_file", "system", "system_file", "user", "user_file"],
+            requires = "model"
+        )]
+        prompt: Option<String>,
+
+        /// System message for a chat request built from the command line
+        #[arg(
+            long = "system",
+            value_name = "TEXT",
+            conflicts_with = "config_file",
+            requires = "user_message"
+        )]
+        system: Option<String>,
+
+        /// Read the system message from a file instead of `--system`
+        #[arg(
+            long = "system-file",
+            value_name = "PATH",
+            conflicts_with_all = ["config_file", "system"],
+            requires = "user_message"
+        )]
+        system_file: Option<PathBuf>,
+
+        /// User message for a chat request built from the command line
+        #[arg(
+            long = "user",
+            value_name = "TEXT",
+            group = "user_message",
+            conflicts_with = "config_file",
+            requires = "model"
+        )]
+        user: Option<String>,
+
+        /// Read the user message from a file instead of `--user`
+        #[arg(
+            long = "user-file",
+            value_name = "PATH",
+            group = "user_message",
+            conflicts_with = "config_file",
+            requires = "model"
+        )]
+        user_file: Option<PathBuf>,
+
+        /// Model name for a request built from the command line
+        #[arg(long = "model", value_name = "NAME", conflicts_with = "config_file")]
+        model: Option<String>,
+
+        /// API endpoint URL for a request built from the command line
+        #[arg(
+            long = "api-url",
+            value_name = "URL",
+            default_value = "http://localhost:8000/v1",
+            conflicts_with = "config_file"
+        )]
+        api_url: String,
 
-        /// Optional input file to process (overrides any file path in config)
+        /// Input file to process (overrides any file path in config); repeat
+        /// to enrich several files, one request each, with `--output-suffix`
         #[arg(short = 'i', long = "input")]
-        input_file: Option<PathBuf>,
+        input_files: Vec<PathBuf>,
 
         /// Output file (if not specified, prints to stdout)
+        #[arg(short = 'o', long = "output", group = "destination")]
+        output: Option<PathBuf>,
+
+        /// Write each input's response next to it, at the input path with
+        /// this appended (e.g. `.enriched.json` turns `page.html` into
+        /// `page.html.enriched.json`)
+        #[arg(
+            long = "output-suffix",
+            value_name = "SUFFIX",
+            group = "destination",
+            requires = "input_files"
+        )]
+        output_suffix: Option<String>,
+
+        /// Output format: `text` writes the first choice as-is; `jsonl` writes
+        /// every choice (see `n` in the configuration) as a
+        /// `{"index", "content"}` JSON line
+        #[arg(
+            long = "format",
+            value_name = "FORMAT",
+            default_value = "text",
+            conflicts_with = "stream"
+        )]
+        format: OutputFormat,
+
+        /// Local file to pick context snippets from; the paragraphs best
+        /// matching `--context-query` replace `{{context}}` in the prompt
+        #[arg(long = "context-file", value_name = "PATH", requires = "context_query")]
+        context_file: Option<PathBuf>,
+
+        /// Keywords used to rank the paragraphs of `--context-file`
+        #[arg(long = "context-query", value_name = "TEXT", requires = "context_file")]
+        context_query: Option<String>,
+
+        /// Number of context snippets to inject
+        #[arg(long = "context-top-k", value_name = "N", default_value_t = 3)]
+        context_top_k: usize,
+
+        /// Sampling preset: deterministic, precise, balanced, or creative.
+        /// Overrides temperature, top_p, and seed from the configuration.
+        #[arg(long = "preset", value_name = "NAME")]
+        preset: Option<Preset>,
+
+        /// Send a unique X-Request-Id header with the request and log it
+        #[arg(long = "request-id")]
+        request_id: bool,
+
+        /// Print an equivalent curl command to stderr, with the API key redacted
+        #[arg(long = "print-curl")]
+        print_curl: bool,
+
+        /// Print the reply to stdout as it is generated (chat prompts only).
+        /// Streamed requests are not retried.
+        #[arg(long = "stream", conflicts_with_all = ["extract", "repair_json"])]
+        stream: bool,
+
+        /// Send the request through Tor, for hosted endpoints that shouldn't
+        /// see where it comes from
+        #[arg(long = "tor", conflicts_with = "stream")]
+        tor: bool,
+
+        /// Retry timeouts, connection failures, 429s, and 500, 502, 503, and
+        /// 504 responses up to N times, overriding `max_retries` in the
+        /// configuration (default 2)
+        #[arg(long = "retries", value_name = "N")]
+        retries: Option<u32>,
+
+        /// Give up after DURATION in total (e.g. `90s` or `5m`), including
+        /// retries and the waits between them
+        #[arg(long = "total-timeout", value_name = "DURATION", value_parser = parse_seconds)]
+        total_timeout: Option<u64>,
+
+        /// Output only the first capture group of this regex applied to the response
+        #[arg(long = "extract", value_name = "REGEX")]
+        extract: Option<String>,
+
+        /// If the output should be JSON but doesn't parse (e.g. it was cut off
+        /// at the token limit), close open strings, arrays, and objects
+        #[arg(long = "repair-json")]
+        repair_json: bool,
+
+        /// Skip the request if the output file already exists and is non-empty
+        #[arg(long = "skip-existing", requires = "destination")]
+        skip_existing: bool,
+
+        /// On failure, print `{error_kind, message, status}` JSON to stderr and
+        /// exit with a code per kind: 2 config, 3 server, 4 timeout,
+        /// 5 connection, 6 invalid response, 1 other
+        #[arg(long = "error-json")]
+        error_json: bool,
+    },
+
+    /// Extract conference speakers from an HTML page with an LLM and write
+    /// them as FOAF RDF
+    Speakers {
+        /// HTML file to read, or an http(s) URL to collect through Tor
+        input: String,
+
+        /// Model name
+        #[arg(
+            long = "model",
+            value_name = "NAME",
+            default_value = "Qwen/Qwen3-30B-A3B-Instruct-2507"
+        )]
+        model: String,
+
+        /// API endpoint URL
+        #[arg(
+            long = "api-url",
+            value_name = "URL",
+            default_value = "http://localhost:8000/v1"
+        )]
+        api_url: String,
+
+        /// Request timeout, e.g. `600` or `20m`; large pages can take a while
+        /// to process
+        #[arg(
+            long = "timeout",
+            value_name = "DURATION",
+            default_value = "1200",
+            value_parser = parse_seconds
+        )]
+        timeout: u64,
+
+        /// Turtle output file (if not specified, prints to stdout)
         #[arg(short = 'o', long = "output")]
         output: Option<PathBuf>,
+
+        /// Also save the extracted speakers as JSON
+        #[arg(long = "json", value_name = "PATH")]
+        json: Option<PathBuf>,
+    },
+
+    /// List the models an OpenAI-compatible server provides
+    Models {
+        /// API endpoint URL
+        #[arg(
+            long = "api-url",
+            value_name = "URL",
+            default_value = "http://localhost:8000/v1"
+        )]
+        api_url: String,
+    },
+
+    /// Download entities of chosen Wikidata classes and industries through
+    /// Tor and convert them to Turtle
+    Wikidata {
+        /// Directory for the downloaded CSV and the Turtle output
+        #[arg(
+            long = "data-dir",
+            value_name = "DIR",
+            default_value = "runtime/wikidata"
+        )]
+        data_dir: PathBuf,
+
+        /// Classes to query, as comma-separated item IDs (default: companies,
+        /// businesses, and nonprofits)
+        #[arg(long = "types", value_name = "QIDS", value_delimiter = ',')]
+        types: Vec<String>,
+
+        /// Industries to query, as comma-separated item IDs (default:
+        /// security and technology industries)
+        #[arg(long = "industries", value_name = "QIDS", value_delimiter = ',')]
+        industries: Vec<String>,
+
+        /// SPARQL endpoint to query
+        #[arg(
+            long = "endpoint",
+            value_name = "URL",
+            default_value = "https://query.wikidata.org/sparql"
+        )]
+        endpoint: String,
+
+        /// Only print how many entities match, without downloading them
+        #[arg(long = "count-only")]
+        count_only: bool,
+
+        /// Write grouped, human-readable Turtle instead of one statement per
+        /// line
+        #[arg(long = "pretty")]
+        pretty: bool,
+    },
+
+    /// Run the steps of a pipeline YAML file (collect, enrich, convert,
+    /// link, analyze), feeding each step's output to the next
+    RunPipeline {
+        /// Pipeline definition
+        file: PathBuf,
+    },
+
+    /// Parse a Turtle or N-Triples file and print its triple, subject, and
+    /// predicate counts and prefixes
+    RdfStats {
+        /// RDF file to check
+        file: PathBuf,
+    },
+
+    /// Split a Turtle or N-Triples file into Turtle shards of at most
+    /// `--max-triples` triples, written to `<PREFIX>-001.ttl` and onward
+    SplitRdf {
+        /// RDF file to split
+        file: PathBuf,
+
+        /// Most triples per shard
+        #[arg(long = "max-triples", value_name = "N")]
+        max_triples: usize,
+
+        /// Shard path prefix (defaults to the input path without its extension)
+        #[arg(short, long, value_name = "PREFIX")]
+        output: Option<PathBuf>,
     },
 }
 
+/// Reads `Name: Value` header lines from a file, skipping blank lines and `#` comments.
+fn read_headers_file(path: &Path) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(path).context("Failed to read headers file")?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/// Merges the `-H` headers with any loaded from `headers_file`, in that
+/// order.
+///
+/// # Errors
+///
+/// Returns an error if the file can't be read or a header is malformed.
+fn request_headers(headers: &[String], headers_file: Option<&Path>) -> Result<Vec<String>> {
+    let mut headers = headers.to_vec();
+    if let Some(path) = headers_file {
+        headers.extend(read_headers_file(path)?);
+    }
+    for header in &headers {
+        validate_header(header)?;
+    }
+    Ok(headers)
+}
+
+/// Splits `--user` credentials into user and password.
+fn parse_basic_auth(credentials: &str) -> Result<(&str, &str)> {
+    credentials.split_once(':').with_context(|| {
+        format!(
+            "A password is required for --user: use --user {}:PASSWORD",
+            credentials
+        )
+    })
+}
+
+/// Whether the request body comes from stdin: `-d -`, `-d @-`, or
+/// `--data-file -`. `--data` and `--data-file` can't both be given.
+fn body_from_stdin(data: Option<&str>, data_file: Option<&Path>) -> bool {
+    matches!(data, Some("-" | "@-")) || data_file == Some(Path::new("-"))
+}
+
+/// Resolves an in-memory request body from `--data`, reading from `stdin`
+/// when `body_from_stdin` says so.
+///
+/// Any other `--data-file` yields `None`; see `upload_file`.
+///
+/// # Errors
+///
+/// Returns an error if stdin can't be read or is empty, since an empty
+/// pipe is almost always a mistake upstream rather than a wanted empty body.
+fn read_request_body(
+    data: Option<&str>,
+    data_file: Option<&Path>,
+    stdin: &mut impl Read,
+) -> Result<Option<Vec<u8>>> {
+    if body_from_stdin(data, data_file) {
+        let mut body = Vec::new();
+        stdin
+            .read_to_end(&mut body)
+            .context("Failed to read request body from stdin")?;
+        if body.is_empty() {
+            anyhow::bail!(
+                "The request body was to be read from stdin, but stdin was empty; \
+                 pipe the body in, e.g. `echo 'q=1' | decisym_defcon33 collect URL -d -`"
+            );
+        }
+        return Ok(Some(body));
+    }
+    Ok(data.map(|data| data.as_bytes().to_vec()))
+}
+
+/// Returns the `--data-file` to stream from disk as the request body, unless
+/// it names stdin.
+fn upload_file(data_file: Option<&Path>) -> Option<&Path> {
+    data_file.filter(|file| *file != Path::new("-"))
+}
+
+/// Reads the URLs in a `--url-file`, one per line, skipping blank lines and
+/// `#` comments.
+fn read_url_file(path: &Path) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read URL file {}", path.display()))?;
+    Ok(parse_url_list(&content))
+}
+
+fn parse_url_list(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+/// Drops URLs that normalize the same as an earlier one, so e.g.
+/// `https://HOST/path` and `https://host/path/` are only fetched once.
+fn distinct_urls(urls: Vec<String>) -> Vec<String> {
+    let total = urls.len();
+    let distinct = UrlNormalizer::new().dedup(urls);
+    if distinct.len() < total {
+        info!("Skipping {} duplicate URL(s)", total - distinct.len());
+    }
+    distinct
+}
+
+/// Replaces a `-` among the URL arguments with the URLs read from `stdin`,
+/// in the same format as a `--url-file`.
+///
+/// # Errors
+///
+/// Returns an error if `-` is given more than once, stdin can't be read, or
+/// it holds no URLs.
+fn expand_stdin_urls(urls: &[String], stdin: &mut impl Read) -> Result<Vec<String>> {
+    match urls.iter().filter(|url| *url == "-").count() {
+        0 => return Ok(urls.to_vec()),
+        1 => {}
+        _ => anyhow::bail!("`-` (read URLs from stdin) can only be given once"),
+    }
+    let mut content = String::new();
+    stdin
+        .read_to_string(&mut content)
+        .context("Failed to read URLs from stdin")?;
+    let from_stdin = parse_url_list(&content);
+    if from_stdin.is_empty() {
+        anyhow::bail!(
+            "No URLs on stdin: pipe them in one per line, e.g. \
+             `cat urls.txt | decisym_defcon33 collect -`"
+        );
+    }
+
+    let mut expanded = Vec::new();
+    for url in urls {
+        if url == "-" {
+            expanded.extend(from_stdin.iter().cloned());
+        } else {
+            expanded.push(url.clone());
+        }
+    }
+    Ok(expanded)
+}
+
+/// Writes one line per URL saying where it was saved or the underlying
+/// reason it failed, after a count of the successes.
+fn write_collect_summary(
+    urls: &[String],
+    results: &[Result<PathBuf>],
+    out: &mut impl Write,
+) -> std::io::Result<()> {
+    let saved = results.iter().filter(|result| result.is_ok()).count();
+    writeln!(out, "Downloaded {} of {} URL(s):", saved, urls.len())?;
+    for (url, result) in urls.iter().zip(results) {
+        match result {
+            Ok(path) => writeln!(out, "  OK      {} -> {}", url, path.display())?,
+            Err(e) => writeln!(out, "  FAILED  {}: {}", url, e.root_cause())?,
+        }
+    }
+    Ok(())
+}
+
+/// `collect --wait` when neither the flag nor a profile sets it
+const DEFAULT_WAIT_SECONDS: u64 = 1;
+
+/// `collect --connect-retries` when neither the flag nor a profile sets it
+const DEFAULT_CONNECT_RETRIES: u32 = 2;
+
 async fn handle_collect_command(cli: &Cli, cmd: &Commands) -> Result<()> {
     let Commands::Collect {
-        url,
+        urls,
+        url_file,
+        fail_fast,
+        concurrency,
+        max_concurrent_circuits,
         output,
         output_alt,
+        output_dir,
+        profile,
+        profiles,
         user_agent,
         wait,
         max_redirects,
         insecure,
+        exit_country,
+        request_id,
+        new_circuit_per_request,
         buffer_size,
+        adaptive_buffer,
+        warc,
+        fail_on_soft_block,
+        max_size,
+        timeout,
+        connect_retries,
         default_filename,
         method,
+        basic_auth,
+        bearer,
         headers,
+        headers_file,
         data,
         data_file,
+        compress_body,
+        accept_encoding,
+        print_curl,
+        spider,
+        max_depth,
+        same_host,
     } = cmd
     else {
         unreachable!("handle_collect_command called with non-Collect command");
@@ -136,62 +806,220 @@ async fn handle_collect_command(cli: &Cli, cmd: &Commands) -> Result<()> {
 
     // Create downloader
     let mut downloader = TorDownloader::new().await?;
-    downloader.set_rate_limit_delay(*wait);
+    downloader.set_rate_limit_delay(DEFAULT_WAIT_SECONDS);
+    downloader.set_connect_retries(DEFAULT_CONNECT_RETRIES);
+    if let Some(name) = profile {
+        Profile::from_yaml_file(profiles, name)?.apply(&mut downloader)?;
+        info!("Using download profile '{}'", name);
+    }
+    if let Some(seconds) = wait {
+        downloader.set_rate_limit_delay(*seconds);
+    }
+    if let Some(retries) = connect_retries {
+        downloader.set_connect_retries(*retries);
+    }
+    downloader.set_max_concurrent_circuits(*max_concurrent_circuits);
     downloader.set_max_redirects(*max_redirects);
     downloader.set_insecure(*insecure);
     downloader.set_buffer_size(*buffer_size);
+    downloader.set_adaptive_buffer(*adaptive_buffer);
+    downloader.set_max_download_size(*max_size);
+    downloader.set_fail_on_soft_block(*fail_on_soft_block);
+    downloader.set_timeout(Duration::from_secs(*timeout));
+    if let Some(path) = warc {
+        downloader.set_warc_output(path);
+        downloader.set_warc_requests(true);
+    }
+    downloader.set_request_id_header(*request_id);
+    downloader.set_compress_body(*compress_body);
+    downloader.set_request_compression(*accept_encoding == AcceptEncoding::Auto);
+    if *new_circuit_per_request {
+        downloader.set_isolation_mode(IsolationMode::PerRequest);
+    }
     downloader.set_default_filename(default_filename);
+    if let Some(dir) = output_dir {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+        downloader.set_output_dir(dir);
+    }
+    if let Some(code) = exit_country {
+        downloader.set_exit_country(code)?;
+    }
+
+    if let Some(credentials) = basic_auth {
+        let (user, password) = parse_basic_auth(credentials)?;
+        downloader.set_basic_auth(user, password);
+    }
+    if let Some(token) = bearer {
+        downloader.set_bearer_token(token);
+    }
 
     // Set custom user agent if provided
     if let Some(user_agent) = user_agent {
         downloader.set_user_agent(user_agent);
     }
 
-    // Check if this is a web service request (POST or has data)
-    let is_web_service = method
-        .as_ref()
-        .map_or(false, |m| m.to_uppercase() == "POST")
-        || data.is_some()
-        || data_file.is_some()
-        || !headers.is_empty();
+    let headers = request_headers(headers, headers_file.as_deref())?;
+
+    // stdin holds either the URLs or the request body, not both
+    if urls.iter().any(|url| url == "-") && body_from_stdin(data.as_deref(), data_file.as_deref()) {
+        anyhow::bail!("stdin can't supply both the URLs (`-`) and the request body");
+    }
+    let mut urls = expand_stdin_urls(urls, &mut std::io::stdin().lock())?;
+    if let Some(path) = url_file {
+        urls.extend(read_url_file(path)?);
+    }
+    let urls = distinct_urls(urls);
+    let output = output.as_ref().or(output_alt.as_ref());
+    if *concurrency == 0 {
+        anyhow::bail!("--concurrency must be at least 1");
+    }
+    match urls.len() {
+        0 => anyhow::bail!("No URLs to download"),
+        1 => {}
+        _ if output.is_some() => {
+            anyhow::bail!("--output names a single file; it can't be used with several URLs")
+        }
+        _ if *spider => anyhow::bail!("--spider crawls from a single URL"),
+        _ => {}
+    }
+
+    if *spider {
+        let mut spider = Spider::new(*max_depth);
+        spider.set_same_host(*same_host);
+        let root = output.or(output_dir.as_ref());
+        return crawl_site(cli, &downloader, &spider, &urls[0], root).await;
+    }
+
+    // Read a piped body once, to send with every URL
+    let body_data = read_request_body(
+        data.as_deref(),
+        data_file.as_deref(),
+        &mut std::io::stdin().lock(),
+    )?;
+    let request = CollectRequest {
+        method: *method,
+        headers: &headers,
+        body: body_data.as_deref(),
+        upload: upload_file(data_file.as_deref()),
+    };
+
+    // One Tor client for every URL, so it bootstraps once. Concurrent
+    // downloads each get their own circuit, and the rate limit still spaces
+    // requests to the same host.
+    let mode = if *fail_fast {
+        BatchMode::FailFast
+    } else {
+        BatchMode::CollectAll
+    };
+    let (downloader, request) = (&downloader, &request);
+    let mut results = run_batch_concurrent(&urls, *concurrency, mode, None, |url| async move {
+        let worker = if *concurrency > 1 {
+            downloader.isolated()
+        } else {
+            downloader.clone()
+        };
+        collect_url(&worker, url, request, *print_curl, output)
+            .await
+            .with_context(|| format!("Failed to download {}", url))
+    })
+    .await?;
+
+    if results.len() == 1 {
+        let final_path = results.remove(0)?;
+        if !cli.quiet {
+            println!();
+            println!("Download complete: {}", final_path.display());
+        }
+        return Ok(());
+    }
+
+    if !cli.quiet {
+        println!();
+        write_collect_summary(&urls, &results, &mut std::io::stdout())?;
+    }
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} download(s) failed", failed, urls.len());
+    }
+    Ok(())
+}
+
+/// The request `collect` sends to each URL
+struct CollectRequest<'a> {
+    method: HttpMethod,
+    headers: &'a [String],
+    /// In-memory body from `--data` or stdin
+    body: Option<&'a [u8]>,
+    /// `--data-file` to stream as the body
+    upload: Option<&'a Path>,
+}
+
+impl CollectRequest<'_> {
+    /// Whether this is a web service request (non-GET or has data) rather
+    /// than a plain download
+    fn is_web_service(&self) -> bool {
+        self.method != HttpMethod::Get
+            || self.body.is_some()
+            || self.upload.is_some()
+            || !self.headers.is_empty()
+    }
+}
 
-    // Download the file
+/// Downloads `url` with `request` and returns the path it was saved to:
+/// `output` if given, or else the server-provided name under the
+/// downloader's output directory.
+async fn collect_url(
+    downloader: &TorDownloader,
+    url: &str,
+    request: &CollectRequest<'_>,
+    print_curl: bool,
+    output: Option<&PathBuf>,
+) -> Result<PathBuf> {
     info!("Downloading: {}", url);
 
-    let filename = if is_web_service {
+    let CollectRequest {
+        method,
+        headers,
+        body: body_data,
+        upload,
+    } = *request;
+    let filename = if request.is_web_service() {
         // Web service mode - use the new download_web_service method
         info!("Using web service mode");
 
-        // Read data from file if specified
-        let body_data = if let Some(file) = data_file {
-            Some(std::fs::read_to_string(file).context("Failed to read data file")?)
-        } else {
-            data.clone()
-        };
+        if print_curl {
+            let curl = match upload {
+                Some(file) => downloader.to_curl_file(url, method, headers, file)?,
+                None => downloader.to_curl(url, method, headers, body_data)?,
+            };
+            eprintln!("{}", curl);
+        }
 
-        let method_str = method.as_ref().map(|s| s.as_str()).unwrap_or("GET");
-        let (response_body, suggested_filename) = downloader
-            .download_web_service(url, method_str, headers, body_data.as_deref())
-            .await?;
+        let (response_body, suggested_filename) = match upload {
+            Some(file) => downloader.request_file(url, method, headers, file).await?,
+            None => downloader.request(url, method, headers, body_data).await?,
+        };
 
         // For web service responses, save directly as the response body
         let output_filename = output
-            .as_ref()
-            .or(output_alt.as_ref())
-            .map(|p| p.to_string_lossy().to_string())
-            .unwrap_or(suggested_filename);
+            .cloned()
+            .unwrap_or_else(|| downloader.output_path(&suggested_filename));
 
         std::fs::write(&output_filename, &response_body)
             .context("Failed to write response to file")?;
 
         output_filename
     } else {
-        downloader.download_file(url).await?
+        if print_curl {
+            eprintln!("{}", downloader.download_to_curl(url)?);
+        }
+        PathBuf::from(downloader.download_file(url).await?)
     };
 
     // Handle output filename
-    let final_path = match output.as_ref().or(output_alt.as_ref()) {
-        Some(output) if output != &PathBuf::from(&filename) => {
+    let final_path = match output {
+        Some(output) if *output != filename => {
             // User specified output file that differs from downloaded name
             std::fs::rename(&filename, output)?;
             info!("Saved as: {}", output.display());
@@ -199,92 +1027,870 @@ async fn handle_collect_command(cli: &Cli, cmd: &Commands) -> Result<()> {
         }
         Some(_) => {
             // Output matches the downloaded filename
-            PathBuf::from(&filename)
+            filename
         }
         None => {
             // Use server-provided name
-            info!("Saved as: {}", &filename);
-            PathBuf::from(&filename)
+            info!("Saved as: {}", filename.display());
+            filename
         }
     };
+    Ok(final_path)
+}
 
+/// Crawls from `url` with `spider`, mirroring pages under `root` (default:
+/// the current directory), and reports the pages saved and failed.
+///
+/// # Errors
+///
+/// Returns an error if the crawl can't start, or if no page was saved.
+async fn crawl_site(
+    cli: &Cli,
+    downloader: &TorDownloader,
+    spider: &Spider,
+    url: &str,
+    root: Option<&PathBuf>,
+) -> Result<()> {
+    let root = root.map_or(Path::new("."), PathBuf::as_path);
+    let results = spider
+        .crawl(
+            url,
+            root,
+            |page| async move { downloader.fetch(&page).await },
+        )
+        .await?;
+
+    // Failed pages were already logged by the crawl
+    let saved = results.iter().filter(|result| result.is_ok()).count();
+    if saved == 0 {
+        anyhow::bail!("No pages were saved from {}", url);
+    }
     if !cli.quiet {
         println!();
-        println!("Download complete: {}", final_path.display());
+        println!(
+            "Crawl complete: {} page(s) saved under {}, {} failed",
+            saved,
+            root.display(),
+            results.len() - saved
+        );
     }
-
     Ok(())
 }
 
-async fn handle_enrich_command(cli: &Cli, cmd: &Commands) -> Result<()> {
-    let Commands::Enrich {
-        config_file,
-        input_file,
-        output,
-    } = cmd
-    else {
-        unreachable!("handle_enrich_command called with non-Enrich command");
-    };
-
-    if !cli.quiet {
-        println!("OpenAI-Compatible API Client");
-        println!("===========================");
-        println!();
+/// Returns the message given inline, or else the contents of `file`.
+fn message_text(text: Option<&str>, file: Option<&Path>) -> Result<Option<String>> {
+    match (text, file) {
+        (Some(text), _) => Ok(Some(text.to_string())),
+        (None, Some(path)) => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read message file: {}", path.display()))
+            .map(Some),
+        (None, None) => Ok(None),
     }
+}
+
+/// Builds an `EnrichConfig` from command-line flags: a completion request for
+/// `--prompt`, or a chat request when `--user` (and optionally `--system`) is given.
+fn quick_prompt_config(
+    api_url: &str,
+    model: Option<&str>,
+    prompt: Option<&str>,
+    system: Option<&str>,
+    user: Option<&str>,
+) -> Result<EnrichConfig> {
+    let model = model.context("--model is required without a configuration file")?;
 
-    // Load configuration
-    let mut config = match config_file.extension().and_then(|s| s.to_str()) {
-        Some("yaml") | Some("yml") => EnrichConfig::from_yaml_file(config_file)?,
-        Some("json") => EnrichConfig::from_json_file(config_file)?,
-        _ => anyhow::bail!("Configuration file must have .yaml, .yml, or .json extension"),
+    let prompt = match (prompt, user) {
+        (Some(prompt), _) => PromptConfig::Completion {
+            prompt: prompt.to_string(),
+        },
+        (None, Some(user)) => {
+            let mut messages = Vec::new();
+            if let Some(system) = system {
+                messages.push(ChatMessage {
+                    role: "system".to_string(),
+                    content: system.to_string(),
+                    name: None,
+                });
+            }
+            messages.push(ChatMessage {
+                role: "user".to_string(),
+                content: user.to_string(),
+                name: None,
+            });
+            PromptConfig::Chat { messages }
+        }
+        (None, None) => anyhow::bail!("Either --config, --prompt, or --user is required"),
     };
 
-    info!("Loaded configuration from: {}", config_file.display());
+    Ok(EnrichConfig::new(api_url, model, prompt))
+}
 
-    // If input file is specified, read it and update the prompt
-    if let Some(input_path) = input_file {
-        let content = std::fs::read_to_string(input_path).context("Failed to read input file")?;
+/// Whether `collect` asks for compressed responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AcceptEncoding {
+    /// Advertise gzip, deflate, and br, and decode what arrives
+    Auto,
+    /// Advertise nothing and keep bodies as sent
+    None,
+}
 
-        // Update the prompt in the config to include the file content
-        match &mut config.prompt {
-            decisym_defcon33::PromptConfig::Completion { prompt } => {
-                *prompt = format!("{}\n\nContent:\n{}", prompt, content);
-            }
-            decisym_defcon33::PromptConfig::Chat { messages } => {
-                // Append content to the last user message
-                if let Some(last_msg) = messages.iter_mut().rev().find(|m| m.role == "user") {
-                    last_msg.content = format!("{}\n\nContent:\n{}", last_msg.content, content);
-                } else {
-                    // If no user message, add one
-                    messages.push(decisym_defcon33::ChatMessage {
-                        role: "user".to_string(),
-                        content: format!("Content:\n{}", content),
-                    });
-                }
-            }
+impl std::str::FromStr for AcceptEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "auto" => Ok(AcceptEncoding::Auto),
+            "none" | "identity" => Ok(AcceptEncoding::None),
+            _ => anyhow::bail!("Unknown accept encoding '{}': expected auto or none", s),
         }
     }
+}
 
-    // Create client and send request
-    let client = OpenAIClient::new()?;
+/// How `enrich` writes the response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// The first choice's text
+    Text,
+    /// One JSON object per choice, one per line
+    Jsonl,
+}
 
-    info!("Sending request to: {}", config.api_url);
-    let response = client.enrich(&config).await?;
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
 
-    // Output response
-    if let Some(output_path) = output {
-        std::fs::write(output_path, &response)?;
-        if !cli.quiet {
-            println!("Response saved to: {}", output_path.display());
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            _ => anyhow::bail!("Unknown format '{}': expected text or jsonl", s),
         }
-    } else {
-        println!("{}", response);
     }
+}
 
-    Ok(())
+/// Single-line spinner showing elapsed time (and token count, when known)
+/// while an enrichment request is in flight.
+struct Progress<W: Write> {
+    out: W,
+    started: Instant,
+    frame: usize,
 }
 
-#[tokio::main]
+impl<W: Write> Progress<W> {
+    const FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+
+    fn new(out: W) -> Self {
+        Self {
+            out,
+            started: Instant::now(),
+            frame: 0,
+        }
+    }
+
+    fn tick(&mut self, tokens: Option<usize>) -> std::io::Result<()> {
+        let spinner = Self::FRAMES[self.frame % Self::FRAMES.len()];
+        self.frame += 1;
+        let elapsed = self.started.elapsed().as_secs();
+        match tokens {
+            Some(tokens) => write!(
+                self.out,
+                "\r{} Generating... {} tokens ({}s)",
+                spinner, tokens, elapsed
+            )?,
+            None => write!(
+                self.out,
+                "\r{} Waiting for response ({}s)",
+                spinner, elapsed
+            )?,
+        }
+        self.out.flush()
+    }
+
+    /// Erases the progress line so the final output starts on a clean line.
+    fn finish(&mut self) -> std::io::Result<()> {
+        write!(self.out, "\r\x1b[2K")?;
+        self.out.flush()
+    }
+}
+
+/// Awaits `request` while redrawing a progress line on `out`.
+async fn with_progress<T>(out: &mut impl Write, request: impl Future<Output = T>) -> T {
+    let mut progress = Progress::new(out);
+    let mut ticker = tokio::time::interval(Duration::from_millis(250));
+    tokio::pin!(request);
+
+    // Progress is best-effort, so drawing errors are ignored
+    let result = loop {
+        tokio::select! {
+            result = &mut request => break result,
+            _ = ticker.tick() => {
+                let _ = progress.tick(None);
+            }
+        }
+    };
+    let _ = progress.finish();
+    result
+}
+
+/// Collects a streamed response while redrawing a progress line on `out` that
+/// counts the pieces received so far.
+async fn stream_with_progress(
+    out: &mut impl Write,
+    pieces: impl futures::Stream<Item = Result<String>>,
+) -> Result<String> {
+    let mut progress = Progress::new(out);
+    let mut ticker = tokio::time::interval(Duration::from_millis(250));
+    let mut pieces = std::pin::pin!(pieces);
+    let mut response = String::new();
+    let mut tokens = 0;
+
+    // Each chunk of a chat completion stream carries about one token
+    let result = loop {
+        tokio::select! {
+            piece = pieces.try_next() => match piece {
+                Ok(Some(piece)) => {
+                    response.push_str(&piece);
+                    tokens += 1;
+                    let _ = progress.tick(Some(tokens));
+                }
+                Ok(None) => break Ok(response),
+                Err(e) => break Err(e),
+            },
+            _ = ticker.tick() => {
+                let _ = progress.tick((tokens > 0).then_some(tokens));
+            }
+        }
+    };
+    let _ = progress.finish();
+    result
+}
+
+/// Whether to draw progress on stderr: not in `--quiet` mode, and only when
+/// stderr is a terminal that can redraw the line.
+fn show_progress(cli: &Cli) -> bool {
+    !cli.quiet && std::io::stderr().is_terminal()
+}
+
+/// Parses a size such as `8192`, `500K`, `10M`, or `1G`. Suffixes are binary
+/// multiples, case-insensitive, and may end in `B`.
+fn parse_size<T: TryFrom<u64>>(value: &str) -> Result<T, String> {
+    const UNITS: &[(&str, u64)] = &[
+        ("", 1),
+        ("b", 1),
+        ("k", 1 << 10),
+        ("kb", 1 << 10),
+        ("m", 1 << 20),
+        ("mb", 1 << 20),
+        ("g", 1 << 30),
+        ("gb", 1 << 30),
+        ("t", 1 << 40),
+        ("tb", 1 << 40),
+    ];
+    let bytes = parse_with_unit(value, UNITS)?;
+    T::try_from(bytes).map_err(|_| format!("size is too large: {}", value))
+}
+
+/// Parses a duration in whole seconds such as `30`, `30s`, `5m`, or `2h`; a
+/// bare number is seconds.
+fn parse_seconds(value: &str) -> Result<u64, String> {
+    const UNITS: &[(&str, u64)] = &[
+        ("", 1),
+        ("s", 1),
+        ("m", 60),
+        ("h", 60 * 60),
+        ("d", 24 * 60 * 60),
+    ];
+    parse_with_unit(value, UNITS)
+}
+
+/// Parses a whole number followed by one of `units`, returning the number
+/// times the unit's multiplier.
+fn parse_with_unit(value: &str, units: &[(&str, u64)]) -> Result<u64, String> {
+    let trimmed = value.trim();
+    let (number, unit) = trimmed.split_at(
+        trimmed
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(trimmed.len()),
+    );
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("expected a number, got '{}'", value))?;
+    let multiplier = units
+        .iter()
+        .find(|(suffix, _)| suffix.eq_ignore_ascii_case(unit.trim()))
+        .map(|(_, multiplier)| *multiplier)
+        .ok_or_else(|| {
+            let suffixes: Vec<&str> = units
+                .iter()
+                .map(|(suffix, _)| *suffix)
+                .filter(|suffix| !suffix.is_empty())
+                .collect();
+            format!(
+                "unknown unit '{}' in '{}' (expected {})",
+                unit.trim(),
+                value,
+                suffixes.join(", ")
+            )
+        })?;
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("value is too large: {}", value))
+}
+
+/// Applies `--extract` and `--repair-json` to one response.
+fn postprocess_response(
+    mut response: String,
+    extract: Option<&Regex>,
+    repair_json: bool,
+) -> Result<String> {
+    if let Some(pattern) = extract {
+        response = extract_capture(&response, pattern)?;
+    }
+    if repair_json && serde_json::from_str::<serde_json::Value>(&response).is_err() {
+        let repaired = decisym_defcon33::repair_json(&response);
+        if repaired == response {
+            warn!("Response is not valid JSON and could not be repaired");
+        } else {
+            info!("Repaired truncated JSON response");
+            response = repaired;
+        }
+    }
+    Ok(response)
+}
+
+/// Compiles an `--extract` pattern, requiring at least one capture group.
+fn compile_extract_pattern(pattern: &str) -> Result<Regex> {
+    let regex = Regex::new(pattern).context("Invalid --extract pattern")?;
+    if regex.captures_len() < 2 {
+        anyhow::bail!("--extract pattern must contain a capture group");
+    }
+    Ok(regex)
+}
+
+/// Returns the text captured by the first group of `pattern` in `output`.
+fn extract_capture(output: &str, pattern: &Regex) -> Result<String> {
+    pattern
+        .captures(output)
+        .and_then(|caps| caps.get(1))
+        .map(|m| m.as_str().to_string())
+        .with_context(|| format!("--extract pattern '{}' did not match the response", pattern))
+}
+
+/// Whether `path` is an existing, non-empty file.
+fn output_exists(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.len() > 0)
+}
+
+/// Describes an enrich failure as a `{error_kind, message, status?}` JSON
+/// object, along with the process exit code for its kind.
+fn error_report(err: &anyhow::Error) -> (serde_json::Value, i32) {
+    let classified = err.chain().find_map(|e| e.downcast_ref::<OpenAIError>());
+    let kind = classified.map_or("other", OpenAIError::kind);
+    let code = match kind {
+        "config" => 2,
+        "server" => 3,
+        "timeout" => 4,
+        "connection" => 5,
+        "invalid_response" => 6,
+        _ => 1,
+    };
+
+    let mut report = serde_json::json!({
+        "error_kind": kind,
+        "message": format!("{:#}", err),
+    });
+    if let Some(status) = classified.and_then(OpenAIError::status) {
+        report["status"] = serde_json::json!(status);
+    }
+    (report, code)
+}
+
+async fn handle_enrich_command(cli: &Cli, cmd: &Commands) -> Result<()> {
+    let Commands::Enrich {
+        config_file,
+        prompt,
+        system,
+        system_file,
+        user,
+        user_file,
+        model,
+        api_url,
+        input_files,
+        output,
+        output_suffix,
+        context_file,
+        context_query,
+        context_top_k,
+        preset,
+        request_id,
+        tor,
+        retries,
+        total_timeout,
+        extract,
+        skip_existing,
+        ..
+    } = cmd
+    else {
+        unreachable!("handle_enrich_command called with non-Enrich command");
+    };
+
+    if !cli.quiet {
+        println!("OpenAI-Compatible API Client");
+        println!("===========================");
+        println!();
+    }
+
+    // Compile the extraction pattern before spending time on the request
+    let extract = extract
+        .as_deref()
+        .map(compile_extract_pattern)
+        .transpose()?;
+
+    // Make resumable runs cheap by not re-enriching finished outputs
+    let mut jobs = enrich_jobs(input_files, output.as_deref(), output_suffix.as_deref())?;
+    jobs.retain(|job| {
+        let Some(output_path) = job
+            .output
+            .as_deref()
+            .filter(|p| *skip_existing && output_exists(p))
+        else {
+            return true;
+        };
+        info!(
+            "Skipping enrichment, output already exists: {}",
+            output_path.display()
+        );
+        if !cli.quiet {
+            println!("Skipped (output exists): {}", output_path.display());
+        }
+        false
+    });
+    if jobs.is_empty() {
+        return Ok(());
+    }
+
+    // Load configuration, or build one from the quick-prompt flags
+    let mut config = match config_file {
+        Some(config_file) => {
+            let config = EnrichConfig::from_file(config_file)?;
+            info!("Loaded configuration from: {}", config_file.display());
+            config
+        }
+        None => {
+            let system = message_text(system.as_deref(), system_file.as_deref())?;
+            let user = message_text(user.as_deref(), user_file.as_deref())?;
+            quick_prompt_config(
+                api_url,
+                model.as_deref(),
+                prompt.as_deref(),
+                system.as_deref(),
+                user.as_deref(),
+            )?
+        }
+    };
+
+    if let Some(preset) = preset {
+        config.parameters.apply_preset(*preset);
+        info!("Applied {:?} sampling preset", preset);
+    }
+
+    // Ground the prompt with the snippets most relevant to the query
+    if let (Some(context_path), Some(query)) = (context_file, context_query) {
+        let text = std::fs::read_to_string(context_path).context("Failed to read context file")?;
+        let snippets = decisym_defcon33::select_context(&text, query, *context_top_k);
+        if snippets.is_empty() {
+            warn!("No context snippets matched query: {}", query);
+        }
+        info!(
+            "Selected {} context snippet(s) from {}",
+            snippets.len(),
+            context_path.display()
+        );
+        if !config.prompt.inject_context(&snippets.join("\n\n")) {
+            return Err(OpenAIError::Config(format!(
+                "--context-file was given but the prompt has no {} placeholder",
+                decisym_defcon33::openai_client::CONTEXT_PLACEHOLDER
+            ))
+            .into());
+        }
+    }
+
+    // Create client, then send one request per input
+    let mut client = if *tor {
+        info!("Routing the request through Tor");
+        OpenAIClient::with_tor(&TorDownloader::new().await?)?
+    } else {
+        OpenAIClient::new()?
+    };
+    client.set_request_id_header(*request_id);
+    if let Some(retries) = retries {
+        config.max_retries = Some(*retries);
+    }
+    if let Some(total) = total_timeout {
+        client.set_total_timeout(Duration::from_secs(*total));
+    }
+
+    for job in &jobs {
+        let mut config = config.clone();
+        // If an input file is specified, read it and update the prompt
+        if let Some(input_path) = &job.input {
+            let content = std::fs::read_to_string(input_path)
+                .with_context(|| format!("Failed to read input file {}", input_path.display()))?;
+            config.prompt.append_content(&content);
+        }
+        enrich_job(
+            cli,
+            cmd,
+            &client,
+            &config,
+            job.output.as_deref(),
+            extract.as_ref(),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// One enrich request: the input appended to the prompt, if any, and the
+/// file the response goes to, if not stdout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct EnrichJob {
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+}
+
+/// Pairs each enrich input with the file its response goes to: `output`,
+/// or the input path with `suffix` appended. Without inputs there is one
+/// job that sends the prompt as configured.
+///
+/// # Errors
+///
+/// Returns an error if several inputs would share one destination.
+fn enrich_jobs(
+    inputs: &[PathBuf],
+    output: Option<&Path>,
+    suffix: Option<&str>,
+) -> Result<Vec<EnrichJob>> {
+    if inputs.len() > 1 && suffix.is_none() {
+        anyhow::bail!(
+            "{} --input files were given; add --output-suffix to write each response next to its input",
+            inputs.len()
+        );
+    }
+    if inputs.is_empty() {
+        return Ok(vec![EnrichJob {
+            input: None,
+            output: output.map(Path::to_path_buf),
+        }]);
+    }
+    Ok(inputs
+        .iter()
+        .map(|input| EnrichJob {
+            input: Some(input.clone()),
+            output: suffix
+                .map(|suffix| suffixed_path(input, suffix))
+                .or_else(|| output.map(Path::to_path_buf)),
+        })
+        .collect())
+}
+
+/// `path` with `suffix` appended to its file name, e.g. `page.html` and
+/// `.enriched.json` give `page.html.enriched.json`.
+fn suffixed_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Sends one enrich request with the options of `cmd`, writing the response
+/// to `output` or stdout.
+async fn enrich_job(
+    cli: &Cli,
+    cmd: &Commands,
+    client: &OpenAIClient,
+    config: &EnrichConfig,
+    output: Option<&Path>,
+    extract: Option<&Regex>,
+) -> Result<()> {
+    let Commands::Enrich {
+        format,
+        print_curl,
+        stream,
+        repair_json,
+        ..
+    } = cmd
+    else {
+        unreachable!("enrich_job called with non-Enrich command");
+    };
+
+    if *print_curl {
+        eprintln!("{}", client.to_curl(config));
+    }
+
+    if *stream {
+        let PromptConfig::Chat { messages } = &config.prompt else {
+            return Err(OpenAIError::Config("--stream requires a chat prompt".to_string()).into());
+        };
+        info!("Streaming response from: {}", config.api_url);
+        let pieces = client.chat_complete_stream(config, messages);
+        let mut stdout = std::io::stdout();
+        let response = if show_progress(cli) {
+            // The progress line owns the terminal until the response is complete
+            let response = stream_with_progress(&mut std::io::stderr(), pieces).await?;
+            write!(stdout, "{}", response)?;
+            response
+        } else {
+            let mut pieces = std::pin::pin!(pieces);
+            let mut response = String::new();
+            while let Some(piece) = pieces.try_next().await? {
+                write!(stdout, "{}", piece)?;
+                stdout.flush()?;
+                response.push_str(&piece);
+            }
+            response
+        };
+        writeln!(stdout)?;
+
+        if let Some(output_path) = output {
+            std::fs::write(output_path, &response)?;
+            if !cli.quiet {
+                println!("Response saved to: {}", output_path.display());
+            }
+        }
+        return Ok(());
+    }
+
+    info!("Sending request to: {}", config.api_url);
+    let response = match format {
+        OutputFormat::Text => {
+            let result = if show_progress(cli) {
+                with_progress(&mut std::io::stderr(), client.enrich_detailed(config)).await?
+            } else {
+                client.enrich_detailed(config).await?
+            };
+            if let Some(total) = result.total_tokens {
+                info!(
+                    "Token usage: {} prompt, {} completion, {} total",
+                    result.prompt_tokens.unwrap_or_default(),
+                    result.completion_tokens.unwrap_or_default(),
+                    total
+                );
+            }
+            if result.is_truncated() {
+                eprintln!(
+                    "Warning: the response stopped at the {} token limit and is probably cut off",
+                    config.parameters.max_tokens
+                );
+            }
+            postprocess_response(result.content, extract, *repair_json)?
+        }
+        OutputFormat::Jsonl => {
+            let choices = if show_progress(cli) {
+                with_progress(&mut std::io::stderr(), client.enrich_all(config)).await?
+            } else {
+                client.enrich_all(config).await?
+            };
+            info!("Received {} choice(s)", choices.len());
+            let mut lines = String::new();
+            for (index, choice) in choices.into_iter().enumerate() {
+                let content = postprocess_response(choice, extract, *repair_json)?;
+                let line = serde_json::json!({"index": index, "content": content});
+                lines.push_str(&line.to_string());
+                lines.push('\n');
+            }
+            lines
+        }
+    };
+
+    // Output response
+    if let Some(output_path) = output {
+        std::fs::write(output_path, &response)?;
+        if !cli.quiet {
+            println!("Response saved to: {}", output_path.display());
+        }
+    } else if *format == OutputFormat::Jsonl {
+        print!("{}", response);
+    } else {
+        println!("{}", response);
+    }
+
+    Ok(())
+}
+
+/// System message for `speakers` extraction
+const SPEAKERS_SYSTEM_PROMPT: &str = "You are an expert at extracting structured information \
+    from HTML content. You extract speakers from conference websites. Output only valid JSON \
+    without any explanation or additional text.";
+
+/// User message for `speakers` extraction; the page follows it
+const SPEAKERS_USER_PROMPT: &str = "Extract all speakers from the conference content below, \
+    with their company affiliation and job title when listed. Output a JSON object of the form \
+    {\"speakers\": [{\"name\": \"...\", \"affiliation\": \"...\", \"title\": \"...\"}]}, \
+    using null for anything not listed.";
+
+/// Builds the deterministic chat request that extracts speakers from `html`.
+fn speakers_config(api_url: &str, model: &str, timeout: u64, html: &str) -> EnrichConfig {
+    let prompt = PromptConfig::Chat {
+        messages: vec![
+            ChatMessage {
+                role: "system".to_string(),
+                content: SPEAKERS_SYSTEM_PROMPT.to_string(),
+                name: None,
+            },
+            ChatMessage {
+                role: "user".to_string(),
+                content: format!("{}\n\nContent:\n{}", SPEAKERS_USER_PROMPT, html),
+                name: None,
+            },
+        ],
+    };
+    let mut config = EnrichConfig::new(api_url, model, prompt);
+    config.parameters.max_tokens = 4096;
+    config.parameters.apply_preset(Preset::Deterministic);
+    config.timeout_seconds = timeout;
+    config
+}
+
+async fn handle_speakers_command(cli: &Cli, cmd: &Commands) -> Result<()> {
+    let Commands::Speakers {
+        input,
+        model,
+        api_url,
+        timeout,
+        output,
+        json,
+    } = cmd
+    else {
+        unreachable!("handle_speakers_command called with non-Speakers command");
+    };
+
+    let html = if input.starts_with("http://") || input.starts_with("https://") {
+        let downloader = TorDownloader::new().await?;
+        let (body, _) = downloader.fetch(input).await?;
+        String::from_utf8_lossy(&body).into_owned()
+    } else {
+        std::fs::read_to_string(input)
+            .with_context(|| format!("Failed to read input file {}", input))?
+    };
+
+    let config = speakers_config(api_url, model, *timeout, &html);
+    info!("Sending request to: {}", config.api_url);
+    let client = OpenAIClient::new()?;
+    let response = if show_progress(cli) {
+        with_progress(&mut std::io::stderr(), client.enrich(&config)).await?
+    } else {
+        client.enrich(&config).await?
+    };
+
+    let speakers = parse_speakers(&response)?;
+    if speakers.is_empty() {
+        warn!("No speakers found in {}", input);
+    }
+    info!("Extracted {} speaker(s)", speakers.len());
+
+    if let Some(json_path) = json {
+        let content = serde_json::to_string_pretty(&speakers)?;
+        std::fs::write(json_path, content)
+            .with_context(|| format!("Failed to write {}", json_path.display()))?;
+    }
+
+    let rdf = speakers_to_foaf(&speakers)?;
+    if let Some(output_path) = output {
+        std::fs::write(output_path, &rdf)
+            .with_context(|| format!("Failed to write {}", output_path.display()))?;
+        if !cli.quiet {
+            println!("FOAF saved to: {}", output_path.display());
+        }
+    } else {
+        print!("{}", rdf);
+    }
+
+    Ok(())
+}
+
+async fn handle_run_pipeline_command(cli: &Cli, file: &Path) -> Result<()> {
+    let pipeline = Pipeline::from_yaml_file(file)?;
+    let client = OpenAIClient::new()?;
+
+    // Tor is only started if a step downloads something
+    let tor = tokio::sync::OnceCell::new();
+    let outputs = pipeline
+        .run(&client, |url| {
+            let tor = &tor;
+            async move {
+                let downloader = tor.get_or_try_init(TorDownloader::new).await?;
+                downloader.fetch(&url).await
+            }
+        })
+        .await?;
+
+    if !cli.quiet {
+        for (step, output) in pipeline.steps.iter().zip(&outputs) {
+            println!("{}: {}", step.name(), output.display());
+        }
+    }
+    Ok(())
+}
+
+async fn handle_wikidata_command(cli: &Cli, cmd: &Commands) -> Result<()> {
+    let Commands::Wikidata {
+        data_dir,
+        types,
+        industries,
+        endpoint,
+        count_only,
+        pretty,
+    } = cmd
+    else {
+        unreachable!("handle_wikidata_command called with non-Wikidata command");
+    };
+
+    let mut downloader = WikidataDownloader::new(data_dir.clone()).await?;
+    downloader.set_endpoint(endpoint);
+    downloader.set_pretty(*pretty);
+    if !types.is_empty() {
+        downloader.set_entity_types(&types.iter().map(String::as_str).collect::<Vec<_>>())?;
+    }
+    if !industries.is_empty() {
+        downloader.set_industries(&industries.iter().map(String::as_str).collect::<Vec<_>>())?;
+    }
+
+    if *count_only {
+        println!("{}", downloader.get_company_count().await?);
+        return Ok(());
+    }
+
+    let rdf_path = downloader.download_and_convert().await?;
+    if !cli.quiet {
+        println!("RDF saved to: {}", rdf_path.display());
+    }
+    Ok(())
+}
+
+/// Parses the RDF in `path` and writes its statistics to `out`.
+fn rdf_stats(path: &Path, out: &mut impl Write) -> Result<()> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let graph =
+        parse_turtle(&content).with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    let mut subjects = HashSet::new();
+    let mut predicates = HashSet::new();
+    for triple in graph.triples() {
+        subjects.insert(triple.subject);
+        predicates.insert(triple.predicate);
+    }
+    writeln!(out, "Triples: {}", graph.len())?;
+    writeln!(out, "Subjects: {}", subjects.len())?;
+    writeln!(out, "Predicates: {}", predicates.len())?;
+    writeln!(out, "Prefixes:")?;
+    for (prefix, namespace) in graph.prefixes() {
+        writeln!(out, "  {}: <{}>", prefix, namespace)?;
+    }
+    Ok(())
+}
+
+#[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
@@ -306,10 +1912,995 @@ async fn main() -> Result<()> {
         Commands::Collect { .. } => {
             handle_collect_command(&cli, &cli.command).await?;
         }
-        Commands::Enrich { .. } => {
-            handle_enrich_command(&cli, &cli.command).await?;
+        Commands::Enrich { error_json, .. } => {
+            if let Err(err) = handle_enrich_command(&cli, &cli.command).await {
+                if !*error_json {
+                    return Err(err);
+                }
+                let (report, code) = error_report(&err);
+                eprintln!("{}", report);
+                std::process::exit(code);
+            }
+        }
+        Commands::Speakers { .. } => {
+            handle_speakers_command(&cli, &cli.command).await?;
+        }
+        Commands::Models { api_url } => {
+            for model in OpenAIClient::new()?.list_models(api_url).await? {
+                println!("{}", model);
+            }
+        }
+        Commands::Wikidata { .. } => {
+            handle_wikidata_command(&cli, &cli.command).await?;
+        }
+        Commands::RunPipeline { file } => {
+            handle_run_pipeline_command(&cli, file).await?;
+        }
+        Commands::RdfStats { file } => {
+            rdf_stats(file, &mut std::io::stdout())?;
+        }
+        Commands::SplitRdf {
+            file,
+            max_triples,
+            output,
+        } => {
+            let prefix = output.clone().unwrap_or_else(|| file.with_extension(""));
+            let shards = split_rdf(file, *max_triples, &prefix)?;
+            info!("Wrote {} shard(s)", shards.len());
+            for shard in shards {
+                println!("{}", shard.display());
+            }
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_headers_file_merges_with_header_flags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("headers.txt");
+        std::fs::write(
+            &path,
+            "# copied from dev tools\n\
+             Accept: application/json\n\
+             \n\
+             Accept-Language: en-US\n\
+             X-Requested-With: XMLHttpRequest\n",
+        )
+        .unwrap();
+
+        let cli = Cli::parse_from([
+            "decisym_defcon33",
+            "collect",
+            "https://example.com/api",
+            "-H",
+            "Authorization: Bearer abc",
+            "--headers-file",
+            path.to_str().unwrap(),
+        ]);
+        let Commands::Collect {
+            headers,
+            headers_file,
+            ..
+        } = cli.command
+        else {
+            panic!("Expected collect command");
+        };
+
+        assert_eq!(
+            request_headers(&headers, headers_file.as_deref()).unwrap(),
+            vec![
+                "Authorization: Bearer abc",
+                "Accept: application/json",
+                "Accept-Language: en-US",
+                "X-Requested-With: XMLHttpRequest",
+            ]
+        );
+
+        std::fs::write(&path, "Accept application/json\n").unwrap();
+        assert!(request_headers(&headers, Some(&path)).is_err());
+    }
+
+    fn enrich_config_from_args(args: &[&str]) -> EnrichConfig {
+        let cli = Cli::parse_from(args);
+        let Commands::Enrich {
+            prompt,
+            system,
+            system_file,
+            user,
+            user_file,
+            model,
+            api_url,
+            ..
+        } = cli.command
+        else {
+            panic!("Expected enrich command");
+        };
+        let system = message_text(system.as_deref(), system_file.as_deref()).unwrap();
+        let user = message_text(user.as_deref(), user_file.as_deref()).unwrap();
+        quick_prompt_config(
+            &api_url,
+            model.as_deref(),
+            prompt.as_deref(),
+            system.as_deref(),
+            user.as_deref(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_enrich_quick_completion_prompt() {
+        let config = enrich_config_from_args(&[
+            "decisym_defcon33",
+            "enrich",
+            "--prompt",
+            "Summarize this",
+            "--model",
+            "test-model",
+        ]);
+
+        assert_eq!(config.api_url, "http://localhost:8000/v1");
+        assert_eq!(config.model, "test-model");
+        assert!(
+            matches!(config.prompt, PromptConfig::Completion { ref prompt } if prompt == "Summarize this")
+        );
+    }
+
+    #[test]
+    fn test_enrich_quick_chat_prompt() {
+        let config = enrich_config_from_args(&[
+            "decisym_defcon33",
+            "enrich",
+            "--system",
+            "You are terse",
+            "--user",
+            "Extract names",
+            "--model",
+            "test-model",
+            "--api-url",
+            "http://llm.internal:9000/v1",
+        ]);
+
+        assert_eq!(config.api_url, "http://llm.internal:9000/v1");
+        let PromptConfig::Chat { messages } = &config.prompt else {
+            panic!("Expected chat prompt config");
+        };
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, "system");
+        assert_eq!(messages[0].content, "You are terse");
+        assert_eq!(messages[1].role, "user");
+        assert_eq!(messages[1].content, "Extract names");
+    }
+
+    #[test]
+    fn test_enrich_chat_prompt_from_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let system_path = dir.path().join("system.txt");
+        let user_path = dir.path().join("user.txt");
+        std::fs::write(&system_path, "You extract speaker names.\nReply in JSON.\n").unwrap();
+        std::fs::write(&user_path, "List the speakers.").unwrap();
+
+        let config = enrich_config_from_args(&[
+            "decisym_defcon33",
+            "enrich",
+            "--system-file",
+            system_path.to_str().unwrap(),
+            "--user-file",
+            user_path.to_str().unwrap(),
+            "--model",
+            "test-model",
+        ]);
+
+        let PromptConfig::Chat { messages } = &config.prompt else {
+            panic!("Expected chat prompt config");
+        };
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, "system");
+        assert_eq!(
+            messages[0].content,
+            "You extract speaker names.\nReply in JSON.\n"
+        );
+        assert_eq!(messages[1].role, "user");
+        assert_eq!(messages[1].content, "List the speakers.");
+
+        assert!(
+            Cli::try_parse_from([
+                "decisym_defcon33",
+                "enrich",
+                "--user",
+                "hi",
+                "--user-file",
+                "user.txt",
+                "--model",
+                "m",
+            ])
+            .is_err(),
+            "--user and --user-file are mutually exclusive"
+        );
+    }
+
+    #[test]
+    fn test_enrich_preset_flag() {
+        let cli = Cli::parse_from([
+            "decisym_defcon33",
+            "enrich",
+            "-c",
+            "examples/chat.yaml",
+            "--preset",
+            "deterministic",
+        ]);
+        let Commands::Enrich { preset, .. } = cli.command else {
+            panic!("Expected enrich command");
+        };
+        assert_eq!(preset, Some(Preset::Deterministic));
+
+        assert!(
+            Cli::try_parse_from([
+                "decisym_defcon33",
+                "enrich",
+                "-c",
+                "examples/chat.yaml",
+                "--preset",
+                "wild"
+            ])
+            .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_enrich_server_error_json() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // One-shot API stub that answers every request with a 500
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            while !String::from_utf8_lossy(&request).contains("\"prompt\"") {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            socket
+                .write_all(
+                    b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 8\r\n\r\nmodel oo",
+                )
+                .await
+                .unwrap();
+        });
+
+        let cli = Cli::parse_from([
+            "decisym_defcon33",
+            "--quiet",
+            "enrich",
+            "--prompt",
+            "hi",
+            "--model",
+            "test-model",
+            "--api-url",
+            &api_url,
+            "--retries",
+            "0",
+            "--error-json",
+        ]);
+        let err = handle_enrich_command(&cli, &cli.command).await.unwrap_err();
+
+        let (report, code) = error_report(&err);
+        assert_eq!(code, 3);
+        assert_eq!(report["error_kind"], "server");
+        assert_eq!(report["status"], 500);
+        assert!(report["message"].as_str().unwrap().contains("model oo"));
+
+        let (report, code) = error_report(&anyhow::anyhow!("disk full"));
+        assert_eq!(code, 1);
+        assert_eq!(report["error_kind"], "other");
+        assert!(report.get("status").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_enrich_jsonl_writes_every_choice() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Mock LLM answering with three candidate extractions
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            while !String::from_utf8_lossy(&request).contains("Extract the speaker") {
+                let n = socket.read(&mut buf).await.unwrap();
+                assert!(n > 0, "request ended early");
+                request.extend_from_slice(&buf[..n]);
+            }
+            let choices: Vec<_> = ["Jane Doe", "J. Doe", "Doe, Jane"]
+                .iter()
+                .enumerate()
+                .map(|(index, content)| {
+                    serde_json::json!({
+                        "index": index,
+                        "message": {"role": "assistant", "content": content},
+                        "finish_reason": "stop"
+                    })
+                })
+                .collect();
+            let body = serde_json::json!({ "choices": choices }).to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request).into_owned()
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("candidates.yaml");
+        std::fs::write(
+            &config,
+            format!(
+                "api_url: \"{}\"\nmodel: \"test-model\"\nn: 3\nmessages:\n  \
+                 - role: \"user\"\n    content: \"Extract the speaker\"\n",
+                api_url
+            ),
+        )
+        .unwrap();
+        let output = dir.path().join("candidates.jsonl");
+        let cli = Cli::parse_from([
+            "decisym_defcon33",
+            "--quiet",
+            "enrich",
+            "-c",
+            config.to_str().unwrap(),
+            "--format",
+            "jsonl",
+            "-o",
+            output.to_str().unwrap(),
+        ]);
+        handle_enrich_command(&cli, &cli.command).await.unwrap();
+        assert!(server.await.unwrap().contains("\"n\":3"));
+
+        let written = std::fs::read_to_string(&output).unwrap();
+        let lines: Vec<serde_json::Value> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            serde_json::json!({"index": 0, "content": "Jane Doe"})
+        );
+        assert_eq!(lines[2]["content"], "Doe, Jane");
+        assert!(written.ends_with('\n'));
+    }
+
+    #[tokio::test]
+    async fn test_enrich_stream_requires_chat_prompt() {
+        let cli = Cli::parse_from([
+            "decisym_defcon33",
+            "--quiet",
+            "enrich",
+            "--prompt",
+            "hi",
+            "--model",
+            "test-model",
+            "--stream",
+        ]);
+        let err = handle_enrich_command(&cli, &cli.command).await.unwrap_err();
+        assert!(err.to_string().contains("--stream requires a chat prompt"));
+
+        assert!(
+            Cli::try_parse_from([
+                "decisym_defcon33",
+                "enrich",
+                "-c",
+                "examples/chat.yaml",
+                "--stream",
+                "--extract",
+                "(.*)"
+            ])
+            .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_speakers_to_foaf_from_fixture() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let html_path = dir.path().join("speakers.html");
+        std::fs::write(
+            &html_path,
+            "<div class=\"speaker\"><h3>Jane Doe</h3><p>CTO, Acme</p></div>\n\
+             <div class=\"speaker\"><h3>John Roe</h3></div>\n",
+        )
+        .unwrap();
+
+        // Mock LLM that checks the page was sent and answers with a fenced
+        // extraction result
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            while !String::from_utf8_lossy(&request).contains("John Roe") {
+                let n = socket.read(&mut buf).await.unwrap();
+                assert!(n > 0, "request ended before the page content");
+                request.extend_from_slice(&buf[..n]);
+            }
+            let content = "```json\n{\"speakers\": [{\"name\": \"Jane Doe\", \
+                \"affiliation\": \"Acme\", \"title\": \"CTO\"}, {\"name\": \"John Roe\", \
+                \"affiliation\": null, \"title\": null}]}\n```";
+            let body = serde_json::json!({
+                "choices": [{
+                    "message": {"role": "assistant", "content": content},
+                    "finish_reason": "stop"
+                }]
+            })
+            .to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request).into_owned()
+        });
+
+        let output = dir.path().join("speakers.ttl");
+        let json = dir.path().join("speakers.json");
+        let cli = Cli::parse_from([
+            "decisym_defcon33",
+            "--quiet",
+            "speakers",
+            html_path.to_str().unwrap(),
+            "--api-url",
+            &api_url,
+            "--model",
+            "test-model",
+            "-o",
+            output.to_str().unwrap(),
+            "--json",
+            json.to_str().unwrap(),
+        ]);
+        handle_speakers_command(&cli, &cli.command).await.unwrap();
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /v1/chat/completions "));
+        assert!(request.contains("\"model\":\"test-model\""));
+
+        let rdf = std::fs::read_to_string(&output).unwrap();
+        assert!(rdf.contains("_:speaker1 a foaf:Person ;\n\tfoaf:name \"Jane Doe\" ;"));
+        assert!(rdf.contains("org:role \"CTO\""));
+        assert!(rdf.contains("_:org1 a foaf:Organization ;\n\tfoaf:name \"Acme\" .\n"));
+        assert!(rdf.contains("_:speaker2 a foaf:Person ;\n\tfoaf:name \"John Roe\" .\n"));
+
+        let saved: Vec<decisym_defcon33::Speaker> =
+            serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved[0].affiliation.as_deref(), Some("Acme"));
+    }
+
+    #[test]
+    fn test_enrich_requires_config_or_prompt() {
+        assert!(Cli::try_parse_from(["decisym_defcon33", "enrich"]).is_err());
+        assert!(
+            Cli::try_parse_from(["decisym_defcon33", "enrich", "--prompt", "hi"]).is_err(),
+            "--prompt without --model should be rejected"
+        );
+    }
+
+    #[test]
+    fn test_long_version_reports_tls_backend() {
+        let version = Cli::command().render_long_version();
+        assert!(version.contains(env!("CARGO_PKG_VERSION")));
+        assert!(version.contains("arti-client: 0.22"));
+        assert!(version.contains("TLS backend: native-tls"));
+        assert!(version.contains("Onion service support:"));
+    }
+
+    #[test]
+    fn test_request_body_from_stdin() {
+        let cli = Cli::parse_from([
+            "decisym_defcon33",
+            "collect",
+            "https://query.wikidata.org/sparql",
+            "-X",
+            "POST",
+            "-d",
+            "@-",
+        ]);
+        let Commands::Collect {
+            data, data_file, ..
+        } = cli.command
+        else {
+            panic!("Expected collect command");
+        };
+
+        let mut stdin = std::io::Cursor::new("query=SELECT * WHERE { ?s ?p ?o }");
+        let body = read_request_body(data.as_deref(), data_file.as_deref(), &mut stdin).unwrap();
+        assert_eq!(
+            body.as_deref(),
+            Some(&b"query=SELECT * WHERE { ?s ?p ?o }"[..])
+        );
+
+        let mut stdin = std::io::Cursor::new("{\"piped\": true}");
+        let body = read_request_body(None, Some(Path::new("-")), &mut stdin).unwrap();
+        assert_eq!(body.as_deref(), Some(&b"{\"piped\": true}"[..]));
+        assert_eq!(upload_file(Some(Path::new("-"))), None);
+
+        let mut stdin = std::io::Cursor::new(vec![0xff, 0xfe, 0x00]);
+        let body = read_request_body(Some("@-"), None, &mut stdin).unwrap();
+        assert_eq!(body.as_deref(), Some(&[0xff, 0xfe, 0x00][..]));
+
+        let mut stdin = std::io::Cursor::new("unused");
+        let body = read_request_body(Some("inline"), None, &mut stdin).unwrap();
+        assert_eq!(body.as_deref(), Some(&b"inline"[..]));
+
+        // A data file is streamed rather than read into memory
+        let file = Path::new("upload.bin");
+        let body = read_request_body(None, Some(file), &mut stdin).unwrap();
+        assert_eq!(body, None);
+        assert_eq!(upload_file(Some(file)), Some(file));
+    }
+
+    #[test]
+    fn test_urls_and_body_from_stdin() {
+        let cli = Cli::parse_from([
+            "decisym_defcon33",
+            "collect",
+            "https://example.com/first",
+            "-",
+            "-d",
+            "-",
+        ]);
+        let Commands::Collect {
+            urls,
+            data,
+            data_file,
+            ..
+        } = cli.command
+        else {
+            panic!("Expected collect command");
+        };
+        assert_eq!(urls, ["https://example.com/first", "-"]);
+        assert!(body_from_stdin(data.as_deref(), data_file.as_deref()));
+        assert!(!body_from_stdin(Some("q=1"), None));
+
+        let mut stdin =
+            std::io::Cursor::new("https://example.com/a\n# skip\n\nhttps://example.com/b\n");
+        assert_eq!(
+            expand_stdin_urls(&urls, &mut stdin).unwrap(),
+            [
+                "https://example.com/first",
+                "https://example.com/a",
+                "https://example.com/b"
+            ]
+        );
+        let plain = vec!["https://example.com/".to_string()];
+        let mut unread = std::io::Cursor::new("not consumed");
+        assert_eq!(expand_stdin_urls(&plain, &mut unread).unwrap(), plain);
+
+        let err = expand_stdin_urls(&urls, &mut std::io::Cursor::new("\n# none\n")).unwrap_err();
+        assert!(err.to_string().starts_with("No URLs on stdin"), "{}", err);
+        let twice = vec!["-".to_string(), "-".to_string()];
+        assert!(expand_stdin_urls(&twice, &mut std::io::Cursor::new("x")).is_err());
+
+        let body = read_request_body(Some("-"), None, &mut std::io::Cursor::new("q=1")).unwrap();
+        assert_eq!(body.as_deref(), Some(&b"q=1"[..]));
+        let err = read_request_body(Some("-"), None, &mut std::io::Cursor::new("")).unwrap_err();
+        assert!(err.to_string().contains("stdin was empty"), "{}", err);
+    }
+
+    #[test]
+    fn test_collect_method_parsing() {
+        let cli = Cli::parse_from([
+            "decisym_defcon33",
+            "collect",
+            "https://example.com",
+            "-X",
+            "put",
+        ]);
+        let Commands::Collect { method, .. } = cli.command else {
+            panic!("Expected collect command");
+        };
+        assert_eq!(method, HttpMethod::Put);
+
+        let err = Cli::try_parse_from([
+            "decisym_defcon33",
+            "collect",
+            "https://example.com",
+            "-X",
+            "PSOT",
+        ])
+        .unwrap_err();
+        assert!(err.to_string().contains("Unsupported HTTP method"));
+    }
+
+    #[test]
+    fn test_collect_multiple_urls() {
+        let dir = tempfile::tempdir().unwrap();
+        let url_file = dir.path().join("urls.txt");
+        std::fs::write(
+            &url_file,
+            "# speaker pages
+https://example.com/c
+https://EXAMPLE.com/a/
+
+  https://example.com/d  
+",
+        )
+        .unwrap();
+
+        let cli = Cli::parse_from([
+            "decisym_defcon33",
+            "collect",
+            "https://example.com/a",
+            "https://example.com/b",
+            "--url-file",
+            url_file.to_str().unwrap(),
+            "--fail-fast",
+            "--concurrency",
+            "4",
+        ]);
+        let Commands::Collect {
+            mut urls,
+            url_file,
+            fail_fast,
+            concurrency,
+            ..
+        } = cli.command
+        else {
+            panic!("Expected collect command");
+        };
+        assert!(fail_fast);
+        assert_eq!(concurrency, 4);
+        urls.extend(read_url_file(url_file.as_deref().unwrap()).unwrap());
+        let urls = distinct_urls(urls);
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/a",
+                "https://example.com/b",
+                "https://example.com/c",
+                "https://example.com/d",
+            ]
+        );
+        assert!(Cli::try_parse_from(["decisym_defcon33", "collect"]).is_err());
+
+        let results = vec![
+            Ok(PathBuf::from("a.html")),
+            Err(anyhow::anyhow!("connection reset").context("Failed to download")),
+        ];
+        let mut out = Vec::new();
+        write_collect_summary(&urls[..2], &results, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Downloaded 1 of 2 URL(s):\n  \
+             OK      https://example.com/a -> a.html\n  \
+             FAILED  https://example.com/b: connection reset\n"
+        );
+    }
+
+    #[test]
+    fn test_collect_spider_flags() {
+        let cli = Cli::parse_from([
+            "decisym_defcon33",
+            "collect",
+            "https://example.com",
+            "--spider",
+            "--max-depth",
+            "3",
+            "--same-host",
+            "-o",
+            "mirror",
+        ]);
+        let Commands::Collect {
+            spider,
+            max_depth,
+            same_host,
+            output,
+            ..
+        } = cli.command
+        else {
+            panic!("Expected collect command");
+        };
+        assert!(spider && same_host);
+        assert_eq!(max_depth, 3);
+        assert_eq!(output, Some(PathBuf::from("mirror")));
+
+        for args in [
+            &["--max-depth", "3"][..],
+            &["--same-host"][..],
+            &["--spider", "-d", "x=1"][..],
+        ] {
+            let mut argv = vec!["decisym_defcon33", "collect", "https://example.com"];
+            argv.extend_from_slice(args);
+            assert!(Cli::try_parse_from(argv).is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn test_collect_auth_flags() {
+        assert_eq!(parse_basic_auth("alice:pa:ss").unwrap(), ("alice", "pa:ss"));
+        let err = parse_basic_auth("alice").unwrap_err();
+        assert!(err.to_string().contains("password is required"), "{}", err);
+
+        assert!(
+            Cli::try_parse_from([
+                "decisym_defcon33",
+                "collect",
+                "https://example.com",
+                "--user",
+                "alice:pw",
+                "--bearer",
+                "tok",
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_rdf_stats_counts_fixture() {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/speakers_foaf.ttl");
+        let cli = Cli::parse_from(["decisym_defcon33", "rdf-stats", fixture.to_str().unwrap()]);
+        let Commands::RdfStats { file } = cli.command else {
+            panic!("expected rdf-stats");
+        };
+
+        let mut out = Vec::new();
+        rdf_stats(&file, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Triples: 15\n\
+             Subjects: 5\n\
+             Predicates: 7\n\
+             Prefixes:\n  \
+             foaf: <http://xmlns.com/foaf/0.1/>\n  \
+             org: <http://www.w3.org/ns/org#>\n"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let broken = dir.path().join("broken.ttl");
+        std::fs::write(
+            &broken,
+            "@prefix ex: <http://example.org/> .\nex:s ex:p \"open .\n",
+        )
+        .unwrap();
+        let err = rdf_stats(&broken, &mut Vec::new()).unwrap_err();
+        assert!(format!("{:#}", err).ends_with(": line 2: Unexpected end of file"));
+    }
+
+    #[tokio::test]
+    async fn test_enrich_skip_existing_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("speakers.json");
+        std::fs::write(&output, "{\"speakers\": []}").unwrap();
+
+        // Nothing listens on the discard port, so any request would fail
+        let cli = Cli::parse_from([
+            "decisym_defcon33",
+            "--quiet",
+            "enrich",
+            "--prompt",
+            "Extract speakers",
+            "--model",
+            "test-model",
+            "--api-url",
+            "http://127.0.0.1:9/v1",
+            "-o",
+            output.to_str().unwrap(),
+            "--skip-existing",
+            "--retries",
+            "0",
+        ]);
+        handle_enrich_command(&cli, &cli.command).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "{\"speakers\": []}"
+        );
+
+        // An empty output file doesn't count as finished
+        std::fs::write(&output, "").unwrap();
+        assert!(!output_exists(&output));
+        assert!(handle_enrich_command(&cli, &cli.command).await.is_err());
+    }
+
+    #[test]
+    fn test_wikidata_count_only_flag() {
+        let cli = Cli::parse_from([
+            "decisym_defcon33",
+            "wikidata",
+            "--count-only",
+            "--types",
+            "Q5,Q43229",
+        ]);
+        let Commands::Wikidata {
+            types,
+            industries,
+            endpoint,
+            count_only,
+            pretty,
+            ..
+        } = cli.command
+        else {
+            panic!("Expected wikidata command");
+        };
+        assert!(count_only);
+        assert!(!pretty);
+        assert_eq!(types, ["Q5", "Q43229"]);
+        assert!(industries.is_empty());
+        assert_eq!(endpoint, "https://query.wikidata.org/sparql");
+    }
+
+    #[test]
+    fn test_enrich_output_suffix() {
+        let cli = Cli::parse_from([
+            "decisym_defcon33",
+            "enrich",
+            "-c",
+            "examples/extract_speakers.yaml",
+            "-i",
+            "data/recon_village.html",
+            "-i",
+            "data/speakers.txt",
+            "--output-suffix",
+            ".enriched.json",
+            "--skip-existing",
+        ]);
+        let Commands::Enrich {
+            input_files,
+            output,
+            output_suffix,
+            ..
+        } = cli.command
+        else {
+            panic!("Expected enrich command");
+        };
+        let jobs = enrich_jobs(&input_files, output.as_deref(), output_suffix.as_deref()).unwrap();
+        let outputs: Vec<_> = jobs.into_iter().filter_map(|job| job.output).collect();
+        assert_eq!(
+            outputs,
+            [
+                PathBuf::from("data/recon_village.html.enriched.json"),
+                PathBuf::from("data/speakers.txt.enriched.json"),
+            ]
+        );
+
+        // Two inputs can't share one -o file, and -o excludes a suffix
+        let err = enrich_jobs(&input_files, Some(Path::new("out.json")), None).unwrap_err();
+        assert!(err.to_string().contains("add --output-suffix"), "{}", err);
+        assert!(
+            Cli::try_parse_from([
+                "decisym_defcon33",
+                "enrich",
+                "-i",
+                "page.html",
+                "-o",
+                "out.json",
+                "--output-suffix",
+                ".json",
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_human_friendly_sizes_and_durations() {
+        assert_eq!(parse_size::<u64>("10M"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size::<u64>("1G"), Ok(1024 * 1024 * 1024));
+        assert_eq!(parse_size::<u64>("500K"), Ok(500 * 1024));
+        assert_eq!(parse_size::<u64>("64kb"), Ok(64 * 1024));
+        assert_eq!(parse_size::<usize>("8192"), Ok(8192));
+        assert!(parse_size::<u64>("10X").is_err());
+        assert!(parse_size::<u64>("1.5M").is_err());
+        assert!(parse_size::<u64>("99999999999T").is_err());
+
+        assert_eq!(parse_seconds("5m"), Ok(300));
+        assert_eq!(parse_seconds("30s"), Ok(30));
+        assert_eq!(parse_seconds("120"), Ok(120));
+        assert_eq!(parse_seconds("2h"), Ok(7200));
+        assert!(parse_seconds("m").is_err());
+        assert!(parse_seconds("5 minutes").is_err());
+
+        let cli = Cli::parse_from([
+            "decisym_defcon33",
+            "collect",
+            "https://example.com",
+            "--buffer-size",
+            "64K",
+            "--max-size",
+            "10M",
+            "--timeout",
+            "2m",
+        ]);
+        let Commands::Collect {
+            wait,
+            buffer_size,
+            max_size,
+            timeout,
+            ..
+        } = cli.command
+        else {
+            panic!("expected collect");
+        };
+        assert_eq!(
+            (wait, buffer_size, max_size, timeout),
+            (None, 65536, 10 << 20, 120)
+        );
+    }
+
+    #[test]
+    fn test_enrich_extract_matching_pattern() {
+        let cli = Cli::parse_from([
+            "decisym_defcon33",
+            "enrich",
+            "--prompt",
+            "List speakers as JSON",
+            "--model",
+            "test-model",
+            "--extract",
+            r"(?s)```json\s*(.*?)\s*```",
+        ]);
+        let Commands::Enrich { extract, .. } = cli.command else {
+            panic!("Expected enrich command");
+        };
+
+        let pattern = compile_extract_pattern(extract.as_deref().unwrap()).unwrap();
+        let output = "Here are the speakers:\n```json\n[\"Ada\", \"Grace\"]\n```\nLet me know!";
+        assert_eq!(
+            extract_capture(output, &pattern).unwrap(),
+            "[\"Ada\", \"Grace\"]"
+        );
+    }
+
+    #[test]
+    fn test_enrich_extract_non_matching_pattern() {
+        let pattern = compile_extract_pattern(r"(\[.*\])").unwrap();
+        let err = extract_capture("I could not find any speakers.", &pattern).unwrap_err();
+        assert!(err.to_string().contains("did not match"));
+
+        assert!(compile_extract_pattern(r"\[.*\]").is_err());
+        assert!(compile_extract_pattern(r"(unclosed").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_progress_goes_to_separate_writer() {
+        let mut stderr = Vec::new();
+        let body = with_progress(&mut stderr, async {
+            tokio::time::sleep(Duration::from_millis(600)).await;
+            "{\"speakers\": []}".to_string()
+        })
+        .await;
+
+        let progress = String::from_utf8(stderr).unwrap();
+        assert!(progress.contains("Waiting for response"), "{}", progress);
+        assert!(progress.ends_with("\r\x1b[2K"));
+        assert_eq!(body, "{\"speakers\": []}");
+
+        let mut line = Vec::new();
+        Progress::new(&mut line).tick(Some(42)).unwrap();
+        assert!(String::from_utf8(line).unwrap().contains("42 tokens"));
+    }
+
+    #[tokio::test]
+    async fn test_stream_progress_counts_tokens() {
+        let mut stderr = Vec::new();
+        let pieces = futures::stream::iter(["Hel", "lo", "!"].map(|p| Ok(p.to_string())));
+        let response = stream_with_progress(&mut stderr, pieces).await.unwrap();
+
+        let progress = String::from_utf8(stderr).unwrap();
+        assert!(progress.contains("Generating... 3 tokens"), "{}", progress);
+        assert!(!progress.contains("Hello"), "{}", progress);
+        assert!(progress.ends_with("\r\x1b[2K"));
+        assert_eq!(response, "Hello!");
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::HashSet;
+
+/// Canonicalizes URLs so equivalent targets compare equal, for cache keys and
+/// de-duplication.
+///
+/// Parsing always lowercases the scheme and host, resolves `.` and `..` path
+/// segments, drops the scheme's default port, and removes the fragment. Query
+/// sorting and trailing-slash stripping are configurable.
+#[derive(Debug, Clone)]
+pub struct UrlNormalizer {
+    sort_query: bool,
+    strip_trailing_slash: bool,
+}
+
+impl Default for UrlNormalizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UrlNormalizer {
+    /// Creates a normalizer that strips trailing slashes and keeps the query
+    /// parameter order.
+    pub fn new() -> Self {
+        Self {
+            sort_query: false,
+            strip_trailing_slash: true,
+        }
+    }
+
+    /// Sort query parameters by name (and then value), so `?b=2&a=1` and
+    /// `?a=1&b=2` normalize the same.
+    pub fn set_sort_query(&mut self, sort: bool) {
+        self.sort_query = sort;
+    }
+
+    /// Treat `/path/` and `/path` as the same resource. The root path `/` is
+    /// always kept.
+    pub fn set_strip_trailing_slash(&mut self, strip: bool) {
+        self.strip_trailing_slash = strip;
+    }
+
+    /// Returns the normalized form of `url`, or `url` unchanged if it can't be
+    /// parsed.
+    pub fn normalize(&self, url: &str) -> String {
+        let Ok(mut parsed) = url::Url::parse(url.trim()) else {
+            return url.to_string();
+        };
+
+        parsed.set_fragment(None);
+
+        if self.strip_trailing_slash && parsed.path().len() > 1 && parsed.path().ends_with('/') {
+            let path = parsed.path().trim_end_matches('/').to_string();
+            parsed.set_path(if path.is_empty() { "/" } else { &path });
+        }
+
+        if self.sort_query && parsed.query().is_some() {
+            let mut pairs: Vec<(String, String)> = parsed.query_pairs().into_owned().collect();
+            pairs.sort();
+            parsed.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+        if parsed.query() == Some("") {
+            parsed.set_query(None);
+        }
+
+        parsed.to_string()
+    }
+
+    /// Drops URLs that normalize the same as an earlier one, keeping the
+    /// first spelling of each in its original order.
+    pub fn dedup<T: AsRef<str>>(&self, urls: impl IntoIterator<Item = T>) -> Vec<T> {
+        let mut seen = HashSet::new();
+        urls.into_iter()
+            .filter(|url| seen.insert(self.normalize(url.as_ref())))
+            .collect()
+    }
+}
+
+/// Normalizes `url` with the default `UrlNormalizer` settings.
+pub fn normalize_url(url: &str) -> String {
+    UrlNormalizer::new().normalize(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lowercases_scheme_and_host() {
+        assert_eq!(
+            normalize_url("HTTPS://Example.COM/Path"),
+            "https://example.com/Path"
+        );
+    }
+
+    #[test]
+    fn test_resolves_dot_segments() {
+        assert_eq!(
+            normalize_url("https://example.com/a/./b/../c"),
+            "https://example.com/a/c"
+        );
+    }
+
+    #[test]
+    fn test_strips_default_port() {
+        assert_eq!(
+            normalize_url("https://example.com:443/path"),
+            "https://example.com/path"
+        );
+        assert_eq!(
+            normalize_url("http://example.com:80/path"),
+            "http://example.com/path"
+        );
+        assert_eq!(
+            normalize_url("https://example.com:8443/path"),
+            "https://example.com:8443/path"
+        );
+    }
+
+    #[test]
+    fn test_trailing_slash_handling() {
+        assert_eq!(
+            normalize_url("https://example.com/path/"),
+            normalize_url("https://example.com/path")
+        );
+        assert_eq!(normalize_url("https://example.com"), "https://example.com/");
+
+        let mut normalizer = UrlNormalizer::new();
+        normalizer.set_strip_trailing_slash(false);
+        assert_eq!(
+            normalizer.normalize("https://example.com/path/"),
+            "https://example.com/path/"
+        );
+    }
+
+    #[test]
+    fn test_query_sorting_is_optional() {
+        let url = "https://example.com/search?b=2&a=1";
+        assert_eq!(normalize_url(url), url);
+
+        let mut normalizer = UrlNormalizer::new();
+        normalizer.set_sort_query(true);
+        assert_eq!(
+            normalizer.normalize(url),
+            "https://example.com/search?a=1&b=2"
+        );
+        assert_eq!(
+            normalizer.normalize("https://example.com/search?"),
+            "https://example.com/search"
+        );
+    }
+
+    #[test]
+    fn test_drops_fragment_and_keeps_unparseable_input() {
+        assert_eq!(
+            normalize_url("https://example.com/page#section"),
+            "https://example.com/page"
+        );
+        assert_eq!(normalize_url("not a url"), "not a url");
+    }
+
+    #[test]
+    fn test_dedup_keeps_first_spelling() {
+        let urls = [
+            "https://HOST/path",
+            "https://example.com/a",
+            "https://host/path/",
+            "https://host:443/path#top",
+            "https://example.com/b",
+        ];
+        assert_eq!(
+            UrlNormalizer::new().dedup(urls),
+            [
+                "https://HOST/path",
+                "https://example.com/a",
+                "https://example.com/b"
+            ]
+        );
+    }
+}
@@ -1,7 +1,14 @@
+use crate::cassette::{Cassette, CassetteMode};
+use crate::download::{HttpMethod, HttpStatusError, TorDownloader, retry_after_delay};
 use anyhow::{Context, Result};
+use futures::{Stream, StreamExt};
 use reqwest::Client;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
 
 /// Configuration for OpenAI-compatible API requests
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,6 +34,17 @@ pub struct EnrichConfig {
     /// Request timeout in seconds
     #[serde(default = "default_timeout")]
     pub timeout_seconds: u64,
+
+    /// Retries of timeouts, connection failures, 429s, and 500, 502, 503, and
+    /// 504 responses, overriding the client's `set_max_retries`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<u32>,
+
+    /// Extra fields merged into the request JSON after the known ones, for
+    /// server parameters this crate doesn't model (e.g. `guided_choice`).
+    /// A key that matches a known field overrides it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extra_body: Option<serde_json::Map<String, serde_json::Value>>,
 }
 
 fn default_timeout() -> u64 {
@@ -48,6 +66,11 @@ pub enum PromptConfig {
 pub struct ChatMessage {
     pub role: String,
     pub content: String,
+
+    /// Optional participant name, for few-shot examples and multi-agent
+    /// conversations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
 }
 
 /// Generation parameters
@@ -76,6 +99,116 @@ pub struct GenerationParams {
     /// Random seed for reproducibility
     #[serde(skip_serializing_if = "Option::is_none")]
     pub seed: Option<u32>,
+
+    /// Penalty for tokens in proportion to how often they already appeared
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+
+    /// Penalty for tokens that already appeared at all
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+
+    /// Bias added to the logits of these token IDs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logit_bias: Option<HashMap<u32, f32>>,
+
+    /// Output format the server should constrain the response to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+}
+
+/// Structured output mode, sent as the request's `response_format`.
+///
+/// With a JSON format, `enrich` strips a markdown code fence around the
+/// response and checks that the rest parses as JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    /// Free-form text, the server default
+    Text,
+    /// Any JSON object: `{"type": "json_object"}`
+    JsonObject,
+    /// JSON matching a schema: `{"type": "json_schema", "json_schema":
+    /// {"name": ..., "schema": {...}}}`
+    JsonSchema { json_schema: serde_json::Value },
+}
+
+impl ResponseFormat {
+    /// Whether the response must be JSON
+    pub fn is_json(&self) -> bool {
+        !matches!(self, ResponseFormat::Text)
+    }
+}
+
+impl Default for GenerationParams {
+    fn default() -> Self {
+        Self {
+            max_tokens: default_max_tokens(),
+            temperature: default_temperature(),
+            top_p: None,
+            n: None,
+            stop: None,
+            seed: None,
+            frequency_penalty: None,
+            presence_penalty: None,
+            logit_bias: None,
+            response_format: None,
+        }
+    }
+}
+
+impl GenerationParams {
+    /// Creates default parameters with the sampling fields of `preset`.
+    pub fn preset(preset: Preset) -> Self {
+        let mut params = Self::default();
+        params.apply_preset(preset);
+        params
+    }
+
+    /// Sets the sampling fields (`temperature`, `top_p`, `seed`) from
+    /// `preset`, leaving `max_tokens`, `n`, and `stop` untouched.
+    pub fn apply_preset(&mut self, preset: Preset) {
+        let (temperature, top_p, seed) = match preset {
+            Preset::Deterministic => (0.0, None, Some(0)),
+            Preset::Precise => (0.2, Some(0.8), None),
+            Preset::Balanced => (0.7, Some(0.9), None),
+            Preset::Creative => (1.0, Some(0.95), None),
+        };
+        self.temperature = temperature;
+        self.top_p = top_p;
+        self.seed = seed;
+    }
+}
+
+/// Named sampling presets for `GenerationParams`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Greedy decoding with a fixed seed, for reproducible output
+    Deterministic,
+    /// Low temperature, for extraction and other factual tasks
+    Precise,
+    /// The default temperature with nucleus sampling
+    Balanced,
+    /// High temperature and wide nucleus, for varied output
+    Creative,
+}
+
+impl std::str::FromStr for Preset {
+    type Err = anyhow::Error;
+
+    /// Parses a preset name case-insensitively.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "deterministic" => Ok(Preset::Deterministic),
+            "precise" => Ok(Preset::Precise),
+            "balanced" => Ok(Preset::Balanced),
+            "creative" => Ok(Preset::Creative),
+            _ => anyhow::bail!(
+                "Unknown preset '{}': expected one of deterministic, precise, balanced, creative",
+                s
+            ),
+        }
+    }
 }
 
 fn default_max_tokens() -> u32 {
@@ -86,10 +219,72 @@ fn default_temperature() -> f32 {
     0.7
 }
 
+/// Generated content of an enrichment request, with why generation stopped
+/// and the token usage the server reported
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct EnrichResult {
+    /// Generated text of the first choice
+    pub content: String,
+
+    /// Why generation stopped, e.g. `stop`, or `length` at `max_tokens`
+    pub finish_reason: Option<String>,
+
+    /// Tokens in the prompt, if the server reported usage
+    pub prompt_tokens: Option<u64>,
+
+    /// Tokens generated, if the server reported usage
+    pub completion_tokens: Option<u64>,
+
+    /// Prompt and completion tokens together, if the server reported usage
+    pub total_tokens: Option<u64>,
+}
+
+impl EnrichResult {
+    /// Whether generation stopped at the token limit, so the content is
+    /// probably cut off.
+    pub fn is_truncated(&self) -> bool {
+        self.finish_reason.as_deref() == Some("length")
+    }
+
+    fn new(choice: Choice, usage: Option<Usage>) -> Self {
+        let usage = usage.unwrap_or_default();
+        Self {
+            content: choice.content,
+            finish_reason: choice.finish_reason,
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            total_tokens: usage.total_tokens,
+        }
+    }
+}
+
+/// Every choice of a completion or chat completion response, in order
+#[derive(Debug)]
+struct Choices {
+    choices: Vec<Choice>,
+    usage: Option<Usage>,
+}
+
+#[derive(Debug)]
+struct Choice {
+    content: String,
+    finish_reason: Option<String>,
+}
+
+/// Token counts reported in a response's `usage` object
+#[derive(Debug, Default, Deserialize)]
+struct Usage {
+    prompt_tokens: Option<u64>,
+    completion_tokens: Option<u64>,
+    total_tokens: Option<u64>,
+}
+
 /// Response from completion endpoint
 #[derive(Debug, Deserialize)]
 struct CompletionResponse {
     choices: Vec<CompletionChoice>,
+    #[serde(default)]
+    usage: Option<Usage>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -102,6 +297,32 @@ struct CompletionChoice {
 #[derive(Debug, Deserialize)]
 struct ChatCompletionResponse {
     choices: Vec<ChatChoice>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+/// Response from the models endpoint
+#[derive(Debug, Deserialize)]
+struct ModelList {
+    data: Vec<Model>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Model {
+    id: String,
+}
+
+/// Response from the embeddings endpoint
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<Embedding>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Embedding {
+    #[serde(default)]
+    index: Option<usize>,
+    embedding: Vec<f32>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -110,9 +331,221 @@ struct ChatChoice {
     finish_reason: Option<String>,
 }
 
+/// One chunk of a streamed chat completion
+#[derive(Debug, Deserialize)]
+struct ChatCompletionChunk {
+    #[serde(default)]
+    choices: Vec<ChatChunkChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChunkChoice {
+    delta: ChatDelta,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatDelta {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+/// A line of a Server-Sent Events chat completion stream
+#[derive(Debug, PartialEq)]
+enum SseLine {
+    /// A piece of the generated message
+    Content(String),
+    /// The `data: [DONE]` sentinel ending the stream
+    Done,
+    /// A comment, blank line, other field, or chunk without content
+    Other,
+}
+
+/// Reads content pieces from a streamed chat completion response
+struct SseReader {
+    request: Option<reqwest::RequestBuilder>,
+    response: Option<reqwest::Response>,
+    buffer: Vec<u8>,
+}
+
+impl SseReader {
+    /// Returns the next piece of content, or `None` at the end of the stream.
+    /// The request is sent on the first call.
+    async fn next_content(&mut self) -> Result<Option<String>> {
+        if let Some(request) = self.request.take() {
+            let response = request
+                .send()
+                .await
+                .map_err(OpenAIError::from_reqwest)
+                .context("Failed to send chat completion request")?;
+            if !response.status().is_success() {
+                return Err(OpenAIError::from_response(response).await.into());
+            }
+            self.response = Some(response);
+        }
+        let Some(response) = self.response.as_mut() else {
+            return Ok(None);
+        };
+
+        loop {
+            while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                match parse_sse_line(String::from_utf8_lossy(&line).trim_end())? {
+                    SseLine::Content(content) => return Ok(Some(content)),
+                    SseLine::Done => return Ok(None),
+                    SseLine::Other => {}
+                }
+            }
+            let chunk = response
+                .chunk()
+                .await
+                .map_err(OpenAIError::from_reqwest)
+                .context("Failed to read chat completion stream")?;
+            match chunk {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk),
+                // Treat a final line without a newline as complete
+                None if !self.buffer.is_empty() => self.buffer.push(b'\n'),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Classified failure of an enrichment request
+#[derive(Debug)]
+pub enum OpenAIError {
+    /// The configuration could not be read or is invalid
+    Config(String),
+    /// The API answered with a non-success status
+    Status {
+        status: u16,
+        body: String,
+        /// Delay the server asked for with `Retry-After`
+        retry_after: Option<Duration>,
+    },
+    /// The request timed out
+    Timeout(String),
+    /// The API could not be reached
+    Connection(String),
+    /// The API response was not in the expected format
+    InvalidResponse(String),
+}
+
+impl OpenAIError {
+    /// Short machine-readable name of the error kind
+    pub fn kind(&self) -> &'static str {
+        match self {
+            OpenAIError::Config(_) => "config",
+            OpenAIError::Status { .. } => "server",
+            OpenAIError::Timeout(_) => "timeout",
+            OpenAIError::Connection(_) => "connection",
+            OpenAIError::InvalidResponse(_) => "invalid_response",
+        }
+    }
+
+    /// HTTP status code, for errors reported by the server
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            OpenAIError::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Whether a retry might succeed: timeouts, connection failures, rate
+    /// limiting, and server errors other than 501 and 505.
+    fn is_transient(&self) -> bool {
+        match self {
+            OpenAIError::Timeout(_) | OpenAIError::Connection(_) => true,
+            OpenAIError::Status { status, .. } => matches!(status, 429 | 500 | 502 | 503 | 504),
+            OpenAIError::Config(_) | OpenAIError::InvalidResponse(_) => false,
+        }
+    }
+
+    /// Whether another endpoint might succeed: connection failures and
+    /// server errors.
+    fn is_unavailable(&self) -> bool {
+        match self {
+            OpenAIError::Connection(_) => true,
+            OpenAIError::Status { status, .. } => *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// Builds a `Status` error from a non-success response, reading its body
+    /// and `Retry-After` header.
+    async fn from_response(response: reqwest::Response) -> Self {
+        let status = response.status().as_u16();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| retry_after_delay(value, std::time::SystemTime::now()));
+        let body = response.text().await.unwrap_or_default();
+        OpenAIError::Status {
+            status,
+            body,
+            retry_after,
+        }
+    }
+
+    /// Classifies a failed `TorDownloader` request.
+    fn from_tor(e: anyhow::Error) -> Self {
+        match e
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<HttpStatusError>())
+        {
+            Some(error) => OpenAIError::Status {
+                status: error.status,
+                body: error.body.clone(),
+                retry_after: None,
+            },
+            None => OpenAIError::Connection(format!("{:#}", e)),
+        }
+    }
+
+    /// Classifies a transport-level `reqwest` error.
+    fn from_reqwest(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            OpenAIError::Timeout(e.to_string())
+        } else if e.is_decode() {
+            OpenAIError::InvalidResponse(e.to_string())
+        } else {
+            OpenAIError::Connection(e.to_string())
+        }
+    }
+}
+
+impl std::fmt::Display for OpenAIError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OpenAIError::Config(msg) => write!(f, "Invalid configuration: {}", msg),
+            OpenAIError::Status { status, body, .. } => {
+                write!(f, "API request failed with status {}: {}", status, body)
+            }
+            OpenAIError::Timeout(msg) => write!(f, "API request timed out: {}", msg),
+            OpenAIError::Connection(msg) => write!(f, "Failed to reach API: {}", msg),
+            OpenAIError::InvalidResponse(msg) => write!(f, "Invalid API response: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for OpenAIError {}
+
+/// Retries of transient failures unless the client or config sets otherwise
+const DEFAULT_MAX_RETRIES: u32 = 2;
+
+/// Timeout for `list_models`, which servers answer without inference
+const MODELS_TIMEOUT_SECONDS: u64 = 30;
+
 /// Client for OpenAI-compatible APIs
 pub struct OpenAIClient {
     client: Client,
+    tor: Option<TorDownloader>,
+    cassette: Option<Cassette>,
+    request_id_header: bool,
+    max_retries: u32,
+    retry_delay: Duration,
+    total_timeout: Option<Duration>,
+    hedge_after: Option<Duration>,
 }
 
 impl OpenAIClient {
@@ -122,42 +555,357 @@ impl OpenAIClient {
             .build()
             .context("Failed to create HTTP client")?;
 
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            tor: None,
+            cassette: None,
+            request_id_header: false,
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_delay: Duration::from_secs(1),
+            total_timeout: None,
+            hedge_after: None,
+        })
+    }
+
+    /// Create a client that sends its requests through `downloader`'s Tor
+    /// transport, sharing its Tor client and circuit, so a hosted endpoint
+    /// doesn't see where they come from. A local server doesn't need this.
+    ///
+    /// Streaming isn't supported over Tor; `chat_complete_stream` yields an
+    /// error instead of connecting directly.
+    pub fn with_tor(downloader: &TorDownloader) -> Result<Self> {
+        let mut client = Self::new()?;
+        client.tor = Some(downloader.clone());
+        Ok(client)
+    }
+
+    /// Create a client that records its requests and responses to the
+    /// cassette file at `path`, or answers them from it without a server.
+    /// Only successful responses are recorded, and streaming isn't supported.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cassette can't be read, or doesn't exist in
+    /// replay mode.
+    pub fn with_cassette(path: impl Into<PathBuf>, mode: CassetteMode) -> Result<Self> {
+        let mut client = Self::new()?;
+        client.cassette = Some(Cassette::open(path, mode)?);
+        Ok(client)
+    }
+
+    /// Attach a fresh `X-Request-Id` (UUID v4) to every request and log it
+    pub fn set_request_id_header(&mut self, enabled: bool) {
+        self.request_id_header = enabled;
+    }
+
+    /// Retry timeouts, connection failures, 429s, and 500, 502, 503, and 504
+    /// responses up to `retries` times (default 2). A config's `max_retries`
+    /// takes precedence.
+    pub fn set_max_retries(&mut self, retries: u32) {
+        self.max_retries = retries;
+    }
+
+    /// Wait before the first retry, doubling for each one after (default 1s)
+    pub fn set_retry_delay(&mut self, delay: Duration) {
+        self.retry_delay = delay;
+    }
+
+    /// Cap the time `enrich` spends on a request including all retries and
+    /// the waits between them. Each attempt's `timeout_seconds` is shortened
+    /// to fit the time left.
+    pub fn set_total_timeout(&mut self, total: Duration) {
+        self.total_timeout = Some(total);
+    }
+
+    /// Hedge each attempt: if no response has arrived after `delay`, send an
+    /// identical second request and use whichever completes first (disabled
+    /// by default)
+    pub fn set_hedge_after(&mut self, delay: Duration) {
+        self.hedge_after = Some(delay);
+    }
+
+    /// Returns a `curl` command equivalent to the request `enrich` sends for
+    /// `config`, with the API key redacted.
+    pub fn to_curl(&self, config: &EnrichConfig) -> String {
+        let (url, request_body) = request_parts(config);
+        let mut headers = vec!["Content-Type: application/json".to_string()];
+        if let Some(api_key) = &config.api_key {
+            headers.push(format!("Authorization: Bearer {}", api_key));
+        }
+        let timeout = config.timeout_seconds.to_string();
+        crate::download::curl_command(
+            "POST",
+            &url,
+            &headers,
+            Some(&request_body.to_string()),
+            &["--max-time", &timeout],
+        )
     }
 
-    /// Send an enrichment request based on the configuration
+    /// Send an enrichment request based on the configuration, retrying
+    /// transient failures as configured.
+    ///
+    /// # Errors
+    ///
+    /// Returns the last attempt's error, or an `OpenAIError::Timeout` if the
+    /// total timeout ran out first.
     pub async fn enrich(&self, config: &EnrichConfig) -> Result<String> {
-        match &config.prompt {
-            PromptConfig::Completion { prompt } => self.complete(config, prompt).await,
-            PromptConfig::Chat { messages } => self.chat_complete(config, messages).await,
+        self.enrich_all(config)
+            .await?
+            .into_iter()
+            .next()
+            .context("No completion returned")
+    }
+
+    /// Like `enrich`, but returns the text of every choice, e.g. all the
+    /// candidates when `n` is greater than 1.
+    ///
+    /// # Errors
+    ///
+    /// Returns the last attempt's error, or an `OpenAIError::Timeout` if the
+    /// total timeout ran out first.
+    pub async fn enrich_all(&self, config: &EnrichConfig) -> Result<Vec<String>> {
+        let response = self.send_with_retries(config).await?;
+        response
+            .choices
+            .into_iter()
+            .map(|choice| check_response_format(config, choice.content))
+            .collect()
+    }
+
+    /// Like `enrich`, but also returns the finish reason and token usage,
+    /// e.g. to tell whether the output was cut off at `max_tokens`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the last attempt's error, or an `OpenAIError::Timeout` if the
+    /// total timeout ran out first.
+    pub async fn enrich_detailed(&self, config: &EnrichConfig) -> Result<EnrichResult> {
+        let response = self.send_with_retries(config).await?;
+        let choice = response
+            .choices
+            .into_iter()
+            .next()
+            .context("No completion returned")?;
+        let mut result = EnrichResult::new(choice, response.usage);
+        result.content = check_response_format(config, result.content)?;
+        Ok(result)
+    }
+
+    /// Sends `config`'s request, retrying transient failures until the retries
+    /// or the total timeout run out.
+    async fn send_with_retries(&self, config: &EnrichConfig) -> Result<Choices> {
+        config.validate()?;
+        let deadline = self.total_timeout.map(|total| Instant::now() + total);
+        let deadline_error = |attempts: u32| {
+            OpenAIError::Timeout(format!(
+                "overall deadline of {:?} exceeded after {} attempt(s)",
+                self.total_timeout.unwrap_or_default(),
+                attempts
+            ))
+        };
+
+        let max_retries = config.max_retries.unwrap_or(self.max_retries);
+        let mut attempts = 0;
+        loop {
+            let mut timeout = Duration::from_secs(config.timeout_seconds);
+            if let Some(deadline) = deadline {
+                timeout = timeout.min(deadline.saturating_duration_since(Instant::now()));
+            }
+            attempts += 1;
+            let result = self.hedged_attempt(config, timeout).await;
+            let Err(e) = result else {
+                return result;
+            };
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                warn!("Attempt {} failed: {:#}", attempts, e);
+                return Err(deadline_error(attempts).into());
+            }
+
+            let transient = classify(&e).is_some_and(OpenAIError::is_transient);
+            if !transient {
+                return Err(e);
+            }
+            if attempts > max_retries {
+                if attempts == 1 {
+                    return Err(e);
+                }
+                return Err(e.context(format!("Giving up after {} attempts", attempts)));
+            }
+            // Wait as long as the server asks, or back off exponentially
+            let delay = match classify(&e) {
+                Some(OpenAIError::Status {
+                    retry_after: Some(retry_after),
+                    ..
+                }) => *retry_after,
+                _ => self.retry_delay * 2u32.saturating_pow(attempts - 1),
+            };
+            if deadline.is_some_and(|deadline| Instant::now() + delay >= deadline) {
+                warn!("Attempt {} failed: {:#}", attempts, e);
+                return Err(deadline_error(attempts).into());
+            }
+            warn!(
+                "Attempt {} of {} failed: {:#}; retrying in {:?}",
+                attempts,
+                max_retries + 1,
+                e,
+                delay
+            );
+            tokio::time::sleep(delay).await;
         }
     }
 
-    /// Send a completion request
-    async fn complete(&self, config: &EnrichConfig, prompt: &str) -> Result<String> {
-        let url = format!("{}/completions", config.api_url);
+    /// Returns the IDs of the models served at `api_url`, from its `/models`
+    /// endpoint.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the response isn't a model
+    /// list.
+    pub async fn list_models(&self, api_url: &str) -> Result<Vec<String>> {
+        let prompt = PromptConfig::Completion {
+            prompt: String::new(),
+        };
+        let config = EnrichConfig::new(api_url, "", prompt);
+        let url = format!("{}/models", api_url);
+        let timeout = Duration::from_secs(MODELS_TIMEOUT_SECONDS);
+        let body = self
+            .send_json(&config, &url, None, timeout, "model list")
+            .await?;
+        let models: ModelList = parse_json(&body).context("Failed to parse model list")?;
+        Ok(models.data.into_iter().map(|model| model.id).collect())
+    }
 
-        let mut request_body = serde_json::json!({
+    /// Returns an embedding vector for each of `inputs`, in order, from
+    /// `{api_url}/embeddings`. Use `embed_with` to send an API key or change
+    /// the timeout.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails, or the response doesn't hold
+    /// one embedding per input.
+    pub async fn embed(
+        &self,
+        api_url: &str,
+        model: &str,
+        inputs: &[String],
+    ) -> Result<Vec<Vec<f32>>> {
+        let prompt = PromptConfig::Completion {
+            prompt: String::new(),
+        };
+        self.embed_with(&EnrichConfig::new(api_url, model, prompt), inputs)
+            .await
+    }
+
+    /// Like `embed`, taking the endpoint, model, `api_key`, and
+    /// `timeout_seconds` from `config`. Its prompt and generation parameters
+    /// are ignored, and the request isn't retried.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails, or the response doesn't hold
+    /// one embedding per input.
+    pub async fn embed_with(
+        &self,
+        config: &EnrichConfig,
+        inputs: &[String],
+    ) -> Result<Vec<Vec<f32>>> {
+        if inputs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let url = format!("{}/embeddings", config.api_url);
+        let request_body = serde_json::json!({
             "model": config.model,
-            "prompt": prompt,
-            "max_tokens": config.parameters.max_tokens,
-            "temperature": config.parameters.temperature,
+            "input": inputs,
         });
+        let timeout = Duration::from_secs(config.timeout_seconds);
+        let body = self
+            .send_json(config, &url, Some(&request_body), timeout, "embeddings")
+            .await?;
+        let mut response: EmbeddingResponse =
+            parse_json(&body).context("Failed to parse embeddings response")?;
 
-        // Add optional parameters
-        if let Some(top_p) = config.parameters.top_p {
-            request_body["top_p"] = serde_json::json!(top_p);
-        }
-        if let Some(n) = config.parameters.n {
-            request_body["n"] = serde_json::json!(n);
+        // Servers may return the vectors out of order; `index` places them
+        response.data.sort_by_key(|embedding| embedding.index);
+        if response.data.len() != inputs.len() {
+            return Err(OpenAIError::InvalidResponse(format!(
+                "expected {} embeddings, got {}",
+                inputs.len(),
+                response.data.len()
+            ))
+            .into());
         }
-        if let Some(stop) = &config.parameters.stop {
-            request_body["stop"] = serde_json::json!(stop);
+        Ok(response
+            .data
+            .into_iter()
+            .map(|embedding| embedding.embedding)
+            .collect())
+    }
+
+    /// Tries `configs` in order with `enrich`, moving to the next when an
+    /// endpoint is unreachable or answers with a 5xx, and returns the first
+    /// success. Each config gets its own retries before falling back.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error that isn't worth falling back on, the last
+    /// config's error if every endpoint is unavailable, or a config error if
+    /// `configs` is empty.
+    pub async fn enrich_with_fallback(&self, configs: &[EnrichConfig]) -> Result<String> {
+        let mut last_error = None;
+        for config in configs {
+            let e = match self.enrich(config).await {
+                Ok(content) => return Ok(content),
+                Err(e) => e,
+            };
+            if !classify(&e).is_some_and(OpenAIError::is_unavailable) {
+                return Err(e);
+            }
+            warn!("Endpoint {} unavailable: {:#}", config.api_url, e);
+            last_error = Some(e);
         }
-        if let Some(seed) = config.parameters.seed {
-            request_body["seed"] = serde_json::json!(seed);
+        Err(last_error.unwrap_or_else(|| {
+            OpenAIError::Config("no enrichment endpoints given".to_string()).into()
+        }))
+    }
+
+    /// Streams a chat completion of `messages` with `config`'s endpoint,
+    /// model, and parameters, yielding each piece of the reply as it
+    /// arrives. The request is sent with `"stream": true` when the stream is
+    /// first polled, and the stream ends at the `data: [DONE]` sentinel.
+    /// Streamed requests are not retried or hedged.
+    ///
+    /// # Errors
+    ///
+    /// Yields an error if the request fails, the API answers with a
+    /// non-success status, or a chunk isn't a valid completion chunk.
+    pub fn chat_complete_stream(
+        &self,
+        config: &EnrichConfig,
+        messages: &[ChatMessage],
+    ) -> impl Stream<Item = Result<String>> + use<> {
+        let mut config = config.clone();
+        config.prompt = PromptConfig::Chat {
+            messages: messages.to_vec(),
+        };
+        let unsupported = if self.tor.is_some() {
+            Some("streaming is not supported over Tor")
+        } else if self.cassette.is_some() {
+            Some("streaming is not supported with a cassette")
+        } else {
+            None
+        };
+        let error = match unsupported {
+            Some(reason) => Some(OpenAIError::Config(reason.to_string()).into()),
+            None => config.validate().err(),
+        };
+        if let Some(error) = error {
+            return futures::stream::once(async move { Err(error) }).left_stream();
         }
 
+        let (url, mut request_body) = request_parts(&config);
+        request_body["stream"] = serde_json::json!(true);
+
         let mut req = self
             .client
             .post(&url)
@@ -167,105 +915,1792 @@ impl OpenAIClient {
         if let Some(api_key) = &config.api_key {
             req = req.header("Authorization", format!("Bearer {}", api_key));
         }
+        if self.request_id_header {
+            req = req.header("X-Request-Id", crate::download::new_request_id());
+        }
 
-        let response = req
-            .send()
-            .await
-            .context("Failed to send completion request")?;
+        let reader = SseReader {
+            request: Some(req),
+            response: None,
+            buffer: Vec::new(),
+        };
+        futures::stream::try_unfold(reader, |mut reader| async move {
+            let content = reader.next_content().await?;
+            Ok(content.map(|content| (content, reader)))
+        })
+        .right_stream()
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            anyhow::bail!("API request failed with status {}: {}", status, error_text);
+    /// Makes one `enrich` attempt, launching a hedge request if the first
+    /// hasn't returned after `hedge_after`. The first success wins and the
+    /// other request is dropped, cancelling it; if one fails, the other's
+    /// result is used instead.
+    async fn hedged_attempt(&self, config: &EnrichConfig, timeout: Duration) -> Result<Choices> {
+        let Some(hedge_after) = self.hedge_after.filter(|delay| *delay < timeout) else {
+            return self.send_request(config, timeout).await;
+        };
+
+        let first = self.send_request(config, timeout);
+        tokio::pin!(first);
+        tokio::select! {
+            result = &mut first => return result,
+            _ = tokio::time::sleep(hedge_after) => {}
         }
 
-        let completion: CompletionResponse = response
-            .json()
-            .await
-            .context("Failed to parse completion response")?;
+        info!(
+            "No response after {:?}; sending a hedge request",
+            hedge_after
+        );
+        let hedge = self.send_request(config, timeout - hedge_after);
+        tokio::pin!(hedge);
+        tokio::select! {
+            result = &mut first => match result {
+                Ok(result) => Ok(result),
+                Err(e) => {
+                    warn!("Request failed: {:#}; waiting for the hedge request", e);
+                    hedge.await
+                }
+            },
+            result = &mut hedge => match result {
+                Ok(result) => Ok(result),
+                Err(e) => {
+                    warn!("Hedge request failed: {:#}; waiting for the first request", e);
+                    first.await
+                }
+            },
+        }
+    }
 
-        completion
+    /// Sends a single completion or chat request for `config`
+    async fn send_request(&self, config: &EnrichConfig, timeout: Duration) -> Result<Choices> {
+        match &config.prompt {
+            PromptConfig::Completion { .. } => self.complete(config, timeout).await,
+            PromptConfig::Chat { .. } => self.chat_complete(config, timeout).await,
+        }
+    }
+
+    /// Send a completion request
+    async fn complete(&self, config: &EnrichConfig, timeout: Duration) -> Result<Choices> {
+        let body = self.post(config, timeout, "completion").await?;
+        let completion: CompletionResponse =
+            parse_json(&body).context("Failed to parse completion response")?;
+
+        if completion.choices.is_empty() {
+            anyhow::bail!("No completion returned");
+        }
+        let choices = completion
             .choices
             .into_iter()
-            .next()
-            .map(|choice| choice.text)
-            .ok_or_else(|| anyhow::anyhow!("No completion returned"))
+            .map(|choice| Choice {
+                content: choice.text,
+                finish_reason: choice.finish_reason,
+            })
+            .collect();
+        Ok(Choices {
+            choices,
+            usage: completion.usage,
+        })
     }
 
     /// Send a chat completion request
-    async fn chat_complete(
+    async fn chat_complete(&self, config: &EnrichConfig, timeout: Duration) -> Result<Choices> {
+        let body = self.post(config, timeout, "chat completion").await?;
+        let chat_completion: ChatCompletionResponse =
+            parse_json(&body).context("Failed to parse chat completion response")?;
+
+        if chat_completion.choices.is_empty() {
+            anyhow::bail!("No chat completion returned");
+        }
+        let choices = chat_completion
+            .choices
+            .into_iter()
+            .map(|choice| Choice {
+                content: choice.message.content,
+                finish_reason: choice.finish_reason,
+            })
+            .collect();
+        Ok(Choices {
+            choices,
+            usage: chat_completion.usage,
+        })
+    }
+
+    /// POSTs `config`'s completion or chat request with `send_json`.
+    async fn post(&self, config: &EnrichConfig, timeout: Duration, what: &str) -> Result<String> {
+        let (url, request_body) = request_parts(config);
+        self.send_json(config, &url, Some(&request_body), timeout, what)
+            .await
+    }
+
+    /// POSTs `request_body` to `url`, or GETs it without a body, over Tor if
+    /// the client was created with `with_tor`, and returns the response body
+    /// decoded with the charset its `Content-Type` declares. A cassette
+    /// replays the response instead, or records it. `what` names the request
+    /// in errors.
+    async fn send_json(
         &self,
         config: &EnrichConfig,
-        messages: &[ChatMessage],
+        url: &str,
+        request_body: Option<&serde_json::Value>,
+        timeout: Duration,
+        what: &str,
     ) -> Result<String> {
-        let url = format!("{}/chat/completions", config.api_url);
-
-        let mut request_body = serde_json::json!({
-            "model": config.model,
-            "messages": messages,
-            "max_tokens": config.parameters.max_tokens,
-            "temperature": config.parameters.temperature,
-        });
-
-        // Add optional parameters
-        if let Some(top_p) = config.parameters.top_p {
-            request_body["top_p"] = serde_json::json!(top_p);
+        let cassette = self.cassette.as_ref();
+        let cassette_key = request_body.unwrap_or(&serde_json::Value::Null);
+        if let Some(cassette) = cassette.filter(|c| c.mode() == CassetteMode::Replay) {
+            return cassette.replay(url, cassette_key);
         }
-        if let Some(n) = config.parameters.n {
-            request_body["n"] = serde_json::json!(n);
-        }
-        if let Some(stop) = &config.parameters.stop {
-            request_body["stop"] = serde_json::json!(stop);
-        }
-        if let Some(seed) = config.parameters.seed {
-            request_body["seed"] = serde_json::json!(seed);
+
+        let body = match &self.tor {
+            Some(tor) => {
+                self.send_over_tor(tor, config, url, request_body, timeout, what)
+                    .await?
+            }
+            None => {
+                self.send_direct(config, url, request_body, timeout, what)
+                    .await?
+            }
+        };
+        if let Some(cassette) = cassette {
+            cassette.record(url, cassette_key, &body)?;
         }
+        Ok(body)
+    }
 
-        let mut req = self
-            .client
-            .post(&url)
-            .json(&request_body)
-            .timeout(Duration::from_secs(config.timeout_seconds));
+    /// Sends the request to `url` with the direct HTTP client.
+    async fn send_direct(
+        &self,
+        config: &EnrichConfig,
+        url: &str,
+        request_body: Option<&serde_json::Value>,
+        timeout: Duration,
+        what: &str,
+    ) -> Result<String> {
+        let req = match request_body {
+            Some(request_body) => self.client.post(url).json(request_body),
+            None => self.client.get(url),
+        };
+        let mut req = req.timeout(timeout);
 
         if let Some(api_key) = &config.api_key {
             req = req.header("Authorization", format!("Bearer {}", api_key));
         }
+        if self.request_id_header {
+            req = req.header("X-Request-Id", crate::download::new_request_id());
+        }
 
         let response = req
             .send()
             .await
-            .context("Failed to send chat completion request")?;
+            .map_err(OpenAIError::from_reqwest)
+            .with_context(|| format!("Failed to send {} request", what))?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            anyhow::bail!("API request failed with status {}: {}", status, error_text);
+            return Err(OpenAIError::from_response(response).await.into());
         }
 
-        let chat_completion: ChatCompletionResponse = response
-            .json()
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let bytes = response
+            .bytes()
             .await
-            .context("Failed to parse chat completion response")?;
+            .map_err(OpenAIError::from_reqwest)
+            .with_context(|| format!("Failed to read {} response", what))?;
+        Ok(decode_body(content_type.as_deref(), &bytes))
+    }
 
-        chat_completion
-            .choices
-            .into_iter()
-            .next()
-            .map(|choice| choice.message.content)
-            .ok_or_else(|| anyhow::anyhow!("No chat completion returned"))
+    /// Sends the request to `url` with `tor`, giving up after `timeout`.
+    async fn send_over_tor(
+        &self,
+        tor: &TorDownloader,
+        config: &EnrichConfig,
+        url: &str,
+        request_body: Option<&serde_json::Value>,
+        timeout: Duration,
+        what: &str,
+    ) -> Result<String> {
+        let mut headers = vec!["Accept: application/json".to_string()];
+        if request_body.is_some() {
+            headers.push("Content-Type: application/json".to_string());
+        }
+        if let Some(api_key) = &config.api_key {
+            headers.push(format!("Authorization: Bearer {}", api_key));
+        }
+        if self.request_id_header {
+            headers.push(format!(
+                "X-Request-Id: {}",
+                crate::download::new_request_id()
+            ));
+        }
+
+        let body = request_body.map(|body| body.to_string());
+        let method = match body {
+            Some(_) => HttpMethod::Post,
+            None => HttpMethod::Get,
+        };
+        let request =
+            tor.request_response(url, method, &headers, body.as_deref().map(str::as_bytes));
+        let response = match tokio::time::timeout(timeout, request).await {
+            Ok(response) => response.map_err(OpenAIError::from_tor),
+            Err(_) => Err(OpenAIError::Timeout(format!(
+                "no response over Tor within {:?}",
+                timeout
+            ))),
+        }
+        .with_context(|| format!("Failed to send {} request over Tor", what))?;
+        Ok(decode_body(response.header("Content-Type"), &response.body))
+    }
+}
+
+/// Strips a markdown code fence from `content` and checks it parses, if
+/// `config` asks for a JSON response format.
+fn check_response_format(config: &EnrichConfig, content: String) -> Result<String> {
+    let json_requested = config
+        .parameters
+        .response_format
+        .as_ref()
+        .is_some_and(ResponseFormat::is_json);
+    if !json_requested {
+        return Ok(content);
+    }
+
+    let json = strip_code_fence(&content);
+    if let Err(e) = serde_json::from_str::<serde_json::Value>(json) {
+        return Err(OpenAIError::InvalidResponse(format!(
+            "expected JSON ({}), got: {}",
+            e, content
+        ))
+        .into());
     }
+    Ok(json.to_string())
+}
+
+/// Returns the body of a ```` ```json ```` fence around `text`, or `text`
+/// trimmed if it isn't fenced.
+fn strip_code_fence(text: &str) -> &str {
+    let text = text.trim();
+    let Some(rest) = text.strip_prefix("```") else {
+        return text;
+    };
+    // Drop the info string (e.g. `json`) on the opening line
+    let body = rest.split_once('\n').map_or("", |(_, body)| body);
+    body.strip_suffix("```").unwrap_or(body).trim()
+}
+
+/// Parses a decoded response body as JSON.
+fn parse_json<T: DeserializeOwned>(body: &str) -> Result<T> {
+    Ok(serde_json::from_str(body).map_err(|e| OpenAIError::InvalidResponse(e.to_string()))?)
+}
+
+/// Decodes `bytes` with the charset named in `content_type`, falling back to
+/// UTF-8 when there is none or it isn't recognized. A byte order mark
+/// overrides the declared charset.
+fn decode_body(content_type: Option<&str>, bytes: &[u8]) -> String {
+    let encoding = content_type
+        .into_iter()
+        .flat_map(|value| value.split(';').skip(1))
+        .find_map(|param| {
+            let (name, value) = param.split_once('=')?;
+            name.trim()
+                .eq_ignore_ascii_case("charset")
+                .then(|| value.trim().trim_matches('"'))
+        })
+        .and_then(|label| encoding_rs::Encoding::for_label(label.as_bytes()))
+        .unwrap_or(encoding_rs::UTF_8);
+    encoding.decode(bytes).0.into_owned()
+}
+
+/// Parses one line of a chat completion event stream.
+fn parse_sse_line(line: &str) -> Result<SseLine> {
+    let Some(data) = line.strip_prefix("data:") else {
+        return Ok(SseLine::Other);
+    };
+    let data = data.trim();
+    if data == "[DONE]" {
+        return Ok(SseLine::Done);
+    }
+    let chunk: ChatCompletionChunk = serde_json::from_str(data)
+        .map_err(|e| OpenAIError::InvalidResponse(format!("{}: {}", e, data)))
+        .context("Failed to parse chat completion chunk")?;
+    Ok(chunk
+        .choices
+        .into_iter()
+        .next()
+        .and_then(|choice| choice.delta.content)
+        .filter(|content| !content.is_empty())
+        .map_or(SseLine::Other, SseLine::Content))
+}
+
+/// Finds the `OpenAIError` behind `e`, if any.
+fn classify(e: &anyhow::Error) -> Option<&OpenAIError> {
+    e.chain()
+        .find_map(|cause| cause.downcast_ref::<OpenAIError>())
+}
+
+/// Splits `content` into the YAML front-matter and the remaining body, or
+/// returns `None` if it doesn't start with a `---` line.
+fn split_front_matter(content: &str) -> Option<(&str, &str)> {
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let rest = content
+        .strip_prefix("---\n")
+        .or_else(|| content.strip_prefix("---\r\n"))?;
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            return Some((&rest[..offset], &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Returns the endpoint URL and JSON body for `config`'s prompt type.
+fn request_parts(config: &EnrichConfig) -> (String, serde_json::Value) {
+    let (url, mut request_body) = match &config.prompt {
+        PromptConfig::Completion { prompt } => (
+            format!("{}/completions", config.api_url),
+            serde_json::json!({
+                "model": config.model,
+                "prompt": prompt,
+                "max_tokens": config.parameters.max_tokens,
+                "temperature": config.parameters.temperature,
+            }),
+        ),
+        PromptConfig::Chat { messages } => (
+            format!("{}/chat/completions", config.api_url),
+            serde_json::json!({
+                "model": config.model,
+                "messages": messages,
+                "max_tokens": config.parameters.max_tokens,
+                "temperature": config.parameters.temperature,
+            }),
+        ),
+    };
+
+    // Add optional parameters
+    if let Some(top_p) = config.parameters.top_p {
+        request_body["top_p"] = serde_json::json!(top_p);
+    }
+    if let Some(n) = config.parameters.n {
+        request_body["n"] = serde_json::json!(n);
+    }
+    if let Some(stop) = &config.parameters.stop {
+        request_body["stop"] = serde_json::json!(stop);
+    }
+    if let Some(seed) = config.parameters.seed {
+        request_body["seed"] = serde_json::json!(seed);
+    }
+    if let Some(frequency_penalty) = config.parameters.frequency_penalty {
+        request_body["frequency_penalty"] = serde_json::json!(frequency_penalty);
+    }
+    if let Some(presence_penalty) = config.parameters.presence_penalty {
+        request_body["presence_penalty"] = serde_json::json!(presence_penalty);
+    }
+    if let Some(logit_bias) = &config.parameters.logit_bias {
+        request_body["logit_bias"] = serde_json::json!(logit_bias);
+    }
+    if let Some(response_format) = &config.parameters.response_format {
+        request_body["response_format"] = serde_json::json!(response_format);
+    }
+    if let Some(extra) = &config.extra_body {
+        for (key, value) in extra {
+            request_body[key.as_str()] = value.clone();
+        }
+    }
+    (url, request_body)
 }
 
 impl EnrichConfig {
+    /// Build a configuration with default generation parameters and timeout
+    pub fn new(api_url: &str, model: &str, prompt: PromptConfig) -> Self {
+        Self {
+            api_url: api_url.to_string(),
+            api_key: None,
+            model: model.to_string(),
+            prompt,
+            parameters: GenerationParams::default(),
+            timeout_seconds: default_timeout(),
+            max_retries: None,
+            extra_body: None,
+        }
+    }
+
+    /// Checks the fields a server would reject: `temperature` in 0.0..=2.0,
+    /// `top_p` in 0.0..=1.0, a positive `max_tokens`, a parseable
+    /// `api_url`, and chat roles of `system`, `user`, or `assistant`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `OpenAIError::Config` listing every violation.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        if let Err(e) = url::Url::parse(&self.api_url) {
+            problems.push(format!("api_url '{}' is not a URL ({})", self.api_url, e));
+        }
+        let parameters = &self.parameters;
+        if !(0.0..=2.0).contains(&parameters.temperature) {
+            problems.push(format!(
+                "temperature {} is outside 0.0..=2.0",
+                parameters.temperature
+            ));
+        }
+        if let Some(top_p) = parameters.top_p.filter(|p| !(0.0..=1.0).contains(p)) {
+            problems.push(format!("top_p {} is outside 0.0..=1.0", top_p));
+        }
+        if parameters.max_tokens == 0 {
+            problems.push("max_tokens must be greater than 0".to_string());
+        }
+        if let PromptConfig::Chat { messages } = &self.prompt {
+            for (i, message) in messages.iter().enumerate() {
+                match message.role.as_str() {
+                    "system" | "user" | "assistant" => {}
+                    "" => problems.push(format!("message {} has an empty role", i + 1)),
+                    role => problems.push(format!(
+                        "message {} has role '{}', expected system, user, or assistant",
+                        i + 1,
+                        role
+                    )),
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(OpenAIError::Config(problems.join("; ")).into())
+        }
+    }
+
+    /// Load configuration from a YAML, JSON, or markdown file, chosen by
+    /// its extension.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `OpenAIError::Config` if the extension isn't `.yaml`,
+    /// `.yml`, `.json`, or `.md`, or the file can't be read or parsed.
+    pub fn from_file(path: &std::path::Path) -> Result<Self> {
+        match path.extension().and_then(|s| s.to_str()) {
+            Some("yaml") | Some("yml") => Self::from_yaml_file(path),
+            Some("json") => Self::from_json_file(path),
+            Some("md") | Some("markdown") => Self::from_markdown_file(path),
+            _ => Err(OpenAIError::Config(
+                "Configuration file must have .yaml, .yml, .json, or .md extension".to_string(),
+            )
+            .into()),
+        }
+    }
+
     /// Load configuration from a YAML file
     pub fn from_yaml_file(path: &std::path::Path) -> Result<Self> {
-        let content = std::fs::read_to_string(path).context("Failed to read configuration file")?;
-        serde_yaml::from_str(&content).context("Failed to parse YAML configuration")
+        let content = Self::read_config_file(path)?;
+        serde_yaml::from_str(&content)
+            .map_err(|e| OpenAIError::Config(e.to_string()))
+            .context("Failed to parse YAML configuration")
     }
 
     /// Load configuration from a JSON file
     pub fn from_json_file(path: &std::path::Path) -> Result<Self> {
-        let content = std::fs::read_to_string(path).context("Failed to read configuration file")?;
-        serde_json::from_str(&content).context("Failed to parse JSON configuration")
+        let content = Self::read_config_file(path)?;
+        serde_json::from_str(&content)
+            .map_err(|e| OpenAIError::Config(e.to_string()))
+            .context("Failed to parse JSON configuration")
+    }
+
+    /// Load configuration from a markdown file with YAML front-matter.
+    ///
+    /// The front-matter between the leading `---` lines holds the config
+    /// fields. The markdown body becomes the completion prompt, or is
+    /// appended as a user message when the front-matter sets `messages`.
+    pub fn from_markdown_file(path: &std::path::Path) -> Result<Self> {
+        let content = Self::read_config_file(path)?;
+        Self::from_markdown(&content)
+            .map_err(|e| OpenAIError::Config(e.to_string()))
+            .context("Failed to parse markdown configuration")
+    }
+
+    fn from_markdown(content: &str) -> Result<Self> {
+        let (front_matter, body) =
+            split_front_matter(content).context("Missing `---` delimited YAML front-matter")?;
+        let body = body.trim();
+        if body.is_empty() {
+            anyhow::bail!("Markdown body is empty");
+        }
+
+        let mut fields =
+            serde_yaml::from_str::<Option<serde_yaml::Mapping>>(front_matter)?.unwrap_or_default();
+        if fields.contains_key("prompt") {
+            anyhow::bail!("Front-matter must not set `prompt`; the markdown body is the prompt");
+        }
+        match fields.get_mut("messages") {
+            Some(serde_yaml::Value::Sequence(messages)) => {
+                messages.push(serde_yaml::to_value(ChatMessage {
+                    role: "user".to_string(),
+                    content: body.to_string(),
+                    name: None,
+                })?);
+            }
+            Some(_) => anyhow::bail!("Front-matter `messages` must be a list"),
+            None => {
+                fields.insert("prompt".into(), body.into());
+            }
+        }
+        Ok(serde_yaml::from_value(serde_yaml::Value::Mapping(fields))?)
+    }
+
+    fn read_config_file(path: &std::path::Path) -> Result<String> {
+        std::fs::read_to_string(path)
+            .map_err(|e| OpenAIError::Config(format!("{}: {}", path.display(), e)))
+            .context("Failed to read configuration file")
+    }
+}
+
+/// Completion length assumed for the low end of a cost estimate
+const DEFAULT_EXPECTED_COMPLETION_TOKENS: u32 = 256;
+
+/// Approximates the number of tokens `text` encodes to.
+///
+/// This is a tokenizer-free heuristic (about four characters per token, and
+/// at least one token per word) that is close enough for BPE vocabularies to
+/// budget requests, but not exact for any particular model.
+pub fn count_tokens(text: &str) -> usize {
+    text.split_whitespace()
+        .map(|word| word.chars().count().div_ceil(4).max(1))
+        .sum()
+}
+
+/// Placeholder in a prompt that `PromptConfig::inject_context` replaces
+pub const CONTEXT_PLACEHOLDER: &str = "{{context}}";
+
+impl PromptConfig {
+    /// Approximate prompt tokens, including the per-message overhead of
+    /// chat formatting.
+    pub fn count_tokens(&self) -> usize {
+        match self {
+            Self::Completion { prompt } => count_tokens(prompt),
+            Self::Chat { messages } => {
+                messages
+                    .iter()
+                    .map(|message| count_tokens(&message.content) + 4)
+                    .sum::<usize>()
+                    + 2
+            }
+        }
+    }
+
+    /// Replaces every `{{context}}` placeholder in the prompt or messages
+    /// with `context`. Returns `false` if there was no placeholder.
+    pub fn inject_context(&mut self, context: &str) -> bool {
+        let texts: Vec<&mut String> = match self {
+            Self::Completion { prompt } => vec![prompt],
+            Self::Chat { messages } => messages.iter_mut().map(|m| &mut m.content).collect(),
+        };
+        let mut found = false;
+        for text in texts {
+            if text.contains(CONTEXT_PLACEHOLDER) {
+                *text = text.replace(CONTEXT_PLACEHOLDER, context);
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// Appends `content` under a `Content:` heading to the prompt, or to the
+    /// last user message, adding one if there is none.
+    pub fn append_content(&mut self, content: &str) {
+        match self {
+            Self::Completion { prompt } => {
+                *prompt = format!("{}\n\nContent:\n{}", prompt, content);
+            }
+            Self::Chat { messages } => {
+                if let Some(last_msg) = messages.iter_mut().rev().find(|m| m.role == "user") {
+                    last_msg.content = format!("{}\n\nContent:\n{}", last_msg.content, content);
+                } else {
+                    messages.push(ChatMessage {
+                        role: "user".to_string(),
+                        content: format!("Content:\n{}", content),
+                        name: None,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Selects the `top_k` snippets of `text` most relevant to `query`.
+///
+/// Snippets are the blank-line separated paragraphs of `text`, scored by how
+/// many distinct query words (case-insensitive, ignoring punctuation) they
+/// contain. Snippets matching no query word are never selected. The result
+/// keeps the original document order.
+pub fn select_context<'a>(text: &'a str, query: &str, top_k: usize) -> Vec<&'a str> {
+    fn words(text: &str) -> std::collections::HashSet<String> {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect()
+    }
+
+    // Paragraph boundaries, also for CRLF line endings
+    let mut paragraphs = Vec::new();
+    let mut start = 0;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if line.trim().is_empty() {
+            paragraphs.push(text[start..offset].trim());
+            start = offset + line.len();
+        }
+        offset += line.len();
+    }
+    paragraphs.push(text[start..].trim());
+
+    let query = words(query);
+    let mut scored: Vec<(usize, usize, &str)> = paragraphs
+        .into_iter()
+        .filter(|snippet| !snippet.is_empty())
+        .enumerate()
+        .map(|(index, snippet)| (words(snippet).intersection(&query).count(), index, snippet))
+        .filter(|(score, _, _)| *score > 0)
+        .collect();
+    // Highest score first, earlier snippets winning ties
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    scored.truncate(top_k);
+    scored.sort_by_key(|(_, index, _)| *index);
+    scored.into_iter().map(|(_, _, snippet)| snippet).collect()
+}
+
+/// Estimated token usage and dollar cost range of a batch of requests
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostEstimate {
+    /// Approximate prompt tokens across all requests
+    pub prompt_tokens: u64,
+
+    /// Completion tokens if each completion has the expected length
+    pub expected_completion_tokens: u64,
+
+    /// Completion tokens if every completion runs to `max_tokens`
+    pub max_completion_tokens: u64,
+
+    /// Cost in dollars with expected-length completions
+    pub low: f64,
+
+    /// Cost in dollars with every completion at `max_tokens`
+    pub high: f64,
+}
+
+/// Estimates the cost of sending each of `configs`, given prices per 1,000
+/// prompt and completion tokens, assuming completions of 256 tokens at the
+/// low end. See `estimate_cost_with_completion`.
+pub fn estimate_cost(
+    configs: &[EnrichConfig],
+    price_per_1k_prompt: f64,
+    price_per_1k_completion: f64,
+) -> CostEstimate {
+    estimate_cost_with_completion(
+        configs,
+        price_per_1k_prompt,
+        price_per_1k_completion,
+        DEFAULT_EXPECTED_COMPLETION_TOKENS,
+    )
+}
+
+/// Estimates the cost of sending each of `configs`.
+///
+/// Prompt tokens come from `count_tokens`. The low end assumes each
+/// completion is `expected_completion_tokens` long (capped at the config's
+/// `max_tokens`); the high end assumes every completion hits `max_tokens`.
+/// Requests asking for `n` completions are counted `n` times.
+pub fn estimate_cost_with_completion(
+    configs: &[EnrichConfig],
+    price_per_1k_prompt: f64,
+    price_per_1k_completion: f64,
+    expected_completion_tokens: u32,
+) -> CostEstimate {
+    let mut prompt_tokens = 0u64;
+    let mut expected = 0u64;
+    let mut max = 0u64;
+    for config in configs {
+        let completions = u64::from(config.parameters.n.unwrap_or(1));
+        let max_tokens = config.parameters.max_tokens;
+        prompt_tokens += config.prompt.count_tokens() as u64;
+        expected += u64::from(expected_completion_tokens.min(max_tokens)) * completions;
+        max += u64::from(max_tokens) * completions;
+    }
+
+    let prompt_cost = prompt_tokens as f64 / 1000.0 * price_per_1k_prompt;
+    CostEstimate {
+        prompt_tokens,
+        expected_completion_tokens: expected,
+        max_completion_tokens: max,
+        low: prompt_cost + expected as f64 / 1000.0 * price_per_1k_completion,
+        high: prompt_cost + max as f64 / 1000.0 * price_per_1k_completion,
+    }
+}
+
+/// Best-effort repair of JSON output that was cut off mid-document, e.g. when
+/// a model hits its token limit.
+///
+/// Closes an unterminated string and any open arrays and objects. If that
+/// alone doesn't parse, the incomplete last element of the innermost container
+/// is dropped instead. Input that is already valid, or that can't be made
+/// valid this way, is returned unchanged.
+pub fn repair_json(input: &str) -> String {
+    if serde_json::from_str::<serde_json::Value>(input).is_ok() {
+        return input.to_string();
+    }
+
+    let mut closers = Vec::new();
+    let mut last_commas: Vec<Option<usize>> = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in input.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' | '[' => {
+                closers.push(if c == '{' { '}' } else { ']' });
+                last_commas.push(None);
+            }
+            '}' | ']' => {
+                if closers.pop() != Some(c) {
+                    // Mismatched delimiters aren't truncation; leave them alone
+                    return input.to_string();
+                }
+                last_commas.pop();
+            }
+            ',' => {
+                if let Some(last) = last_commas.last_mut() {
+                    *last = Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let close = |mut text: String| {
+        text.truncate(text.trim_end().len());
+        if text.ends_with(',') {
+            text.pop();
+        }
+        text.extend(closers.iter().rev());
+        text
+    };
+
+    let mut candidate = input.to_string();
+    if in_string {
+        if escaped {
+            candidate.pop();
+        }
+        candidate.push('"');
+    }
+    let mut candidates = vec![close(candidate)];
+    if let Some(Some(comma)) = last_commas.last() {
+        candidates.push(close(input[..*comma].to_string()));
+    }
+
+    candidates
+        .into_iter()
+        .find(|candidate| serde_json::from_str::<serde_json::Value>(candidate).is_ok())
+        .unwrap_or_else(|| input.to_string())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_repair_truncated_object() {
+        let truncated = r#"{"speakers": [{"name": "Ada"}, {"name": "Gra"#;
+        let repaired = repair_json(truncated);
+        let value: serde_json::Value = serde_json::from_str(&repaired).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"speakers": [{"name": "Ada"}, {"name": "Gra"}]})
+        );
+
+        // A dangling key can't be completed, so that member is dropped
+        let repaired = repair_json(r#"{"count": 2, "na"#);
+        assert_eq!(repaired, r#"{"count": 2}"#);
+    }
+
+    #[test]
+    fn test_repair_truncated_array() {
+        let repaired = repair_json(r#"["Ada", "Grace", "Mar"#);
+        assert_eq!(repaired, r#"["Ada", "Grace", "Mar"]"#);
+
+        let repaired = repair_json("[1, 2, tru");
+        assert_eq!(repaired, "[1, 2]");
+
+        let repaired = repair_json("[[1, 2], [3,\n");
+        assert_eq!(repaired, "[[1, 2], [3]]");
+    }
+
+    #[test]
+    fn test_repair_leaves_other_input_unchanged() {
+        assert_eq!(repair_json(r#"{"a": 1}"#), r#"{"a": 1}"#);
+        assert_eq!(repair_json(r#"{"a": }"#), r#"{"a": }"#);
+        assert_eq!(repair_json("[1, 2}"), "[1, 2}");
+        assert_eq!(repair_json("Sorry, I can't help"), "Sorry, I can't help");
+    }
+
+    #[test]
+    fn test_to_curl_redacts_api_key() {
+        let mut config = EnrichConfig::new(
+            "http://localhost:8000/v1",
+            "llama",
+            PromptConfig::Chat {
+                messages: vec![ChatMessage {
+                    role: "user".to_string(),
+                    content: "What's new?".to_string(),
+                    name: None,
+                }],
+            },
+        );
+        config.api_key = Some("sk-secret".to_string());
+
+        let curl = OpenAIClient::new().unwrap().to_curl(&config);
+        assert!(curl.starts_with("curl --max-time 300 -X POST "), "{}", curl);
+        assert!(
+            curl.contains(" -H 'Content-Type: application/json' "),
+            "{}",
+            curl
+        );
+        assert!(curl.contains(" -H 'Authorization: REDACTED' "), "{}", curl);
+        assert!(!curl.contains("sk-secret"), "{}", curl);
+        assert!(curl.contains(r#""content":"What'\''s new?""#), "{}", curl);
+        assert!(
+            curl.ends_with(" http://localhost:8000/v1/chat/completions"),
+            "{}",
+            curl
+        );
+    }
+
+    #[test]
+    fn test_extra_body_is_merged_into_request() {
+        let yaml = "api_url: http://localhost:8000/v1\n\
+                    model: llama\n\
+                    prompt: Is this a speaker?\n\
+                    temperature: 0.5\n\
+                    extra_body:\n  guided_choice: [\"yes\", \"no\"]\n  temperature: 0.0\n";
+        let config: EnrichConfig = serde_yaml::from_str(yaml).unwrap();
+
+        let (_, body) = request_parts(&config);
+        assert_eq!(body["guided_choice"], serde_json::json!(["yes", "no"]));
+        assert_eq!(body["temperature"], serde_json::json!(0.0));
+        assert_eq!(body["model"], "llama");
+
+        let (_, body) = request_parts(&chat_config("http://localhost:8000/v1"));
+        assert!(body.get("guided_choice").is_none());
+    }
+
+    #[test]
+    fn test_penalties_and_logit_bias_round_trip() {
+        let yaml = "api_url: http://localhost:8000/v1\n\
+                    model: llama\n\
+                    prompt: Name the speakers\n\
+                    frequency_penalty: 0.5\n\
+                    presence_penalty: -0.25\n\
+                    logit_bias:\n  50256: -100\n  13: 2.5\n";
+        let config: EnrichConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.parameters.frequency_penalty, Some(0.5));
+        assert_eq!(config.parameters.presence_penalty, Some(-0.25));
+        assert_eq!(
+            config.parameters.logit_bias,
+            Some(HashMap::from([(50256, -100.0), (13, 2.5)]))
+        );
+
+        let reparsed: EnrichConfig =
+            serde_yaml::from_str(&serde_yaml::to_string(&config).unwrap()).unwrap();
+        assert_eq!(reparsed.parameters.logit_bias, config.parameters.logit_bias);
+
+        let (_, body) = request_parts(&config);
+        assert_eq!(body["frequency_penalty"], serde_json::json!(0.5));
+        assert_eq!(body["presence_penalty"], serde_json::json!(-0.25));
+        assert_eq!(
+            body["logit_bias"],
+            serde_json::json!({"50256": -100.0, "13": 2.5})
+        );
+
+        let mut chat = chat_config("http://localhost:8000/v1");
+        let (_, body) = request_parts(&chat);
+        assert!(body.get("frequency_penalty").is_none());
+        assert!(body.get("logit_bias").is_none());
+        chat.parameters = config.parameters;
+        let (_, body) = request_parts(&chat);
+        assert_eq!(body["presence_penalty"], serde_json::json!(-0.25));
+    }
+
+    #[test]
+    fn test_response_format_is_sent() {
+        let yaml = "api_url: http://localhost:8000/v1\n\
+                    model: llama\n\
+                    prompt: List the speakers\n\
+                    response_format:\n  type: json_schema\n  json_schema:\n    name: speakers\n    schema: {type: array}\n";
+        let config: EnrichConfig = serde_yaml::from_str(yaml).unwrap();
+        let (_, body) = request_parts(&config);
+        assert_eq!(
+            body["response_format"],
+            serde_json::json!({
+                "type": "json_schema",
+                "json_schema": {"name": "speakers", "schema": {"type": "array"}}
+            })
+        );
+
+        let (_, body) = request_parts(&chat_config("http://localhost:8000/v1"));
+        assert!(body.get("response_format").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_json_response_format_strips_fences() {
+        let fenced = "```json\n{\"speakers\": [\"Jane Doe\"]}\n```";
+        let prose = "Sure! Here are the speakers: Jane Doe";
+        let bodies = [fenced, prose, prose].map(|content| {
+            serde_json::json!({
+                "choices": [{"message": {"role": "assistant", "content": content}, "finish_reason": "stop"}]
+            })
+            .to_string()
+        });
+        let (url, server) = mock_server(bodies.iter().map(|b| json_response(b)).collect()).await;
+        let client = OpenAIClient::new().unwrap();
+        let mut config = chat_config(&url);
+        config.parameters.response_format = Some(ResponseFormat::JsonObject);
+
+        let content = client.enrich(&config).await.unwrap();
+        assert_eq!(content, r#"{"speakers": ["Jane Doe"]}"#);
+
+        let err = client.enrich(&config).await.unwrap_err();
+        assert_eq!(classify(&err).unwrap().kind(), "invalid_response");
+        assert!(err.to_string().ends_with(prose));
+
+        // Without a JSON format the response is passed through
+        config.parameters.response_format = Some(ResponseFormat::Text);
+        assert_eq!(client.enrich(&config).await.unwrap(), prose);
+
+        let requests = server.await.unwrap();
+        assert!(requests[0].contains(r#""response_format":{"type":"json_object"}"#));
+        assert!(requests[2].contains(r#""response_format":{"type":"text"}"#));
+    }
+
+    #[test]
+    fn test_chat_message_name_round_trip() {
+        let yaml = "api_url: http://localhost:8000/v1\n\
+                    model: llama\n\
+                    messages:\n\
+                    \x20 - role: user\n    name: example_user\n    content: Who spoke?\n\
+                    \x20 - role: assistant\n    content: '[\"Jane Doe\"]'\n";
+        let config: EnrichConfig = serde_yaml::from_str(yaml).unwrap();
+        let PromptConfig::Chat { messages } = &config.prompt else {
+            panic!("expected chat messages, got {:?}", config.prompt);
+        };
+        assert_eq!(messages[0].name.as_deref(), Some("example_user"));
+        assert_eq!(messages[1].name, None);
+
+        let json = serde_json::to_value(messages).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {"role": "user", "content": "Who spoke?", "name": "example_user"},
+                {"role": "assistant", "content": "[\"Jane Doe\"]"}
+            ])
+        );
+        let round_trip: Vec<ChatMessage> = serde_json::from_value(json).unwrap();
+        assert_eq!(round_trip[0].name.as_deref(), Some("example_user"));
+        assert_eq!(round_trip[1].name, None);
+
+        let (_, body) = request_parts(&config);
+        assert_eq!(body["messages"][0]["name"], "example_user");
+        assert!(body["messages"][1].get("name").is_none());
+    }
+
+    #[test]
+    fn test_markdown_front_matter_config() {
+        let content = "---\n\
+                       api_url: http://localhost:8000/v1\n\
+                       model: llama\n\
+                       temperature: 0.1\n\
+                       ---\n\
+                       \n\
+                       # Task\n\
+                       \n\
+                       List every speaker.\n";
+        let config = EnrichConfig::from_markdown(content).unwrap();
+        assert_eq!(config.model, "llama");
+        assert_eq!(config.parameters.temperature, 0.1);
+        match config.prompt {
+            PromptConfig::Completion { prompt } => {
+                assert_eq!(prompt, "# Task\n\nList every speaker.")
+            }
+            other => panic!("expected a completion prompt, got {:?}", other),
+        }
+
+        let content = "---\n\
+                       api_url: http://localhost:8000/v1\n\
+                       model: llama\n\
+                       messages:\n  - role: system\n    content: Be brief.\n\
+                       ---\n\
+                       List every speaker.\n";
+        let config = EnrichConfig::from_markdown(content).unwrap();
+        match config.prompt {
+            PromptConfig::Chat { messages } => {
+                assert_eq!(messages.len(), 2);
+                assert_eq!(messages[1].role, "user");
+                assert_eq!(messages[1].content, "List every speaker.");
+            }
+            other => panic!("expected chat messages, got {:?}", other),
+        }
+
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/summarize.md");
+        assert!(EnrichConfig::from_markdown_file(&path).is_ok());
+
+        assert!(EnrichConfig::from_markdown("List every speaker.").is_err());
+        assert!(EnrichConfig::from_markdown("---\nmodel: llama\n---\n").is_err());
+    }
+
+    #[test]
+    fn test_count_tokens() {
+        assert_eq!(count_tokens(""), 0);
+        assert_eq!(count_tokens("a an the"), 3);
+        assert_eq!(count_tokens("extraordinarily"), 4);
+    }
+
+    #[test]
+    fn test_cost_estimate_scales_with_input() {
+        let config = |words: usize| {
+            let mut config = EnrichConfig::new(
+                "http://localhost:8000/v1",
+                "llama",
+                PromptConfig::Completion {
+                    prompt: "word ".repeat(words),
+                },
+            );
+            config.parameters.max_tokens = 1000;
+            config
+        };
+
+        let small = estimate_cost(&[config(1000)], 1.0, 2.0);
+        assert_eq!(small.prompt_tokens, 1000);
+        assert_eq!(small.expected_completion_tokens, 256);
+        assert_eq!(small.max_completion_tokens, 1000);
+        assert!((small.low - (1.0 + 0.512)).abs() < 1e-9, "{:?}", small);
+        assert!((small.high - (1.0 + 2.0)).abs() < 1e-9, "{:?}", small);
+
+        // Ten times the prompt text costs ten times as much to send
+        let large = estimate_cost(&[config(10_000)], 1.0, 0.0);
+        assert_eq!(large.prompt_tokens, 10 * small.prompt_tokens);
+        assert!((large.low - 10.0).abs() < 1e-9, "{:?}", large);
+
+        // So does a batch ten times as long
+        let batch = vec![config(1000); 10];
+        let batch = estimate_cost_with_completion(&batch, 1.0, 2.0, 100);
+        assert_eq!(batch.prompt_tokens, 10_000);
+        assert_eq!(batch.expected_completion_tokens, 1000);
+        assert!(batch.low < batch.high);
+    }
+
+    #[test]
+    fn test_generation_presets() {
+        let params = GenerationParams::preset(Preset::Deterministic);
+        assert_eq!(params.temperature, 0.0);
+        assert_eq!(params.top_p, None);
+        assert_eq!(params.seed, Some(0));
+
+        let params = GenerationParams::preset(Preset::Precise);
+        assert_eq!(params.temperature, 0.2);
+        assert_eq!(params.top_p, Some(0.8));
+        assert_eq!(params.seed, None);
+
+        let params = GenerationParams::preset(Preset::Balanced);
+        assert_eq!(params.temperature, 0.7);
+        assert_eq!(params.top_p, Some(0.9));
+        assert_eq!(params.seed, None);
+
+        let params = GenerationParams::preset(Preset::Creative);
+        assert_eq!(params.temperature, 1.0);
+        assert_eq!(params.top_p, Some(0.95));
+        assert_eq!(params.seed, None);
+    }
+
+    #[test]
+    fn test_preset_leaves_non_sampling_fields() {
+        let mut params = GenerationParams {
+            max_tokens: 4096,
+            n: Some(2),
+            stop: Some(vec!["END".to_string()]),
+            seed: Some(7),
+            ..GenerationParams::default()
+        };
+        params.apply_preset(Preset::Creative);
+
+        assert_eq!(params.max_tokens, 4096);
+        assert_eq!(params.n, Some(2));
+        assert_eq!(params.stop, Some(vec!["END".to_string()]));
+        assert_eq!(params.temperature, 1.0);
+        assert_eq!(params.seed, None);
+
+        assert_eq!(
+            "Deterministic".parse::<Preset>().unwrap(),
+            Preset::Deterministic
+        );
+        assert!("spicy".parse::<Preset>().is_err());
+    }
+
+    #[test]
+    fn test_select_context_ranks_by_query_overlap() {
+        let text = "Ada Lovelace wrote the first program.\n\n\
+                    The venue opens at nine.\n\n\
+                    Talk by Grace Hopper on compilers.\n\r\n\
+                    Grace and Ada share a panel.";
+        assert_eq!(
+            select_context(text, "Grace Hopper, Ada", 2),
+            vec![
+                "Talk by Grace Hopper on compilers.",
+                "Grace and Ada share a panel."
+            ]
+        );
+        assert_eq!(
+            select_context(text, "ADA", 5),
+            vec![
+                "Ada Lovelace wrote the first program.",
+                "Grace and Ada share a panel."
+            ]
+        );
+        assert!(select_context(text, "parking", 3).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_selected_context_is_injected_into_request() {
+        let body = r#"{"choices":[{"message":{"role":"assistant","content":"ok"},"finish_reason":"stop"}]}"#;
+        let (url, server) = mock_server(vec![json_response(body)]).await;
+
+        let mut config = chat_config(&url);
+        config.prompt = PromptConfig::Chat {
+            messages: vec![ChatMessage {
+                role: "user".to_string(),
+                content: "Using this context:\n{{context}}\n\nList the speakers.".to_string(),
+                name: None,
+            }],
+        };
+        let notes = "Keynote speaker: Ada Lovelace.\n\nLunch is served at noon.";
+        let context = select_context(notes, "keynote speaker", 1).join("\n\n");
+        assert!(config.prompt.inject_context(&context));
+
+        let client = OpenAIClient::new().unwrap();
+        assert_eq!(client.enrich(&config).await.unwrap(), "ok");
+
+        let request = server.await.unwrap().remove(0);
+        assert!(request.contains("Using this context:\\nKeynote speaker: Ada Lovelace."));
+        assert!(!request.contains("Lunch"));
+        assert!(!request.contains(CONTEXT_PLACEHOLDER));
+
+        let mut completion = PromptConfig::Completion {
+            prompt: "No placeholder".to_string(),
+        };
+        assert!(!completion.inject_context("ignored"));
+    }
+
+    /// Serves each canned raw HTTP response (see `json_response` and
+    /// `raw_response`) to one connection in turn and returns the base URL
+    /// plus a handle yielding the raw requests received.
+    pub(crate) async fn mock_server(
+        responses: Vec<String>,
+    ) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                requests.push(read_request(&mut socket).await);
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    /// A 200 response carrying the JSON `body`
+    pub(crate) fn json_response(body: &str) -> String {
+        raw_response("200 OK", "Content-Type: application/json\r\n", body)
+    }
+
+    fn raw_response(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            headers,
+            body.len(),
+            body
+        )
+    }
+
+    /// Reads one HTTP request, using Content-Length to find the end of the body.
+    async fn read_request(socket: &mut tokio::net::TcpStream) -> String {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            data.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&data).to_string();
+            if let Some(end) = text.find("\r\n\r\n") {
+                let content_length = text[..end]
+                    .lines()
+                    .find_map(|l| {
+                        l.to_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|v| v.trim().parse::<usize>().unwrap())
+                    })
+                    .unwrap_or(0);
+                if data.len() >= end + 4 + content_length {
+                    return text;
+                }
+            }
+            if n == 0 {
+                return text;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validate_reports_every_problem() {
+        assert!(chat_config("http://localhost:8000/v1").validate().is_ok());
+
+        let mut config = chat_config("localhost/v1");
+        config.parameters.temperature = 5.0;
+        config.parameters.top_p = Some(1.5);
+        config.parameters.max_tokens = 0;
+        if let PromptConfig::Chat { messages } = &mut config.prompt {
+            messages[0].role = "bot".to_string();
+            messages.push(ChatMessage {
+                role: String::new(),
+                content: "Hi".to_string(),
+                name: None,
+            });
+        }
+
+        // Rejected before anything is sent, so no server is needed
+        let err = OpenAIClient::new()
+            .unwrap()
+            .enrich(&config)
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<OpenAIError>().unwrap().kind(), "config");
+        let message = err.to_string();
+        for problem in [
+            "api_url 'localhost/v1' is not a URL",
+            "temperature 5 is outside 0.0..=2.0",
+            "top_p 1.5 is outside 0.0..=1.0",
+            "max_tokens must be greater than 0",
+            "message 1 has role 'bot', expected system, user, or assistant",
+            "message 2 has an empty role",
+        ] {
+            assert!(message.contains(problem), "{}", message);
+        }
+    }
+
+    fn chat_config(api_url: &str) -> EnrichConfig {
+        EnrichConfig::new(
+            api_url,
+            "test-model",
+            PromptConfig::Chat {
+                messages: vec![ChatMessage {
+                    role: "user".to_string(),
+                    content: "Extract names".to_string(),
+                    name: None,
+                }],
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_total_timeout_caps_retries() {
+        // A server that accepts connections but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&accepted);
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                sockets.push(socket);
+            }
+        });
+
+        let mut config = chat_config(&url);
+        config.timeout_seconds = 60;
+        let mut client = OpenAIClient::new().unwrap();
+        client.set_max_retries(10);
+        client.set_retry_delay(Duration::from_millis(20));
+        client.set_total_timeout(Duration::from_millis(300));
+
+        let started = Instant::now();
+        let err = client.enrich(&config).await.unwrap_err();
+        let elapsed = started.elapsed();
+
+        assert!(elapsed >= Duration::from_millis(300), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+        let err = err.downcast_ref::<OpenAIError>().unwrap();
+        assert_eq!(err.kind(), "timeout");
+        assert!(
+            err.to_string().contains("overall deadline of 300ms"),
+            "{}",
+            err
+        );
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_transient_errors_are_retried() {
+        let (url, server) = mock_server(vec![
+            raw_response("503 Service Unavailable", "", "busy"),
+            json_response(
+                r#"{"choices":[{"message":{"role":"assistant","content":"ok"},"finish_reason":"stop"}]}"#,
+            ),
+        ])
+        .await;
+
+        let mut client = OpenAIClient::new().unwrap();
+        client.set_max_retries(1);
+        client.set_retry_delay(Duration::from_millis(10));
+        assert_eq!(client.enrich(&chat_config(&url)).await.unwrap(), "ok");
+        assert_eq!(server.await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_to_next_endpoint() {
+        // Nothing listens on the first endpoint's port
+        let down = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let down_url = format!("http://{}/v1", down.local_addr().unwrap());
+        drop(down);
+
+        let (url, server) = mock_server(vec![json_response(
+            r#"{"choices":[{"message":{"role":"assistant","content":"ok"},"finish_reason":"stop"}]}"#,
+        )])
+        .await;
+
+        let mut client = OpenAIClient::new().unwrap();
+        client.set_max_retries(0);
+        let configs = [chat_config(&down_url), chat_config(&url)];
+        assert_eq!(client.enrich_with_fallback(&configs).await.unwrap(), "ok");
+        assert_eq!(server.await.unwrap().len(), 1);
+
+        let err = client
+            .enrich_with_fallback(&configs[..1])
+            .await
+            .unwrap_err();
+        assert_eq!(classify(&err).unwrap().kind(), "connection");
+        let err = client.enrich_with_fallback(&[]).await.unwrap_err();
+        assert_eq!(classify(&err).unwrap().kind(), "config");
+    }
+
+    #[tokio::test]
+    async fn test_enrich_detailed_reports_finish_reason_and_usage() {
+        let (url, server) = mock_server(vec![
+            json_response(
+                r#"{"choices":[{"message":{"role":"assistant","content":"[\"Jane"},"finish_reason":"length"}],
+                "usage":{"prompt_tokens":120,"completion_tokens":16,"total_tokens":136}}"#,
+            ),
+            json_response(r#"{"choices":[{"text":"done","finish_reason":"stop"}]}"#),
+        ])
+        .await;
+        let client = OpenAIClient::new().unwrap();
+
+        let result = client.enrich_detailed(&chat_config(&url)).await.unwrap();
+        assert_eq!(
+            result,
+            EnrichResult {
+                content: "[\"Jane".to_string(),
+                finish_reason: Some("length".to_string()),
+                prompt_tokens: Some(120),
+                completion_tokens: Some(16),
+                total_tokens: Some(136),
+            }
+        );
+        assert!(result.is_truncated());
+
+        let config = EnrichConfig::new(
+            &url,
+            "test-model",
+            PromptConfig::Completion {
+                prompt: "Say done".to_string(),
+            },
+        );
+        let result = client.enrich_detailed(&config).await.unwrap();
+        assert_eq!(result.content, "done");
+        assert!(!result.is_truncated());
+        assert_eq!(result.total_tokens, None);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_with_tor_sends_requests_over_the_tor_transport() {
+        let (url, server) = mock_server(vec![json_response(
+            r#"{"choices":[{"message":{"role":"assistant","content":"Jane Doe"}}]}"#,
+        )])
+        .await;
+        let addr = url
+            .strip_prefix("http://")
+            .and_then(|rest| rest.strip_suffix("/v1"))
+            .unwrap()
+            .to_string();
+
+        // The connector stands in for Tor, recording where each stream goes
+        let (targets_tx, mut targets) = tokio::sync::mpsc::unbounded_channel();
+        let mut downloader = TorDownloader::with_connector(move |host, port| {
+            targets_tx.send((host, port)).unwrap();
+            tokio::net::TcpStream::connect(addr.clone())
+        });
+        downloader.set_rate_limit_delay(0);
+        let client = OpenAIClient::with_tor(&downloader).unwrap();
+
+        let content = client
+            .enrich(&chat_config("http://llm.example/v1"))
+            .await
+            .unwrap();
+        assert_eq!(content, "Jane Doe");
+        assert_eq!(targets.try_recv().unwrap(), ("llm.example".to_string(), 80));
+        assert!(targets.try_recv().is_err());
+
+        let request = server.await.unwrap().remove(0);
+        assert!(request.starts_with("POST /v1/chat/completions HTTP/1.1\r\n"));
+        assert!(request.contains("Host: llm.example\r\n"), "{}", request);
+    }
+
+    #[tokio::test]
+    async fn test_enrich_all_returns_every_choice() {
+        let choices = r#"{"choices":[
+            {"index":0,"message":{"role":"assistant","content":"Jane Doe"},"finish_reason":"stop"},
+            {"index":1,"message":{"role":"assistant","content":"J. Doe"},"finish_reason":"stop"},
+            {"index":2,"message":{"role":"assistant","content":"Doe, Jane"},"finish_reason":"stop"}]}"#;
+        let (url, server) = mock_server(vec![json_response(choices), json_response(choices)]).await;
+        let client = OpenAIClient::new().unwrap();
+        let mut config = chat_config(&url);
+        config.parameters.n = Some(3);
+
+        let all = client.enrich_all(&config).await.unwrap();
+        assert_eq!(all, ["Jane Doe", "J. Doe", "Doe, Jane"]);
+        assert_eq!(client.enrich(&config).await.unwrap(), "Jane Doe");
+
+        let requests = server.await.unwrap();
+        assert!(requests[0].contains(r#""n":3"#), "{}", requests[0]);
+    }
+
+    #[tokio::test]
+    async fn test_response_is_decoded_with_declared_charset() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            read_request(&mut socket).await;
+            // "Café Müller" in ISO-8859-1, which isn't valid UTF-8
+            let mut body = br#"{"choices":[{"message":{"role":"assistant","content":"Caf"#.to_vec();
+            body.extend_from_slice(b"\xe9 M\xfcller\"},\"finish_reason\":\"stop\"}]}");
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json; charset=\"ISO-8859-1\"\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            socket.write_all(head.as_bytes()).await.unwrap();
+            socket.write_all(&body).await.unwrap();
+        });
+
+        let client = OpenAIClient::new().unwrap();
+        let response = client.enrich(&chat_config(&url)).await.unwrap();
+        assert_eq!(response, "Café Müller");
+        server.await.unwrap();
+
+        assert_eq!(decode_body(None, "Café".as_bytes()), "Café");
+        assert_eq!(
+            decode_body(Some("application/json; charset=bogus"), "Café".as_bytes()),
+            "Café"
+        );
+        assert_eq!(
+            decode_body(Some("application/json;Charset=windows-1252"), b"\x93hi\x94"),
+            "\u{201c}hi\u{201d}"
+        );
+    }
+
+    #[test]
+    fn test_tor_errors_are_classified() {
+        let error = anyhow::Error::new(HttpStatusError {
+            status: 503,
+            body: "model is loading".to_string(),
+        })
+        .context("Failed to send request");
+        let error = OpenAIError::from_tor(error);
+        assert_eq!(error.status(), Some(503));
+        assert!(error.is_unavailable());
+        assert!(error.to_string().contains("model is loading"), "{}", error);
+
+        let error = OpenAIError::from_tor(anyhow::anyhow!("Failed to obtain exit circuit"));
+        assert_eq!(error.kind(), "connection");
+        assert!(error.is_transient());
+    }
+
+    #[test]
+    fn test_parse_sse_line() {
+        let line = r#"data: {"choices":[{"delta":{"content":"Hi"},"finish_reason":null}]}"#;
+        assert_eq!(
+            parse_sse_line(line).unwrap(),
+            SseLine::Content("Hi".to_string())
+        );
+        let role = r#"data:{"choices":[{"delta":{"role":"assistant"}}]}"#;
+        assert_eq!(parse_sse_line(role).unwrap(), SseLine::Other);
+        assert_eq!(
+            parse_sse_line(r#"data: {"choices":[]}"#).unwrap(),
+            SseLine::Other
+        );
+        assert_eq!(parse_sse_line(": keep-alive").unwrap(), SseLine::Other);
+        assert_eq!(parse_sse_line("").unwrap(), SseLine::Other);
+        assert_eq!(parse_sse_line("data: [DONE]").unwrap(), SseLine::Done);
+        let err = parse_sse_line("data: {oops").unwrap_err();
+        assert_eq!(classify(&err).unwrap().kind(), "invalid_response");
+    }
+
+    #[tokio::test]
+    async fn test_chat_complete_stream() {
+        use futures::TryStreamExt;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let request = read_request(&mut socket).await;
+            let chunk = |delta: &str| {
+                format!(
+                    "data: {{\"choices\":[{{\"delta\":{},\"finish_reason\":null}}]}}\n\n",
+                    delta
+                )
+            };
+            let body = [
+                chunk(r#"{"role":"assistant"}"#),
+                chunk(r#"{"content":"Jane"}"#),
+                ": keep-alive\n\n".to_string(),
+                chunk(r#"{"content":" Doe"}"#),
+                "data: [DONE]\n\n".to_string(),
+                chunk(r#"{"content":"ignored"}"#),
+            ]
+            .concat();
+            let (first, rest) = body.split_at(body.len() / 2);
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            socket.write_all(first.as_bytes()).await.unwrap();
+            socket.flush().await.unwrap();
+            socket.write_all(rest.as_bytes()).await.unwrap();
+            request
+        });
+
+        let client = OpenAIClient::new().unwrap();
+        let config = chat_config(&url);
+        let messages = [ChatMessage {
+            role: "user".to_string(),
+            content: "Stream please".to_string(),
+            name: None,
+        }];
+        let pieces: Vec<String> = client
+            .chat_complete_stream(&config, &messages)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(pieces, ["Jane", " Doe"]);
+
+        let request = server.await.unwrap();
+        assert!(request.contains(r#""stream":true"#), "{}", request);
+        assert!(request.contains("Stream please"), "{}", request);
+        assert!(!request.contains("Extract names"), "{}", request);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_is_honored() {
+        let ok = r#"{"choices":[{"message":{"role":"assistant","content":"ok"},"finish_reason":"stop"}]}"#;
+        let (url, server) = mock_server(vec![
+            raw_response("429 Too Many Requests", "Retry-After: 0\r\n", "slow down"),
+            raw_response("200 OK", "", ok),
+        ])
+        .await;
+
+        // The backoff alone would wait a minute
+        let mut client = OpenAIClient::new().unwrap();
+        client.set_retry_delay(Duration::from_secs(60));
+        let mut config = chat_config(&url);
+        config.max_retries = Some(1);
+        let started = Instant::now();
+        assert_eq!(client.enrich(&config).await.unwrap(), "ok");
+        assert!(started.elapsed() < Duration::from_secs(10));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_retries_are_exhausted_or_skipped() {
+        let busy = raw_response("503 Service Unavailable", "", "warming up");
+        let (url, server) = mock_server(vec![busy.clone(), busy.clone(), busy]).await;
+        let mut client = OpenAIClient::new().unwrap();
+        client.set_max_retries(0);
+        client.set_retry_delay(Duration::from_millis(10));
+        let mut config = chat_config(&url);
+        config.max_retries = Some(2);
+
+        let err = client.enrich(&config).await.unwrap_err();
+        assert_eq!(classify(&err).unwrap().status(), Some(503));
+        assert!(err.to_string().contains("after 3 attempts"), "{:#}", err);
+        server.await.unwrap();
+
+        // A bad request fails without retrying
+        let (url, server) =
+            mock_server(vec![raw_response("400 Bad Request", "", "unknown model")]).await;
+        let mut config = chat_config(&url);
+        config.max_retries = Some(3);
+        let err = client.enrich(&config).await.unwrap_err();
+        assert_eq!(classify(&err).unwrap().status(), Some(400));
+        assert!(!format!("{:#}", err).contains("attempts"), "{:#}", err);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_models() {
+        let body = r#"{"object":"list","data":[
+            {"id":"Qwen/Qwen3-30B-A3B-Instruct-2507","object":"model","owned_by":"vllm"},
+            {"id":"embed-model","object":"model"}
+        ]}"#;
+        let (url, server) = mock_server(vec![json_response(body), json_response("{}")]).await;
+        let client = OpenAIClient::new().unwrap();
+        assert_eq!(
+            client.list_models(&url).await.unwrap(),
+            ["Qwen/Qwen3-30B-A3B-Instruct-2507", "embed-model"]
+        );
+        let err = client.list_models(&url).await.unwrap_err();
+        assert_eq!(classify(&err).unwrap().kind(), "invalid_response");
+
+        let requests = server.await.unwrap();
+        assert!(requests[0].starts_with("GET /v1/models "));
+    }
+
+    #[tokio::test]
+    async fn test_embed_orders_vectors_by_index() {
+        let body = r#"{"object":"list","data":[
+            {"object":"embedding","index":1,"embedding":[0.5,-1.0]},
+            {"object":"embedding","index":0,"embedding":[0.25,2.0]}
+        ],"model":"embed-model"}"#;
+        let (url, server) = mock_server(vec![json_response(body), json_response(body)]).await;
+        let inputs = vec!["Jane Doe bio".to_string(), "John Roe bio".to_string()];
+
+        let client = OpenAIClient::new().unwrap();
+        let vectors = client.embed(&url, "embed-model", &inputs).await.unwrap();
+        assert_eq!(vectors, [vec![0.25, 2.0], vec![0.5, -1.0]]);
+
+        let mut config = chat_config(&url);
+        config.api_key = Some("sk-test".to_string());
+        let err = client.embed_with(&config, &inputs[..1]).await.unwrap_err();
+        assert_eq!(classify(&err).unwrap().kind(), "invalid_response");
+        assert!(
+            client
+                .embed(&url, "embed-model", &[])
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        let requests = server.await.unwrap();
+        assert!(requests[0].starts_with("POST /v1/embeddings "));
+        assert!(
+            requests[0]
+                .ends_with(r#"{"input":["Jane Doe bio","John Roe bio"],"model":"embed-model"}"#)
+        );
+        assert!(!requests[0].contains("authorization: Bearer"));
+        assert!(requests[1].contains("authorization: Bearer sk-test"));
+    }
+
+    #[tokio::test]
+    async fn test_cassette_records_then_replays_offline() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("enrich.json");
+        let body = r#"{"choices":[{"message":{"role":"assistant","content":"[\"Jane Doe\"]"},"finish_reason":"stop"}]}"#;
+        let (url, server) = mock_server(vec![json_response(body)]).await;
+        let config = chat_config(&url);
+
+        let recorder = OpenAIClient::with_cassette(&path, CassetteMode::Record).unwrap();
+        assert_eq!(recorder.enrich(&config).await.unwrap(), r#"["Jane Doe"]"#);
+        server.await.unwrap();
+
+        // The server is gone, so only the cassette can answer
+        let mut player = OpenAIClient::with_cassette(&path, CassetteMode::Replay).unwrap();
+        player.set_max_retries(0);
+        assert_eq!(player.enrich(&config).await.unwrap(), r#"["Jane Doe"]"#);
+        assert!(OpenAIClient::new().unwrap().enrich(&config).await.is_err());
+
+        let mut other = chat_config(&url);
+        other.parameters.temperature = 0.0;
+        let err = player.enrich(&other).await.unwrap_err();
+        assert_eq!(classify(&err).unwrap().kind(), "config");
+    }
+
+    #[tokio::test]
+    async fn test_hedge_request_wins_when_first_stalls() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            // The first request is read but never answered
+            let (mut stalled, _) = listener.accept().await.unwrap();
+            read_request(&mut stalled).await;
+
+            let (mut socket, _) = listener.accept().await.unwrap();
+            read_request(&mut socket).await;
+            let body = r#"{"choices":[{"message":{"role":"assistant","content":"hedged"},"finish_reason":"stop"}]}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+
+            // The losing request is cancelled, closing its connection
+            let mut buf = [0u8; 1];
+            stalled.read(&mut buf).await.unwrap()
+        });
+
+        let mut config = chat_config(&url);
+        config.timeout_seconds = 60;
+        let mut client = OpenAIClient::new().unwrap();
+        client.set_hedge_after(Duration::from_millis(100));
+
+        let started = Instant::now();
+        assert_eq!(client.enrich(&config).await.unwrap(), "hedged");
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(server.await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_request_id_header_is_sent() {
+        let body = r#"{"choices":[{"message":{"role":"assistant","content":"ok"},"finish_reason":"stop"}]}"#;
+        let (url, server) = mock_server(vec![json_response(body), json_response(body)]).await;
+
+        let mut client = OpenAIClient::new().unwrap();
+        assert_eq!(client.enrich(&chat_config(&url)).await.unwrap(), "ok");
+        client.set_request_id_header(true);
+        assert_eq!(client.enrich(&chat_config(&url)).await.unwrap(), "ok");
+
+        let requests = server.await.unwrap();
+        assert!(!requests[0].to_lowercase().contains("x-request-id"));
+        let id_line = requests[1]
+            .lines()
+            .find(|l| l.to_lowercase().starts_with("x-request-id:"))
+            .expect("X-Request-Id header missing");
+        let id = id_line.split_once(':').unwrap().1.trim();
+        assert!(uuid::Uuid::parse_str(id).is_ok());
     }
 }
@@ -0,0 +1,425 @@
+use crate::download::HttpResponseMeta;
+use crate::openai_client::{EnrichConfig, OpenAIClient};
+use crate::speakers::{link_affiliations, parse_speakers, speakers_by_company, speakers_to_foaf};
+use crate::turtle::parse_turtle;
+use crate::wikidata::WikidataDownloader;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// One step of a `Pipeline`. Steps that read a file take it from `input`,
+/// or from the previous step's output when `input` is omitted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum Step {
+    /// Download `url` and save the body to `output`
+    Collect { url: String, output: PathBuf },
+
+    /// Send the input to an LLM with the enrich configuration file `config`,
+    /// filling its `{{context}}` placeholder or appending the input to the
+    /// prompt, and save the response to `output`
+    #[serde(alias = "extract")]
+    Enrich {
+        config: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        input: Option<PathBuf>,
+        output: PathBuf,
+    },
+
+    /// Parse the speakers in an extraction response and save them as FOAF
+    /// Turtle to `output`
+    Convert {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        input: Option<PathBuf>,
+        output: PathBuf,
+    },
+
+    /// Link the affiliations in the input FOAF Turtle to Wikidata companies
+    /// with `owl:sameAs` (see `link_affiliations`) and save it to `output`.
+    /// The companies are read from the Turtle file `companies`, or downloaded
+    /// through Tor into `data_dir`; give exactly one.
+    Link {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        input: Option<PathBuf>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        companies: Option<PathBuf>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        data_dir: Option<PathBuf>,
+        output: PathBuf,
+    },
+
+    /// Group the speakers in the input FOAF Turtle by affiliation (see
+    /// `speakers_by_company`) and save the table as CSV to `output`
+    Analyze {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        input: Option<PathBuf>,
+        output: PathBuf,
+    },
+}
+
+impl Step {
+    /// The step's name as written in the pipeline file
+    pub fn name(&self) -> &'static str {
+        match self {
+            Step::Collect { .. } => "collect",
+            Step::Enrich { .. } => "enrich",
+            Step::Convert { .. } => "convert",
+            Step::Link { .. } => "link",
+            Step::Analyze { .. } => "analyze",
+        }
+    }
+}
+
+/// A declarative workflow over the library, e.g. collect → enrich →
+/// convert → link → analyze, loaded from YAML:
+///
+/// ```yaml
+/// steps:
+///   - step: collect
+///     url: https://www.reconvillage.org/
+///     output: out/recon_village.html
+///   - step: extract
+///     config: examples/extract_speakers.yaml
+///     output: out/speakers.json
+///   - step: convert
+///     output: out/speakers_foaf.ttl
+///   - step: link
+///     companies: analysis/security_companies.ttl
+///     output: out/speakers_linked.ttl
+///   - step: analyze
+///     output: out/speakers_by_company.csv
+/// ```
+///
+/// Paths are used as written, relative to the working directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pipeline {
+    pub steps: Vec<Step>,
+}
+
+impl Pipeline {
+    /// Load a pipeline from a YAML file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read or parsed, has no steps,
+    /// its first step needs an `input` it doesn't give, or a `link` step
+    /// doesn't give exactly one of `companies` and `data_dir`.
+    pub fn from_yaml_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read pipeline {}", path.display()))?;
+        let pipeline: Self = serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse pipeline {}", path.display()))?;
+        pipeline.validate()?;
+        Ok(pipeline)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let Some(first) = self.steps.first() else {
+            anyhow::bail!("Pipeline has no steps");
+        };
+        let needs_input = matches!(
+            first,
+            Step::Enrich { input: None, .. }
+                | Step::Convert { input: None, .. }
+                | Step::Link { input: None, .. }
+                | Step::Analyze { input: None, .. }
+        );
+        if needs_input {
+            anyhow::bail!(
+                "The first step ({}) has no previous output; give it an `input`",
+                first.name()
+            );
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            let ambiguous_link = matches!(
+                step,
+                Step::Link { companies, data_dir, .. } if companies.is_some() == data_dir.is_some()
+            );
+            if ambiguous_link {
+                anyhow::bail!(
+                    "Step {} (link) needs exactly one of `companies` and `data_dir`",
+                    i + 1
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the steps in order, downloading with `fetch` and sending LLM
+    /// requests with `client`. Returns the file each step wrote.
+    ///
+    /// # Errors
+    ///
+    /// Stops at the first step that fails, returning its error.
+    pub async fn run<F, Fut>(&self, client: &OpenAIClient, mut fetch: F) -> Result<Vec<PathBuf>>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = Result<(Vec<u8>, HttpResponseMeta)>>,
+    {
+        self.validate()?;
+        let mut outputs: Vec<PathBuf> = Vec::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            info!("Pipeline step {}: {}", i + 1, step.name());
+            let previous = outputs.last().map(PathBuf::as_path);
+            let output = self
+                .run_step(step, previous, client, &mut fetch)
+                .await
+                .with_context(|| format!("Pipeline step {} ({}) failed", i + 1, step.name()))?;
+            info!("Step {} wrote {}", i + 1, output.display());
+            outputs.push(output);
+        }
+        Ok(outputs)
+    }
+
+    async fn run_step<F, Fut>(
+        &self,
+        step: &Step,
+        previous: Option<&Path>,
+        client: &OpenAIClient,
+        fetch: &mut F,
+    ) -> Result<PathBuf>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = Result<(Vec<u8>, HttpResponseMeta)>>,
+    {
+        let read_input = |input: &Option<PathBuf>| -> Result<String> {
+            let path = input
+                .as_deref()
+                .or(previous)
+                .context("No input and no previous output")?;
+            std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))
+        };
+
+        match step {
+            Step::Collect { url, output } => {
+                let (body, _) = fetch(url.clone())
+                    .await
+                    .with_context(|| format!("Failed to download {}", url))?;
+                write_output(output, &body)?;
+                Ok(output.clone())
+            }
+            Step::Enrich {
+                config,
+                input,
+                output,
+            } => {
+                let content = read_input(input)?;
+                let mut config = EnrichConfig::from_file(config)?;
+                if !config.prompt.inject_context(&content) {
+                    config.prompt.append_content(&content);
+                }
+                let response = client.enrich(&config).await?;
+                write_output(output, response.as_bytes())?;
+                Ok(output.clone())
+            }
+            Step::Convert { input, output } => {
+                let speakers = parse_speakers(&read_input(input)?)?;
+                info!("Converting {} speaker(s) to FOAF", speakers.len());
+                write_output(output, speakers_to_foaf(&speakers)?.as_bytes())?;
+                Ok(output.clone())
+            }
+            Step::Link {
+                input,
+                companies,
+                data_dir,
+                output,
+            } => {
+                let speakers = parse_turtle(&read_input(input)?)?;
+                let companies = match (companies, data_dir) {
+                    (Some(path), _) => path.clone(),
+                    (None, Some(data_dir)) => {
+                        let mut downloader = WikidataDownloader::new(data_dir.clone()).await?;
+                        downloader.download_and_convert().await?
+                    }
+                    (None, None) => anyhow::bail!("No `companies` or `data_dir` to link against"),
+                };
+                let content = std::fs::read_to_string(&companies)
+                    .with_context(|| format!("Failed to read {}", companies.display()))?;
+                let companies = parse_turtle(&content)
+                    .with_context(|| format!("Failed to parse {}", companies.display()))?;
+
+                let (linked, links) = link_affiliations(&speakers, &companies)?;
+                info!("Linked {} affiliation(s) to Wikidata", links);
+                write_output(output, linked.to_turtle(true)?.as_bytes())?;
+                Ok(output.clone())
+            }
+            Step::Analyze { input, output } => {
+                let graph = parse_turtle(&read_input(input)?)?;
+                let mut csv = csv::Writer::from_writer(Vec::new());
+                csv.write_record(["company", "wikidata", "speaker_count", "speakers"])?;
+                for row in speakers_by_company(&graph) {
+                    csv.write_record([
+                        row.company,
+                        row.wikidata.unwrap_or_default(),
+                        row.speakers.len().to_string(),
+                        row.speakers.join(", "),
+                    ])?;
+                }
+                write_output(output, &csv.into_inner()?)?;
+                Ok(output.clone())
+            }
+        }
+    }
+}
+
+/// Writes `content` to `path`, creating its parent directories.
+fn write_output(path: &Path, content: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+    std::fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::Headers;
+    use crate::openai_client::tests::{json_response, mock_server};
+
+    #[tokio::test]
+    async fn test_collect_then_enrich() {
+        let dir = tempfile::tempdir().unwrap();
+        let (api_url, server) = mock_server(vec![json_response(
+            r#"{"choices":[{"message":{"role":"assistant","content":"[\"Jane Doe\"]"},"finish_reason":"stop"}]}"#,
+        )])
+        .await;
+        let config = dir.path().join("extract.yaml");
+        std::fs::write(
+            &config,
+            format!(
+                "api_url: {}\nmodel: test-model\nmessages:\n  - role: user\n    content: \"List the speakers in: {{{{context}}}}\"\n",
+                api_url
+            ),
+        )
+        .unwrap();
+
+        let html = dir.path().join("out/page.html");
+        let speakers = dir.path().join("out/speakers.json");
+        let pipeline_file = dir.path().join("pipeline.yaml");
+        std::fs::write(
+            &pipeline_file,
+            format!(
+                "steps:\n\
+                 \x20 - step: collect\n    url: https://conference.example/\n    output: {}\n\
+                 \x20 - step: extract\n    config: {}\n    output: {}\n",
+                html.display(),
+                config.display(),
+                speakers.display()
+            ),
+        )
+        .unwrap();
+
+        let pipeline = Pipeline::from_yaml_file(&pipeline_file).unwrap();
+        assert_eq!(pipeline.steps[1].name(), "enrich");
+        let client = OpenAIClient::new().unwrap();
+        let outputs = pipeline
+            .run(&client, |url| async move {
+                let meta = HttpResponseMeta {
+                    status: 200,
+                    final_url: url,
+                    headers: Headers::new(),
+                    trailers: Headers::new(),
+                    soft_block: None,
+                };
+                Ok((b"<p>Jane Doe, Acme</p>".to_vec(), meta))
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(outputs, [html.clone(), speakers.clone()]);
+        assert_eq!(
+            std::fs::read_to_string(&html).unwrap(),
+            "<p>Jane Doe, Acme</p>"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&speakers).unwrap(),
+            r#"["Jane Doe"]"#
+        );
+        let request = server.await.unwrap().remove(0);
+        assert!(request.contains(r#""content":"List the speakers in: <p>Jane Doe, Acme</p>""#));
+    }
+
+    #[test]
+    fn test_first_step_needs_input() {
+        let pipeline: Pipeline =
+            serde_yaml::from_str("steps:\n  - step: convert\n    output: foaf.ttl\n").unwrap();
+        let err = pipeline.validate().unwrap_err();
+        assert!(err.to_string().contains("(convert) has no previous output"));
+        assert!(serde_yaml::from_str::<Pipeline>("steps:\n  - step: analyze\n").is_err());
+
+        let pipeline: Pipeline = serde_yaml::from_str(
+            "steps:\n  - step: link\n    input: foaf.ttl\n    output: linked.ttl\n",
+        )
+        .unwrap();
+        let err = pipeline.validate().unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("exactly one of `companies` and `data_dir`")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_convert_link_analyze() {
+        let dir = tempfile::tempdir().unwrap();
+        let speakers = dir.path().join("speakers.json");
+        std::fs::write(
+            &speakers,
+            r#"[{"name": "Jane Doe", "affiliation": "Acme"},
+                {"name": "John Roe", "affiliation": "Globex, Inc."},
+                {"name": "Ali Smith", "affiliation": "Acme"}]"#,
+        )
+        .unwrap();
+        let companies = dir.path().join("companies.ttl");
+        std::fs::write(
+            &companies,
+            "@prefix wd: <http://www.wikidata.org/entity/> .\n\
+             @prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .\n\
+             wd:Q1 rdfs:label \"Acme\"@en .\n",
+        )
+        .unwrap();
+
+        let (foaf, linked, table) = (
+            dir.path().join("foaf.ttl"),
+            dir.path().join("linked.ttl"),
+            dir.path().join("by_company.csv"),
+        );
+        let pipeline: Pipeline = serde_yaml::from_str(&format!(
+            "steps:\n\
+             \x20 - step: convert\n    input: {}\n    output: {}\n\
+             \x20 - step: link\n    companies: {}\n    output: {}\n\
+             \x20 - step: analyze\n    output: {}\n",
+            speakers.display(),
+            foaf.display(),
+            companies.display(),
+            linked.display(),
+            table.display()
+        ))
+        .unwrap();
+
+        // No step downloads or sends anything
+        let client = OpenAIClient::new().unwrap();
+        let outputs = pipeline
+            .run(&client, |url| async move {
+                anyhow::bail!("Unexpected download of {}", url)
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(outputs, [foaf, linked.clone(), table.clone()]);
+        assert!(
+            std::fs::read_to_string(&linked)
+                .unwrap()
+                .contains("owl:sameAs <http://www.wikidata.org/entity/Q1>")
+        );
+        assert_eq!(
+            std::fs::read_to_string(&table).unwrap(),
+            "company,wikidata,speaker_count,speakers\n\
+             Acme,http://www.wikidata.org/entity/Q1,2,\"Jane Doe, Ali Smith\"\n\
+             \"Globex, Inc.\",,1,John Roe\n"
+        );
+    }
+}
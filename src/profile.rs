@@ -0,0 +1,129 @@
+use crate::download::TorDownloader;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Download etiquette for one kind of target: the user agent, pacing,
+/// headers, and retries to use with it.
+///
+/// Unset fields leave the downloader's own setting alone.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// User-Agent to send
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+
+    /// Seconds to wait before each request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wait_seconds: Option<u64>,
+
+    /// `Name: Value` headers sent with every request
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<String>,
+
+    /// Retries of a dropped, reset, or timed-out connection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_retries: Option<u32>,
+}
+
+impl Profile {
+    /// Loads the profile called `name` from a YAML file mapping profile
+    /// names to their settings.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read or parsed, or has no
+    /// profile called `name`.
+    pub fn from_yaml_file(path: &Path, name: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read profiles {}", path.display()))?;
+        let mut profiles: BTreeMap<String, Profile> = serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse profiles {}", path.display()))?;
+        let available = profiles.keys().cloned().collect::<Vec<_>>().join(", ");
+        profiles.remove(name).with_context(|| {
+            format!(
+                "No profile '{}' in {} (available: {})",
+                name,
+                path.display(),
+                available
+            )
+        })
+    }
+
+    /// Applies the profile's settings to `downloader`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a header isn't a valid `Name: Value` line.
+    pub fn apply(&self, downloader: &mut TorDownloader) -> Result<()> {
+        if let Some(user_agent) = &self.user_agent {
+            downloader.set_user_agent(user_agent);
+        }
+        if let Some(seconds) = self.wait_seconds {
+            downloader.set_rate_limit_delay(seconds);
+        }
+        if !self.headers.is_empty() {
+            downloader.set_extra_headers(self.headers.clone())?;
+        }
+        if let Some(retries) = self.connect_retries {
+            downloader.set_connect_retries(retries);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_profile_applies_to_downloader() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.yaml");
+        std::fs::write(
+            &path,
+            "wikidata:\n\
+             \x20 user_agent: OSINT-Research-Bot/1.0\n\
+             \x20 wait_seconds: 5\n\
+             \x20 headers:\n\
+             \x20   - \"Accept: text/csv\"\n\
+             \x20 connect_retries: 4\n\
+             generic-api:\n\
+             \x20 headers: [\"Accept: application/json\"]\n",
+        )
+        .unwrap();
+
+        let profile = Profile::from_yaml_file(&path, "wikidata").unwrap();
+        let mut downloader = TorDownloader::without_tor();
+        profile.apply(&mut downloader).unwrap();
+        assert_eq!(downloader.user_agent(), "OSINT-Research-Bot/1.0");
+        assert_eq!(downloader.rate_limit_delay(), Duration::from_secs(5));
+        assert_eq!(downloader.extra_headers(), ["Accept: text/csv"]);
+        assert_eq!(downloader.connect_retries(), 4);
+        let curl = downloader
+            .download_to_curl("https://query.wikidata.org/sparql")
+            .unwrap();
+        assert!(curl.contains("'Accept: text/csv'"), "{}", curl);
+
+        let err = Profile::from_yaml_file(&path, "wikipedia").unwrap_err();
+        assert!(
+            err.to_string().contains("available: generic-api, wikidata"),
+            "{}",
+            err
+        );
+
+        let bad = Profile {
+            headers: vec!["Accept text/csv".to_string()],
+            ..Profile::default()
+        };
+        assert!(bad.apply(&mut downloader).is_err());
+
+        let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/profiles.yaml");
+        for name in ["wikidata", "generic-api"] {
+            assert!(Profile::from_yaml_file(&examples, name).is_ok(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,616 @@
+use anyhow::{Context, Result};
+use oxrdf::vocab::{rdf, xsd};
+use oxrdf::{
+    BlankNode, BlankNodeRef, Literal, LiteralRef, NamedNode, NamedNodeRef, NamedOrBlankNode,
+    NamedOrBlankNodeRef, TermRef, Triple, TripleRef,
+};
+use oxttl::NTriplesSerializer;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::LazyLock;
+
+/// `rdf:type`, written as `a` in Turtle
+pub const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+
+/// `rdfs:label`
+pub const RDFS_LABEL: &str = "http://www.w3.org/2000/01/rdf-schema#label";
+
+/// `xsd:dateTime`
+pub const XSD_DATE_TIME: &str = "http://www.w3.org/2001/XMLSchema#dateTime";
+
+/// Lexical form of `xsd:dateTime`; field ranges are checked separately
+static DATE_TIME: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^-?(\d{4,})-(\d{2})-(\d{2})T(\d{2}):(\d{2}):(\d{2})(\.\d+)?(Z|[+-]\d{2}:\d{2})?$")
+        .expect("valid dateTime pattern")
+});
+
+/// A BCP 47 language tag as Turtle accepts it
+static LANGUAGE_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[a-zA-Z]+(-[a-zA-Z0-9]+)*$").expect("valid language pattern"));
+
+/// A local name that can follow a prefix without escaping
+static LOCAL_NAME: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^[A-Za-z0-9_]([A-Za-z0-9_.-]*[A-Za-z0-9_-])?$").expect("valid local name pattern")
+});
+
+/// An RDF term to add to a `Graph`, which checks it when it's added
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Term {
+    /// An absolute IRI
+    Iri(String),
+    /// A blank node, by label
+    Blank(String),
+    /// A literal with an optional language tag or datatype IRI
+    Literal {
+        value: String,
+        lang: Option<String>,
+        datatype: Option<String>,
+    },
+}
+
+impl Term {
+    /// An IRI term
+    pub fn iri(iri: &str) -> Self {
+        Term::Iri(iri.to_string())
+    }
+
+    /// A blank node term
+    pub fn blank(label: &str) -> Self {
+        Term::Blank(label.to_string())
+    }
+
+    /// A plain string literal
+    pub fn literal(value: &str) -> Self {
+        Term::Literal {
+            value: value.to_string(),
+            lang: None,
+            datatype: None,
+        }
+    }
+
+    /// A language-tagged string literal
+    pub fn lang_literal(value: &str, lang: &str) -> Self {
+        Term::Literal {
+            value: value.to_string(),
+            lang: Some(lang.to_string()),
+            datatype: None,
+        }
+    }
+
+    /// A literal of `datatype`.
+    ///
+    /// An `xsd:dateTime` value that isn't a valid lexical form (e.g. a zero
+    /// month or a bare date) becomes a plain literal, since stores reject or
+    /// mangle ill-typed values.
+    pub fn typed_literal(value: &str, datatype: &str) -> Self {
+        if datatype == XSD_DATE_TIME && !is_date_time(value) {
+            return Term::literal(value);
+        }
+        Term::Literal {
+            value: value.to_string(),
+            lang: None,
+            datatype: Some(datatype.to_string()),
+        }
+    }
+
+    /// Converts the term to oxrdf, checking its IRIs, blank node label, and
+    /// language tag.
+    fn to_rdf(&self) -> Result<oxrdf::Term> {
+        Ok(match self {
+            Term::Iri(iri) => named_node(iri)?.into(),
+            Term::Blank(label) => BlankNode::new(label)
+                .with_context(|| format!("Invalid blank node label '{}'", label))?
+                .into(),
+            Term::Literal {
+                value,
+                lang: Some(lang),
+                ..
+            } => Literal::new_language_tagged_literal(value, lang)
+                .with_context(|| format!("Invalid language tag '{}'", lang))?
+                .into(),
+            Term::Literal {
+                value,
+                datatype: Some(datatype),
+                ..
+            } => Literal::new_typed_literal(value, named_node(datatype)?).into(),
+            Term::Literal { value, .. } => Literal::new_simple_literal(value).into(),
+        })
+    }
+}
+
+/// Parses `iri` as an oxrdf named node.
+fn named_node(iri: &str) -> Result<NamedNode> {
+    NamedNode::new(iri).with_context(|| format!("Invalid IRI <{}>", iri))
+}
+
+/// Returns whether `value` is a valid `xsd:dateTime` lexical form.
+pub fn is_date_time(value: &str) -> bool {
+    let Some(captures) = DATE_TIME.captures(value) else {
+        return false;
+    };
+    let field = |i: usize| captures[i].parse::<u32>().unwrap_or(u32::MAX);
+    let (month, day, hour, minute, second) = (field(2), field(3), field(4), field(5), field(6));
+    // 24:00:00 is allowed as the end of a day
+    let end_of_day = hour == 24 && minute == 0 && second == 0 && captures.get(7).is_none();
+    (1..=12).contains(&month)
+        && (1..=31).contains(&day)
+        && (hour < 24 || end_of_day)
+        && minute < 60
+        && second < 60
+}
+
+/// Returns whether `lang` is a well-formed language tag.
+pub fn is_language_tag(lang: &str) -> bool {
+    LANGUAGE_TAG.is_match(lang)
+}
+
+/// A graph of oxrdf triples that serializes itself as Turtle, or as
+/// N-Triples with oxttl.
+///
+/// Triples keep the order they were added in, and duplicates are dropped.
+/// Subjects must be IRIs or blank nodes, and predicates are IRIs.
+#[derive(Debug, Clone, Default)]
+pub struct Graph {
+    prefixes: BTreeMap<String, String>,
+    triples: Vec<Triple>,
+    seen: HashSet<Triple>,
+}
+
+impl Graph {
+    /// Creates an empty graph with no prefixes
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare `prefix` for IRIs in `namespace`, replacing any earlier
+    /// declaration of `prefix`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `namespace` isn't a valid IRI.
+    pub fn add_prefix(&mut self, prefix: &str, namespace: &str) -> Result<()> {
+        named_node(namespace)?;
+        self.prefixes
+            .insert(prefix.to_string(), namespace.to_string());
+        Ok(())
+    }
+
+    /// Declared prefixes and their namespaces, by prefix
+    pub fn prefixes(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.prefixes
+            .iter()
+            .map(|(prefix, namespace)| (prefix.as_str(), namespace.as_str()))
+    }
+
+    /// Triples in the order they were added
+    pub fn triples(&self) -> impl Iterator<Item = TripleRef<'_>> {
+        self.triples.iter().map(Triple::as_ref)
+    }
+
+    /// Adds a triple, returning whether it was new
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the subject is a literal, or an IRI, blank node
+    /// label, or language tag isn't valid.
+    pub fn add(&mut self, subject: Term, predicate: &str, object: Term) -> Result<bool> {
+        let subject = match subject.to_rdf()? {
+            oxrdf::Term::NamedNode(node) => NamedOrBlankNode::from(node),
+            oxrdf::Term::BlankNode(node) => node.into(),
+            oxrdf::Term::Literal(literal) => {
+                anyhow::bail!("The literal {} can't be a subject", literal)
+            }
+        };
+        let triple = Triple::new(subject, named_node(predicate)?, object.to_rdf()?);
+        Ok(self.insert(triple))
+    }
+
+    /// Adds an oxrdf triple, returning whether it was new
+    pub fn insert(&mut self, triple: Triple) -> bool {
+        if !self.seen.insert(triple.clone()) {
+            return false;
+        }
+        self.triples.push(triple);
+        true
+    }
+
+    /// Number of distinct triples
+    pub fn len(&self) -> usize {
+        self.triples.len()
+    }
+
+    /// Whether the graph has no triples
+    pub fn is_empty(&self) -> bool {
+        self.triples.is_empty()
+    }
+
+    /// Serializes the graph as Turtle. `pretty` output declares the prefixes
+    /// and writes each subject once, in order of first appearance, with its
+    /// predicates and objects in `;` and `,` lists. A blank node that is the
+    /// object of exactly one triple is written inline there, as `[ ... ]`.
+    /// Otherwise each triple is written in full on its own line, as
+    /// N-Triples, which Turtle includes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the serializer fails.
+    pub fn to_turtle(&self, pretty: bool) -> Result<String> {
+        if !pretty {
+            return Ok(self
+                .to_ntriples()?
+                .into_iter()
+                .map(|line| line + "\n")
+                .collect());
+        }
+
+        let writer = TurtleWriter::new(self);
+        let mut turtle = String::new();
+        for (prefix, namespace) in &self.prefixes {
+            turtle.push_str(&format!("@prefix {}: <{}> .\n", prefix, namespace));
+        }
+        for subject in &writer.subjects {
+            let nested = matches!(subject, NamedOrBlankNodeRef::BlankNode(node)
+                if writer.nested.contains(node));
+            if nested {
+                continue;
+            }
+            turtle.push_str(&writer.term((*subject).into()));
+            turtle.push(' ');
+            writer.write_description(*subject, 1, &mut turtle);
+            turtle.push_str(" .\n");
+        }
+        Ok(turtle)
+    }
+
+    /// Serializes each triple as an N-Triples line, with full IRIs, so
+    /// output from separate runs can be merged line by line.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the serializer fails.
+    pub fn to_ntriples(&self) -> Result<Vec<String>> {
+        let mut writer = NTriplesSerializer::new().for_writer(Vec::new());
+        for triple in self.triples() {
+            writer.serialize_triple(triple)?;
+        }
+        let ntriples = String::from_utf8(writer.finish())?;
+        Ok(ntriples.lines().map(str::to_string).collect())
+    }
+
+    /// The triples reordered so each subject's come together, and within
+    /// them each predicate's, keeping the order of first appearance.
+    fn grouped_by_subject(&self) -> Vec<TripleRef<'_>> {
+        let mut subjects: HashMap<NamedOrBlankNodeRef, usize> = HashMap::new();
+        let mut predicates: HashMap<(NamedOrBlankNodeRef, NamedNodeRef), usize> = HashMap::new();
+        for (i, triple) in self.triples().enumerate() {
+            subjects.entry(triple.subject).or_insert(i);
+            predicates
+                .entry((triple.subject, triple.predicate))
+                .or_insert(i);
+        }
+        let mut triples: Vec<TripleRef> = self.triples().collect();
+        triples.sort_by_key(|triple| {
+            (
+                subjects[&triple.subject],
+                predicates[&(triple.subject, triple.predicate)],
+            )
+        });
+        triples
+    }
+}
+
+/// Writes a graph's triples as pretty Turtle, nesting blank nodes that have
+/// a single referrer.
+struct TurtleWriter<'a> {
+    /// Prefixes, longest namespace first so the most specific one is used
+    prefixes: Vec<(&'a str, &'a str)>,
+    /// Subjects in order of first appearance
+    subjects: Vec<NamedOrBlankNodeRef<'a>>,
+    /// Each subject's triples, grouped by predicate
+    descriptions: HashMap<NamedOrBlankNodeRef<'a>, Vec<TripleRef<'a>>>,
+    /// Blank nodes written inline where they're referenced
+    nested: HashSet<BlankNodeRef<'a>>,
+}
+
+impl<'a> TurtleWriter<'a> {
+    fn new(graph: &'a Graph) -> Self {
+        let mut prefixes: Vec<(&str, &str)> = graph.prefixes().collect();
+        prefixes.sort_by_key(|(_, namespace)| std::cmp::Reverse(namespace.len()));
+
+        let mut subjects = Vec::new();
+        let mut descriptions: HashMap<NamedOrBlankNodeRef, Vec<TripleRef>> = HashMap::new();
+        for triple in graph.grouped_by_subject() {
+            descriptions
+                .entry(triple.subject)
+                .or_insert_with(|| {
+                    subjects.push(triple.subject);
+                    Vec::new()
+                })
+                .push(triple);
+        }
+
+        // Blank nodes described in the graph and referenced exactly once
+        let mut referrers: HashMap<BlankNodeRef, Vec<NamedOrBlankNodeRef>> = HashMap::new();
+        for triple in graph.triples() {
+            if let TermRef::BlankNode(node) = triple.object {
+                referrers.entry(node).or_default().push(triple.subject);
+            }
+        }
+        let mut parents: HashMap<BlankNodeRef, NamedOrBlankNodeRef> = referrers
+            .into_iter()
+            .filter(|(node, referrers)| {
+                referrers.len() == 1 && descriptions.contains_key(&(*node).into())
+            })
+            .map(|(node, referrers)| (node, referrers[0]))
+            .collect();
+
+        // Nodes that refer to each other in a cycle can't all be nested;
+        // the first of each cycle to appear is written at the top level.
+        for subject in &subjects {
+            let NamedOrBlankNodeRef::BlankNode(start) = *subject else {
+                continue;
+            };
+            let mut visited = HashSet::new();
+            let mut node = start;
+            while let Some(NamedOrBlankNodeRef::BlankNode(parent)) = parents.get(&node).copied() {
+                if parent == start {
+                    parents.remove(&start);
+                    break;
+                }
+                if !visited.insert(parent) {
+                    break;
+                }
+                node = parent;
+            }
+        }
+
+        Self {
+            prefixes,
+            subjects,
+            descriptions,
+            nested: parents.into_keys().collect(),
+        }
+    }
+
+    /// Appends `subject`'s predicates and objects, with continuation lines
+    /// indented `depth` tabs.
+    fn write_description(&self, subject: NamedOrBlankNodeRef<'a>, depth: usize, out: &mut String) {
+        let mut previous: Option<NamedNodeRef> = None;
+        for triple in &self.descriptions[&subject] {
+            match previous {
+                Some(predicate) if predicate == triple.predicate => out.push_str(" , "),
+                Some(_) => {
+                    out.push_str(" ;\n");
+                    out.push_str(&"\t".repeat(depth));
+                    out.push_str(&self.predicate(triple.predicate));
+                    out.push(' ');
+                }
+                None => {
+                    out.push_str(&self.predicate(triple.predicate));
+                    out.push(' ');
+                }
+            }
+            previous = Some(triple.predicate);
+
+            match triple.object {
+                TermRef::BlankNode(node) if self.nested.contains(&node) => {
+                    out.push_str("[\n");
+                    out.push_str(&"\t".repeat(depth + 1));
+                    self.write_description(node.into(), depth + 1, out);
+                    out.push('\n');
+                    out.push_str(&"\t".repeat(depth));
+                    out.push(']');
+                }
+                object => out.push_str(&self.term(object)),
+            }
+        }
+    }
+
+    /// A predicate, with `rdf:type` as `a`
+    fn predicate(&self, predicate: NamedNodeRef) -> String {
+        if predicate.as_str() == RDF_TYPE {
+            return "a".to_string();
+        }
+        self.named_node(predicate)
+    }
+
+    /// An IRI as a prefixed name where one fits, otherwise in full
+    fn named_node(&self, node: NamedNodeRef) -> String {
+        for (prefix, namespace) in &self.prefixes {
+            let local = node.as_str().strip_prefix(namespace);
+            if matches!(local, Some(local) if LOCAL_NAME.is_match(local)) {
+                return format!("{}:{}", prefix, &node.as_str()[namespace.len()..]);
+            }
+        }
+        node.to_string()
+    }
+
+    /// A term, escaped by oxrdf, with a prefixed datatype where one fits
+    fn term(&self, term: TermRef) -> String {
+        match term {
+            TermRef::NamedNode(node) => self.named_node(node),
+            TermRef::Literal(literal)
+                if !matches!(literal.datatype(), xsd::STRING | rdf::LANG_STRING) =>
+            {
+                format!(
+                    "{}^^{}",
+                    LiteralRef::new_simple_literal(literal.value()),
+                    self.named_node(literal.datatype())
+                )
+            }
+            term => term.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EX: &str = "http://example.org/";
+
+    fn example_graph() -> Graph {
+        let mut graph = Graph::new();
+        graph.add_prefix("ex", EX).unwrap();
+        graph
+            .add_prefix("xsd", "http://www.w3.org/2001/XMLSchema#")
+            .unwrap();
+        let s = Term::iri(&format!("{}s", EX));
+        let knows = format!("{}knows", EX);
+        let triples = [
+            (RDF_TYPE, Term::iri(&format!("{}Thing", EX))),
+            (
+                RDFS_LABEL,
+                Term::lang_literal("Line one\nTab\there \"quoted\" \\ bell\u{7}", "en"),
+            ),
+            (&knows, Term::iri(&format!("{}a", EX))),
+            (
+                &format!("{}born", EX),
+                Term::typed_literal("1815-12-10T00:00:00Z", XSD_DATE_TIME),
+            ),
+            (&knows, Term::blank("friend")),
+        ];
+        for (predicate, object) in triples {
+            assert!(graph.add(s.clone(), predicate, object).unwrap());
+        }
+        graph
+    }
+
+    #[test]
+    fn test_turtle_escapes_and_groups() {
+        let graph = example_graph();
+        assert_eq!(graph.len(), 5);
+        let turtle = graph.to_turtle(true).unwrap();
+        assert!(
+            turtle.contains(
+                "ex:s a ex:Thing ;\n\t\
+             <http://www.w3.org/2000/01/rdf-schema#label> \
+             \"Line one\\nTab\\there \\\"quoted\\\" \\\\ bell\\u0007\"@en ;\n\t\
+             ex:knows ex:a , _:friend ;\n\t\
+             ex:born \"1815-12-10T00:00:00Z\"^^xsd:dateTime .\n"
+            ),
+            "{}",
+            turtle
+        );
+        assert_eq!(turtle.matches("ex:s ").count(), 1);
+
+        let flat = graph.to_turtle(false).unwrap();
+        assert_eq!(flat.lines().count(), 5);
+        assert!(flat.ends_with("<http://example.org/s> <http://example.org/knows> _:friend .\n"));
+    }
+
+    #[test]
+    fn test_singly_referenced_blank_nodes_are_nested() {
+        let mut graph = Graph::new();
+        graph.add_prefix("ex", EX).unwrap();
+        let ex = |name: &str| format!("{}{}", EX, name);
+        let s = Term::iri(&ex("s"));
+        let triples = [
+            (s.clone(), "address", Term::blank("address")),
+            (Term::blank("address"), "city", Term::literal("Paris")),
+            (Term::blank("address"), "geo", Term::blank("point")),
+            (Term::blank("point"), "lat", Term::literal("48.8")),
+            (s.clone(), "knows", Term::blank("shared")),
+            (Term::iri(&ex("t")), "knows", Term::blank("shared")),
+            (Term::blank("shared"), "name", Term::literal("Shared")),
+            (Term::blank("a"), "next", Term::blank("b")),
+            (Term::blank("b"), "next", Term::blank("a")),
+        ];
+        for (subject, predicate, object) in triples {
+            graph.add(subject, &ex(predicate), object).unwrap();
+        }
+
+        let turtle = graph.to_turtle(true).unwrap();
+        assert!(
+            turtle.contains(
+                "ex:s ex:address [\n\t\tex:city \"Paris\" ;\n\t\tex:geo [\n\t\t\t\
+                 ex:lat \"48.8\"\n\t\t]\n\t] ;\n\tex:knows _:shared .\n"
+            ),
+            "{}",
+            turtle
+        );
+        assert!(!turtle.contains("_:address") && !turtle.contains("_:point"));
+        // Referenced twice, so it keeps its label
+        assert!(turtle.contains("_:shared ex:name \"Shared\" .\n"));
+        // A cycle is broken at the first node to appear
+        assert!(turtle.contains("_:a ex:next [\n\t\tex:next _:a\n\t] .\n"));
+        assert_eq!(
+            crate::turtle::parse_turtle(&turtle).unwrap().len(),
+            graph.len()
+        );
+    }
+
+    #[test]
+    fn test_ntriples_use_full_iris() {
+        let mut graph = example_graph();
+        assert!(
+            !graph
+                .add(
+                    Term::iri(&format!("{}s", EX)),
+                    RDF_TYPE,
+                    Term::iri(&format!("{}Thing", EX))
+                )
+                .unwrap()
+        );
+        let lines = graph.to_ntriples().unwrap();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[0],
+            "<http://example.org/s> <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> \
+             <http://example.org/Thing> ."
+        );
+        assert!(
+            lines[3].ends_with(
+                "\"1815-12-10T00:00:00Z\"^^<http://www.w3.org/2001/XMLSchema#dateTime> ."
+            )
+        );
+    }
+
+    #[test]
+    fn test_invalid_terms_are_rejected() {
+        let mut graph = Graph::new();
+        let s = Term::iri("http://example.org/s");
+        let p = "http://example.org/p";
+        assert!(
+            graph
+                .add(s.clone(), p, Term::iri("http://example.org/a b"))
+                .is_err()
+        );
+        assert!(graph.add(s.clone(), "p", Term::literal("x")).is_err());
+        assert!(graph.add(s.clone(), p, Term::blank("not a label")).is_err());
+        assert!(graph.add(s, p, Term::lang_literal("x", "en_US")).is_err());
+        assert!(
+            graph
+                .add(Term::literal("x"), p, Term::literal("y"))
+                .is_err()
+        );
+        assert!(graph.add_prefix("ex", "not an iri").is_err());
+        assert!(graph.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_date_times_become_plain_literals() {
+        for valid in [
+            "2020-01-01T00:00:00Z",
+            "-0044-03-15T12:30:00",
+            "2020-02-29T23:59:59.5+02:00",
+            "1999-12-31T24:00:00Z",
+        ] {
+            assert!(is_date_time(valid), "{}", valid);
+        }
+        for invalid in [
+            "1990-00-00T00:00:00Z",
+            "2020-01-01",
+            "2020-01-01 00:00:00",
+            "2020-13-01T00:00:00Z",
+            "2020-01-01T24:00:01Z",
+            "unknown",
+        ] {
+            assert!(!is_date_time(invalid), "{}", invalid);
+            assert_eq!(
+                Term::typed_literal(invalid, XSD_DATE_TIME),
+                Term::literal(invalid)
+            );
+        }
+    }
+}
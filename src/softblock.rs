@@ -0,0 +1,230 @@
+use crate::download::Headers;
+use std::fmt;
+
+/// Trimmed bodies shorter than this are placeholders rather than pages
+const MIN_BODY_BYTES: usize = 64;
+
+/// A password field or login phrase on a page smaller than this is the
+/// page's purpose, not a login box or comment prompt beside the content
+const LOGIN_FORM_MAX_BYTES: usize = 16 * 1024;
+
+/// Phrases that only appear when the content is withheld until login
+const LOGIN_PHRASES: &[&str] = &[
+    "please log in",
+    "please login",
+    "please sign in",
+    "log in to continue",
+    "login to continue",
+    "sign in to continue",
+    "you must be logged in",
+    "you need to log in",
+    "you need to sign in",
+    "login required",
+    "authentication required",
+];
+
+/// Password inputs, however the attribute is quoted
+const PASSWORD_FIELDS: &[&str] = &["type=\"password\"", "type='password'", "type=password"];
+
+/// Title or heading text of an error page served with status 200
+const NOT_FOUND_PHRASES: &[&str] = &[
+    "404",
+    "not found",
+    "page does not exist",
+    "page doesn't exist",
+    "no longer available",
+];
+
+/// Path segments, ignoring any extension, of login pages that a meta refresh
+/// may point at
+const LOGIN_PATHS: &[&str] = &["login", "log-in", "signin", "sign-in", "sign_in", "auth"];
+
+/// Why a successful response looks like it doesn't hold the requested
+/// content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoftBlockKind {
+    /// The body is empty or only a few bytes
+    TinyBody,
+    /// An error page, e.g. titled "Page not found"
+    NotFound,
+    /// A login form or a demand to log in
+    LoginWall,
+    /// A `<meta http-equiv="refresh">` to a login page
+    LoginRedirect,
+}
+
+impl fmt::Display for SoftBlockKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            SoftBlockKind::TinyBody => "nearly empty page",
+            SoftBlockKind::NotFound => "\"not found\" page",
+            SoftBlockKind::LoginWall => "login wall",
+            SoftBlockKind::LoginRedirect => "redirect to a login page",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Guesses whether an HTML page answered with status 200 is really a
+/// "soft 404" or login wall, which would poison extraction with boilerplate.
+///
+/// Only HTML is checked (a response declaring another content type returns
+/// `None`). The checks, in order: a meta refresh to a login path, login
+/// phrases or a password field on a small page, "not found" in the title or
+/// first heading, and a body of only a few bytes. These are heuristics; a
+/// page about logging in can be flagged, and a login wall in an unexpected
+/// language missed.
+pub fn detect_soft_block(body: &[u8], headers: &Headers) -> Option<SoftBlockKind> {
+    let is_html = headers
+        .get("content-type")
+        .is_none_or(|content_type| content_type.to_ascii_lowercase().contains("html"));
+    if !is_html {
+        return None;
+    }
+
+    let page = String::from_utf8_lossy(body).to_lowercase();
+    let refreshes_to_login = meta_refresh_target(&page).is_some_and(is_login_path);
+    if refreshes_to_login {
+        return Some(SoftBlockKind::LoginRedirect);
+    }
+
+    let asks_for_login = PASSWORD_FIELDS.iter().any(|f| page.contains(f))
+        || LOGIN_PHRASES.iter().any(|phrase| page.contains(phrase));
+    if page.len() <= LOGIN_FORM_MAX_BYTES && asks_for_login {
+        return Some(SoftBlockKind::LoginWall);
+    }
+
+    let not_found = [element_text(&page, "title"), element_text(&page, "h1")]
+        .into_iter()
+        .flatten()
+        .any(|text| NOT_FOUND_PHRASES.iter().any(|phrase| text.contains(phrase)));
+    if not_found {
+        return Some(SoftBlockKind::NotFound);
+    }
+
+    if page.trim().len() < MIN_BODY_BYTES {
+        return Some(SoftBlockKind::TinyBody);
+    }
+    None
+}
+
+/// Returns the `url=` target of the first `<meta http-equiv="refresh">` in
+/// lowercased `page`.
+fn meta_refresh_target(page: &str) -> Option<&str> {
+    page.match_indices("<meta")
+        .filter_map(|(start, _)| {
+            let tag = &page[start..];
+            tag.find('>').map(|end| &tag[..end])
+        })
+        .filter(|tag| tag.contains("http-equiv") && tag.contains("refresh"))
+        .find_map(|tag| {
+            let url = &tag[tag.find("url=")? + "url=".len()..];
+            let url = url.trim_start_matches(['\'', '"']);
+            Some(url.split(['\'', '"']).next().unwrap_or(url))
+        })
+}
+
+/// Whether a URL or path has a segment naming a login page, such as
+/// `/login`, `/auth/`, or `/Account/SignIn.aspx`. The query and fragment are
+/// ignored, so `?next=/login` doesn't count.
+fn is_login_path(target: &str) -> bool {
+    let path = target.split(['?', '#']).next().unwrap_or(target);
+    path.split('/')
+        .map(|segment| segment.split('.').next().unwrap_or(segment))
+        .any(|segment| LOGIN_PATHS.contains(&segment))
+}
+
+/// Returns the text inside the first `<name>` element of lowercased `page`.
+fn element_text<'a>(page: &'a str, name: &str) -> Option<&'a str> {
+    let open = page.find(&format!("<{}", name))?;
+    let content_start = open + page[open..].find('>')? + 1;
+    let content = &page[content_start..];
+    let end = content.find(&format!("</{}", name))?;
+    Some(content[..end].trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn html_headers() -> Headers {
+        let mut headers = Headers::new();
+        headers.append("Content-Type", "text/html; charset=utf-8");
+        headers
+    }
+
+    fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/data")
+                .join(name),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_detect_login_wall_fixture() {
+        assert_eq!(
+            detect_soft_block(&fixture("login_wall.html"), &html_headers()),
+            Some(SoftBlockKind::LoginWall)
+        );
+    }
+
+    #[test]
+    fn test_normal_page_is_not_blocked() {
+        let page = fixture("recon_village_defcon33.html");
+        assert_eq!(detect_soft_block(&page, &html_headers()), None);
+        assert_eq!(detect_soft_block(&page, &Headers::new()), None);
+    }
+
+    #[test]
+    fn test_comment_prompt_on_article_is_not_a_login_wall() {
+        let mut page =
+            String::from("<html><head><title>Recon at scale</title></head><body><article>");
+        while page.len() <= LOGIN_FORM_MAX_BYTES {
+            page.push_str("<p>Passive DNS, certificate logs, and archived pages all help.</p>");
+        }
+        page.push_str("</article><footer>Please sign in to comment.</footer></body></html>");
+        assert_eq!(detect_soft_block(page.as_bytes(), &html_headers()), None);
+    }
+
+    #[test]
+    fn test_detect_other_soft_blocks() {
+        let headers = html_headers();
+        assert_eq!(
+            detect_soft_block(b"  <html></html>\n", &headers),
+            Some(SoftBlockKind::TinyBody)
+        );
+
+        let not_found = "<html><head><title>Oops! Page Not Found | Example</title></head>\
+                         <body><p>Try the search box or head back to the home page.</p></body></html>";
+        assert_eq!(
+            detect_soft_block(not_found.as_bytes(), &headers),
+            Some(SoftBlockKind::NotFound)
+        );
+
+        let refresh = "<html><head><meta http-equiv=\"Refresh\" content=\"0; URL='/Account/SignIn?next=/team'\">\
+                       </head><body>Redirecting to the team page...</body></html>";
+        assert_eq!(
+            detect_soft_block(refresh.as_bytes(), &headers),
+            Some(SoftBlockKind::LoginRedirect)
+        );
+
+        // Login words elsewhere in the URL, or after the path, aren't a login page
+        let author = "<html><head><meta http-equiv=\"refresh\" content=\"0; url=/authors/jane?from=login\">\
+                      </head><body>Moved to the author page.</body></html>";
+        assert_eq!(detect_soft_block(author.as_bytes(), &headers), None);
+        let auth =
+            "<meta http-equiv=\"refresh\" content=\"0; url=https://example.com/auth/start\">";
+        assert_eq!(
+            detect_soft_block(auth.as_bytes(), &headers),
+            Some(SoftBlockKind::LoginRedirect)
+        );
+
+        // A short reply that isn't HTML, e.g. an API's `{}`, is left alone
+        let mut json = Headers::new();
+        json.append("Content-Type", "application/json");
+        assert_eq!(detect_soft_block(b"{}", &json), None);
+    }
+}
@@ -0,0 +1,313 @@
+use anyhow::{Context, Result};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+
+/// A single RDF term in a SPARQL JSON results binding
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SparqlValue {
+    /// Term type: `uri`, `literal`, or `bnode`
+    #[serde(rename = "type")]
+    pub value_type: String,
+
+    /// Lexical value of the term
+    pub value: String,
+
+    /// Language tag for language-tagged literals
+    #[serde(rename = "xml:lang", skip_serializing_if = "Option::is_none", default)]
+    pub lang: Option<String>,
+
+    /// Datatype IRI for typed literals
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub datatype: Option<String>,
+}
+
+/// One row of a SELECT result, keyed by variable name
+pub type SparqlBinding = HashMap<String, SparqlValue>;
+
+/// Parses a complete SPARQL JSON results document into its bindings.
+///
+/// Use `binding_stream` instead for result sets too large to hold in memory.
+///
+/// # Errors
+///
+/// Returns an error if `body` isn't a SPARQL JSON SELECT result.
+pub fn parse_bindings(body: &[u8]) -> Result<Vec<SparqlBinding>> {
+    #[derive(Deserialize)]
+    struct Document {
+        results: Results,
+    }
+
+    #[derive(Deserialize)]
+    struct Results {
+        bindings: Vec<SparqlBinding>,
+    }
+
+    let document: Document =
+        serde_json::from_slice(body).context("Failed to parse SPARQL JSON results")?;
+    Ok(document.results.bindings)
+}
+
+/// Streams the bindings of a SPARQL JSON results document one at a time.
+///
+/// Only the binding currently being parsed is held in memory, so this works for
+/// SELECT results far larger than would be practical to deserialize at once.
+/// The reader can be anything that yields the response body, such as a file or
+/// a Tor stream positioned after the HTTP headers.
+pub fn binding_stream<R>(reader: R) -> impl Stream<Item = Result<SparqlBinding>>
+where
+    R: AsyncRead + Unpin,
+{
+    let parser = BindingParser {
+        reader: BufReader::new(reader),
+        in_bindings: false,
+    };
+    futures::stream::try_unfold(parser, |mut parser| async move {
+        let binding = parser.next_binding().await?;
+        Ok(binding.map(|binding| (binding, parser)))
+    })
+}
+
+/// Incremental scanner over a SPARQL JSON results document
+struct BindingParser<R> {
+    reader: BufReader<R>,
+    in_bindings: bool,
+}
+
+impl<R: AsyncRead + Unpin> BindingParser<R> {
+    /// Returns the next binding, or `None` once the bindings array is exhausted.
+    async fn next_binding(&mut self) -> Result<Option<SparqlBinding>> {
+        if !self.in_bindings {
+            self.seek_bindings().await?;
+            self.in_bindings = true;
+        }
+
+        loop {
+            match self.next_byte().await? {
+                None => anyhow::bail!("Unexpected end of SPARQL results inside bindings"),
+                Some(b']') => return Ok(None),
+                Some(b',') => continue,
+                Some(b) if b.is_ascii_whitespace() => continue,
+                Some(b'{') => {
+                    let object = self.read_nested(b'{').await?;
+                    let binding = serde_json::from_slice(&object)
+                        .context("Failed to parse SPARQL result binding")?;
+                    return Ok(Some(binding));
+                }
+                Some(b) => anyhow::bail!(
+                    "Unexpected character '{}' in SPARQL bindings array",
+                    b as char
+                ),
+            }
+        }
+    }
+
+    /// Advances the reader to just past the `[` opening the `results.bindings`
+    /// array, skipping other members such as `head` on the way.
+    async fn seek_bindings(&mut self) -> Result<()> {
+        self.expect(b'{').await?;
+        self.seek_member("results").await?;
+        self.expect(b'{').await?;
+        self.seek_member("bindings").await?;
+        self.expect(b'[')
+            .await
+            .context("SPARQL results 'bindings' is not an array")
+    }
+
+    /// Reads members of an object whose `{` has been consumed up to the one
+    /// called `name`, leaving the reader just before its value.
+    async fn seek_member(&mut self, name: &str) -> Result<()> {
+        loop {
+            match self.next_non_whitespace().await? {
+                Some(b'"') => {}
+                Some(b',') => continue,
+                Some(b'}') | None => anyhow::bail!("SPARQL results contain no '{}' member", name),
+                Some(b) => anyhow::bail!(
+                    "Unexpected character '{}' in SPARQL results object",
+                    b as char
+                ),
+            }
+            let key = self.read_string().await?;
+            self.expect(b':').await?;
+            if key == name {
+                return Ok(());
+            }
+            self.skip_value().await?;
+        }
+    }
+
+    /// Skips over the next JSON value.
+    async fn skip_value(&mut self) -> Result<()> {
+        match self.next_non_whitespace().await? {
+            None => anyhow::bail!("Unexpected end of SPARQL results"),
+            Some(b'"') => {
+                self.read_string().await?;
+            }
+            Some(open @ (b'{' | b'[')) => {
+                self.read_nested(open).await?;
+            }
+            // A number, `true`, `false` or `null` runs up to the next delimiter
+            Some(_) => loop {
+                match self.reader.fill_buf().await?.first() {
+                    Some(b',' | b'}' | b']') | None => break,
+                    Some(b) if b.is_ascii_whitespace() => break,
+                    Some(_) => self.reader.consume(1),
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Consumes the next non-whitespace byte, failing unless it's `expected`.
+    async fn expect(&mut self, expected: u8) -> Result<()> {
+        match self.next_non_whitespace().await? {
+            Some(b) if b == expected => Ok(()),
+            Some(b) => anyhow::bail!(
+                "Expected '{}' in SPARQL results, found '{}'",
+                expected as char,
+                b as char
+            ),
+            None => anyhow::bail!(
+                "Expected '{}' in SPARQL results, found the end",
+                expected as char
+            ),
+        }
+    }
+
+    /// Reads a JSON object or array whose opening `open` bracket has already
+    /// been consumed.
+    async fn read_nested(&mut self, open: u8) -> Result<Vec<u8>> {
+        let mut value = vec![open];
+        let mut depth = 1;
+        let mut in_string = false;
+        let mut escaped = false;
+
+        while depth > 0 {
+            let b = self
+                .next_byte()
+                .await?
+                .context("Unexpected end of SPARQL results inside an object or array")?;
+            value.push(b);
+
+            if in_string {
+                match b {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => {}
+                }
+            } else {
+                match b {
+                    b'"' => in_string = true,
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => depth -= 1,
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// Reads the remainder of a JSON string whose opening quote has been consumed.
+    async fn read_string(&mut self) -> Result<String> {
+        let mut raw = Vec::new();
+        let mut escaped = false;
+        loop {
+            let b = self
+                .next_byte()
+                .await?
+                .context("Unexpected end of SPARQL results inside a string")?;
+            if escaped {
+                escaped = false;
+            } else if b == b'\\' {
+                escaped = true;
+            } else if b == b'"' {
+                break;
+            }
+            raw.push(b);
+        }
+        Ok(String::from_utf8_lossy(&raw).into_owned())
+    }
+
+    async fn next_non_whitespace(&mut self) -> Result<Option<u8>> {
+        loop {
+            match self.next_byte().await? {
+                Some(b) if b.is_ascii_whitespace() => continue,
+                other => return Ok(other),
+            }
+        }
+    }
+
+    async fn next_byte(&mut self) -> Result<Option<u8>> {
+        if self.reader.fill_buf().await?.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(self.reader.read_u8().await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_binding_stream_yields_rows_one_at_a_time() {
+        let fixture = include_bytes!("../tests/data/sparql_select_results.json");
+        let stream = binding_stream(&fixture[..]);
+        futures::pin_mut!(stream);
+
+        let first = stream.next().await.unwrap().unwrap();
+        assert_eq!(
+            first["company"].value,
+            "http://www.wikidata.org/entity/Q123"
+        );
+        assert_eq!(first["companyName"].lang.as_deref(), Some("en"));
+        assert_eq!(
+            first["inception"].datatype.as_deref(),
+            Some("http://www.w3.org/2001/XMLSchema#dateTime")
+        );
+
+        let second = stream.next().await.unwrap().unwrap();
+        assert_eq!(second["companyName"].value, "Brace {and} \"Quote\" Ltd");
+        assert!(!second.contains_key("inception"));
+
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_binding_stream_only_reads_results_bindings() {
+        // `bindings` elsewhere in the document must not be mistaken for rows
+        let document = br#"{
+            "head": {"vars": ["bindings"], "link": [], "bindings": []},
+            "boolean": null,
+            "results": {"distinct": false, "ordered": true, "note": {"bindings": [1]},
+                "bindings": [{"bindings": {"type": "literal", "value": "row"}}]}
+        }"#;
+        let rows: Vec<_> = binding_stream(&document[..]).collect().await;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].as_ref().unwrap()["bindings"].value, "row");
+
+        let ask = br#"{"head": {}, "boolean": true}"#;
+        let err = binding_stream(&ask[..])
+            .collect::<Vec<_>>()
+            .await
+            .remove(0)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "SPARQL results contain no 'results' member"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_binding_stream_rejects_truncated_document() {
+        let truncated = br#"{"results": {"bindings": [{"a": {"type": "uri", "value": "x"}}, {"#;
+        let results: Vec<_> = binding_stream(&truncated[..]).collect().await;
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+    }
+}
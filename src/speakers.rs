@@ -0,0 +1,418 @@
+use crate::openai_client::repair_json;
+use crate::rdf::{Graph, RDF_TYPE, RDFS_LABEL, Term};
+use anyhow::{Context, Result};
+use oxrdf::{NamedNodeRef, NamedOrBlankNodeRef, TermRef, TripleRef};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+/// A conference speaker extracted from a schedule or speaker page
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Speaker {
+    /// Full name as listed
+    pub name: String,
+
+    /// Company or organization the speaker represents
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub affiliation: Option<String>,
+
+    /// Job title or role
+    #[serde(default, alias = "role", skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+/// Parses the speakers in an extraction response.
+///
+/// Accepts a JSON array of names (the `extract_speakers.yaml` format), an
+/// array of speaker objects, or an object with a `speakers` array, optionally
+/// wrapped in a markdown code fence or surrounded by other text. Output cut
+/// off mid-document is repaired first. Names are trimmed, blank entries are
+/// skipped, and repeated names keep their first entry.
+///
+/// # Errors
+///
+/// Returns an error if the response holds no JSON, or an entry is neither a
+/// string nor an object with a `name`.
+pub fn parse_speakers(response: &str) -> Result<Vec<Speaker>> {
+    let start = response
+        .find(['[', '{'])
+        .context("No JSON found in speaker extraction response")?;
+    let json = response[start..].trim_end();
+    let json = json.strip_suffix("```").unwrap_or(json).trim_end();
+
+    // Trailing prose after the document is ignored
+    let mut values = serde_json::Deserializer::from_str(json).into_iter::<Value>();
+    let value = match values.next() {
+        Some(Ok(value)) => value,
+        _ => serde_json::from_str(&repair_json(json))
+            .context("Failed to parse speaker extraction response")?,
+    };
+
+    let entries = match value {
+        Value::Array(entries) => entries,
+        Value::Object(mut object) => match object.remove("speakers") {
+            Some(Value::Array(entries)) => entries,
+            _ => anyhow::bail!("Expected a \"speakers\" array in the extraction response"),
+        },
+        _ => anyhow::bail!("Expected a JSON array of speakers"),
+    };
+
+    let mut seen = HashSet::new();
+    let mut speakers = Vec::new();
+    for entry in entries {
+        let mut speaker = match entry {
+            Value::String(name) => Speaker {
+                name,
+                affiliation: None,
+                title: None,
+            },
+            entry => serde_json::from_value(entry).context("Invalid speaker entry")?,
+        };
+        speaker.name = speaker.name.trim().to_string();
+        speaker.affiliation = non_blank(speaker.affiliation);
+        speaker.title = non_blank(speaker.title);
+        if !speaker.name.is_empty() && seen.insert(speaker.name.clone()) {
+            speakers.push(speaker);
+        }
+    }
+    Ok(speakers)
+}
+
+const FOAF: &str = "http://xmlns.com/foaf/0.1/";
+const ORG: &str = "http://www.w3.org/ns/org#";
+const OWL: &str = "http://www.w3.org/2002/07/owl#";
+
+/// Speakers sharing an affiliation, as `speakers_by_company` reports them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompanySpeakers {
+    /// The affiliation's `foaf:name`
+    pub company: String,
+
+    /// The Wikidata entity the affiliation is linked to, if any
+    pub wikidata: Option<String>,
+
+    /// Names of the speakers, in the order they appear in the graph
+    pub speakers: Vec<String>,
+}
+
+/// Trims `value`, treating blank strings as missing.
+fn non_blank(value: Option<String>) -> Option<String> {
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Serializes speakers as FOAF in Turtle, in the shape the queries under
+/// `analysis/queries` expect.
+///
+/// Each speaker is a `foaf:Person` blank node with a `foaf:name`. A speaker
+/// with an affiliation or title also holds an `org:Membership` carrying the
+/// title as `org:role`, and is `org:memberOf` their affiliation. Each
+/// distinct affiliation becomes one `foaf:Organization`.
+///
+/// # Errors
+///
+/// Returns an error if the graph can't be serialized.
+pub fn speakers_to_foaf(speakers: &[Speaker]) -> Result<String> {
+    let foaf = |name: &str| format!("{}{}", FOAF, name);
+    let org = |name: &str| format!("{}{}", ORG, name);
+
+    let mut graph = Graph::new();
+    graph.add_prefix("foaf", FOAF)?;
+    graph.add_prefix("org", ORG)?;
+
+    // Affiliations in order of first appearance; the index names the node
+    let mut organizations: Vec<&str> = Vec::new();
+    for (i, speaker) in speakers.iter().enumerate() {
+        let n = i + 1;
+        let person = Term::Blank(format!("speaker{}", n));
+        let organization = speaker.affiliation.as_deref().map(|affiliation| {
+            let k = match organizations.iter().position(|name| *name == affiliation) {
+                Some(k) => k,
+                None => {
+                    organizations.push(affiliation);
+                    organizations.len() - 1
+                }
+            };
+            Term::Blank(format!("org{}", k + 1))
+        });
+
+        graph.add(person.clone(), RDF_TYPE, Term::iri(&foaf("Person")))?;
+        graph.add(person.clone(), &foaf("name"), Term::literal(&speaker.name))?;
+        if let Some(organization) = &organization {
+            graph.add(person.clone(), &org("memberOf"), organization.clone())?;
+        }
+        if organization.is_none() && speaker.title.is_none() {
+            continue;
+        }
+
+        let membership = Term::Blank(format!("membership{}", n));
+        graph.add(person.clone(), &org("holds"), membership.clone())?;
+        graph.add(membership.clone(), RDF_TYPE, Term::iri(&org("Membership")))?;
+        graph.add(membership.clone(), &org("member"), person)?;
+        if let Some(organization) = organization {
+            graph.add(membership.clone(), &org("organization"), organization)?;
+        }
+        if let Some(title) = &speaker.title {
+            graph.add(membership, &org("role"), Term::literal(title))?;
+        }
+    }
+
+    for (k, name) in organizations.iter().enumerate() {
+        let organization = Term::Blank(format!("org{}", k + 1));
+        graph.add(
+            organization.clone(),
+            RDF_TYPE,
+            Term::iri(&foaf("Organization")),
+        )?;
+        graph.add(organization, &foaf("name"), Term::literal(name))?;
+    }
+    graph.to_turtle(true)
+}
+
+/// Links the organizations in a `speakers_to_foaf` graph to Wikidata
+/// companies, such as those `WikidataDownloader` writes.
+///
+/// Returns a copy of `foaf` with an `owl:sameAs` from each organization
+/// whose `foaf:name` matches a company's `rdfs:label`, ignoring case, and
+/// the number of organizations linked.
+///
+/// # Errors
+///
+/// Returns an error if the `owl` prefix can't be declared.
+pub fn link_affiliations(foaf: &Graph, companies: &Graph) -> Result<(Graph, usize)> {
+    let mut labels: HashMap<String, NamedOrBlankNodeRef> = HashMap::new();
+    for triple in companies.triples() {
+        if let (RDFS_LABEL, TermRef::Literal(label)) = (triple.predicate.as_str(), triple.object) {
+            labels
+                .entry(label.value().to_lowercase())
+                .or_insert(triple.subject);
+        }
+    }
+
+    let organization = format!("{}Organization", FOAF);
+    let organizations: HashSet<NamedOrBlankNodeRef> = foaf
+        .triples()
+        .filter(|triple| {
+            matches!(triple.object, TermRef::NamedNode(class)
+                if triple.predicate.as_str() == RDF_TYPE && class.as_str() == organization)
+        })
+        .map(|triple| triple.subject)
+        .collect();
+
+    let mut linked = foaf.clone();
+    linked.add_prefix("owl", OWL)?;
+    let (foaf_name, same_as) = (format!("{}name", FOAF), format!("{}sameAs", OWL));
+    let same_as = NamedNodeRef::new_unchecked(&same_as);
+    let mut links = 0;
+    for triple in foaf.triples() {
+        if !organizations.contains(&triple.subject) || triple.predicate.as_str() != foaf_name {
+            continue;
+        }
+        let TermRef::Literal(name) = triple.object else {
+            continue;
+        };
+        let Some(company) = labels.get(&name.value().to_lowercase()) else {
+            continue;
+        };
+        if linked.insert(TripleRef::new(triple.subject, same_as, *company).into_owned()) {
+            links += 1;
+        }
+    }
+    Ok((linked, links))
+}
+
+/// Groups the speakers in a `speakers_to_foaf` graph, optionally linked by
+/// `link_affiliations`, by affiliation: most speakers first, then by name,
+/// like `analysis/queries/speakers_by_company.rq`. Speakers without an
+/// affiliation are left out.
+pub fn speakers_by_company(graph: &Graph) -> Vec<CompanySpeakers> {
+    let (foaf_name, member_of) = (format!("{}name", FOAF), format!("{}memberOf", ORG));
+    let same_as = format!("{}sameAs", OWL);
+    let mut names: HashMap<TermRef, &str> = HashMap::new();
+    let mut wikidata: HashMap<TermRef, &str> = HashMap::new();
+    for triple in graph.triples() {
+        let predicate = triple.predicate.as_str();
+        match triple.object {
+            TermRef::Literal(name) if predicate == foaf_name => {
+                names.insert(triple.subject.into(), name.value());
+            }
+            TermRef::NamedNode(iri) if predicate == same_as => {
+                wikidata.insert(triple.subject.into(), iri.as_str());
+            }
+            _ => {}
+        }
+    }
+
+    // Affiliations in order of first appearance
+    let mut companies: Vec<(TermRef, Vec<String>)> = Vec::new();
+    for triple in graph.triples() {
+        if triple.predicate.as_str() != member_of {
+            continue;
+        }
+        let Some(name) = names.get(&triple.subject.into()) else {
+            continue;
+        };
+        let organization = triple.object;
+        match companies.iter_mut().find(|(org, _)| *org == organization) {
+            Some((_, speakers)) => speakers.push(name.to_string()),
+            None => companies.push((organization, vec![name.to_string()])),
+        }
+    }
+
+    let mut companies: Vec<CompanySpeakers> = companies
+        .into_iter()
+        .filter_map(|(organization, speakers)| {
+            Some(CompanySpeakers {
+                company: names.get(&organization)?.to_string(),
+                wikidata: wikidata.get(&organization).map(|iri| iri.to_string()),
+                speakers,
+            })
+        })
+        .collect();
+    companies.sort_by(|a, b| {
+        b.speakers
+            .len()
+            .cmp(&a.speakers.len())
+            .then_with(|| a.company.cmp(&b.company))
+    });
+    companies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_speaker_names() {
+        let speakers = parse_speakers(r#"["Jane Doe", " John Roe ", "", "Jane Doe"]"#).unwrap();
+        let names: Vec<&str> = speakers.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["Jane Doe", "John Roe"]);
+        assert_eq!(speakers[0].affiliation, None);
+    }
+
+    #[test]
+    fn test_parse_speaker_objects() {
+        let response = "Here are the speakers:\n```json\n{\"speakers\": [\n  \
+            {\"name\": \"Jane Doe\", \"affiliation\": \"Acme\", \"title\": \"CTO\"},\n  \
+            {\"name\": \"John Roe\", \"affiliation\": \" \", \"role\": \"Lurker\"}\n]}\n```\nLet me know!";
+        let speakers = parse_speakers(response).unwrap();
+        assert_eq!(
+            speakers,
+            [
+                Speaker {
+                    name: "Jane Doe".to_string(),
+                    affiliation: Some("Acme".to_string()),
+                    title: Some("CTO".to_string()),
+                },
+                Speaker {
+                    name: "John Roe".to_string(),
+                    affiliation: None,
+                    title: Some("Lurker".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_truncated_and_invalid_responses() {
+        let speakers = parse_speakers(r#"["Jane Doe", "John Roe", "Ali"#).unwrap();
+        assert_eq!(speakers.len(), 3);
+
+        assert!(parse_speakers("No speakers found.").is_err());
+        assert!(parse_speakers(r#"{"names": []}"#).is_err());
+        assert!(parse_speakers(r#"[{"affiliation": "Acme"}]"#).is_err());
+    }
+
+    #[test]
+    fn test_speakers_to_foaf() {
+        let speakers = [
+            Speaker {
+                name: "Jane \"JD\" Doe\n".to_string(),
+                affiliation: Some("Acme".to_string()),
+                title: Some("CTO".to_string()),
+            },
+            Speaker {
+                name: "John Roe".to_string(),
+                affiliation: None,
+                title: None,
+            },
+            Speaker {
+                name: "Ali Smith".to_string(),
+                affiliation: Some("Acme".to_string()),
+                title: None,
+            },
+        ];
+        let rdf = speakers_to_foaf(&speakers).unwrap();
+        assert!(rdf.starts_with(
+            "@prefix foaf: <http://xmlns.com/foaf/0.1/> .\n@prefix org: <http://www.w3.org/ns/org#> .\n"
+        ));
+        assert!(rdf.contains(
+            "_:speaker1 a foaf:Person ;\n\tfoaf:name \"Jane \\\"JD\\\" Doe\\n\" ;\n\t\
+             org:memberOf _:org1 ;\n\torg:holds [\n\t\ta org:Membership ;\n\t\t\
+             org:member _:speaker1 ;\n\t\torg:organization _:org1 ;\n\t\t\
+             org:role \"CTO\"\n\t] .\n"
+        ));
+        assert!(!rdf.contains("_:membership1"));
+        assert!(rdf.contains("_:speaker2 a foaf:Person ;\n\tfoaf:name \"John Roe\" .\n"));
+        assert!(!rdf.contains("_:membership2"));
+        assert!(rdf.contains(
+            "_:speaker3 a foaf:Person ;\n\tfoaf:name \"Ali Smith\" ;\n\t\
+             org:memberOf _:org1 ;"
+        ));
+        assert!(rdf.contains("_:org1 a foaf:Organization ;\n\tfoaf:name \"Acme\" .\n"));
+        assert!(!rdf.contains("_:org2"));
+    }
+
+    #[test]
+    fn test_link_and_group_affiliations() {
+        let speaker = |name: &str, affiliation: Option<&str>| Speaker {
+            name: name.to_string(),
+            affiliation: affiliation.map(str::to_string),
+            title: None,
+        };
+        let foaf = crate::turtle::parse_turtle(
+            &speakers_to_foaf(&[
+                speaker("John Roe", Some("Globex")),
+                speaker("Jane Doe", Some("Acme")),
+                speaker("Lee Fox", None),
+                speaker("Ali Smith", Some("Acme")),
+            ])
+            .unwrap(),
+        )
+        .unwrap();
+        let companies = crate::turtle::parse_turtle(
+            "@prefix wd: <http://www.wikidata.org/entity/> .\n\
+             @prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .\n\
+             wd:Q1 rdfs:label \"ACME\"@en .\n\
+             wd:Q2 rdfs:label \"Initech\"@en .\n",
+        )
+        .unwrap();
+
+        let (linked, links) = link_affiliations(&foaf, &companies).unwrap();
+        assert_eq!(links, 1);
+        assert_eq!(linked.len(), foaf.len() + 1);
+        assert!(
+            linked
+                .to_turtle(true)
+                .unwrap()
+                .contains("owl:sameAs <http://www.wikidata.org/entity/Q1>")
+        );
+
+        assert_eq!(
+            speakers_by_company(&linked),
+            [
+                CompanySpeakers {
+                    company: "Acme".to_string(),
+                    wikidata: Some("http://www.wikidata.org/entity/Q1".to_string()),
+                    speakers: vec!["Jane Doe".to_string(), "Ali Smith".to_string()],
+                },
+                CompanySpeakers {
+                    company: "Globex".to_string(),
+                    wikidata: None,
+                    speakers: vec!["John Roe".to_string()],
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,369 @@
+use crate::batch::{BatchMode, RetryBudget, run_batch};
+use crate::download::HttpResponseMeta;
+use crate::normalize::UrlNormalizer;
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::HashSet;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use tracing::{info, warn};
+
+/// Matches the `href` of an `<a>` tag, double-, single-, or un-quoted
+static ANCHOR_HREF: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?is)<a\s[^>]*?\bhref\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#)
+        .expect("valid anchor pattern")
+});
+
+/// Returns the `http` and `https` links of the `<a href>` tags in `html`,
+/// resolved against `base`, without fragments, in document order and without
+/// duplicates.
+pub fn extract_links(html: &str, base: &url::Url) -> Vec<url::Url> {
+    let mut seen = HashSet::new();
+    ANCHOR_HREF
+        .captures_iter(html)
+        .filter_map(|captures| {
+            let href = captures
+                .get(1)
+                .or_else(|| captures.get(2))
+                .or_else(|| captures.get(3))?;
+            let mut link = base
+                .join(&href.as_str().trim().replace("&amp;", "&"))
+                .ok()?;
+            link.set_fragment(None);
+            matches!(link.scheme(), "http" | "https").then_some(link)
+        })
+        .filter(|link| seen.insert(link.to_string()))
+        .collect()
+}
+
+/// Returns where the page at `url` is saved under `root`: a directory per
+/// host (with any non-default port) and path segment, with `index.html` for
+/// paths ending in `/` and any query appended to the file name.
+pub fn mirror_path(root: &Path, url: &url::Url) -> PathBuf {
+    let mut host = url.host_str().unwrap_or("unknown-host").to_string();
+    if let Some(port) = url.port() {
+        host.push_str(&format!("_{}", port));
+    }
+    let mut path = root.join(host);
+
+    let mut segments: Vec<&str> = url
+        .path_segments()
+        .map(|segments| segments.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+    let file = if url.path().ends_with('/') {
+        "index.html"
+    } else {
+        segments.pop().unwrap_or("index.html")
+    };
+    for segment in segments {
+        path.push(segment);
+    }
+
+    match url.query() {
+        Some(query) => path.join(format!("{}?{}", file, query.replace('/', "%2F"))),
+        None => path.join(file),
+    }
+}
+
+/// A bounded breadth-first crawl that follows links from a start page and
+/// mirrors each page it fetches to disk.
+///
+/// Pages are fetched a depth level at a time as a batch, so the batch mode
+/// and retry budget apply as they do for `TorDownloader::download_many`.
+/// URLs are normalized into a visited set before fetching, so link cycles
+/// and duplicate spellings of a page are fetched once.
+#[derive(Debug, Clone)]
+pub struct Spider {
+    max_depth: u32,
+    same_host: bool,
+    batch_mode: BatchMode,
+    retry_budget: Option<RetryBudget>,
+    normalizer: UrlNormalizer,
+}
+
+impl Spider {
+    /// Creates a spider that follows links up to `max_depth` hops from the
+    /// start page, which is depth 0.
+    pub fn new(max_depth: u32) -> Self {
+        Self {
+            max_depth,
+            same_host: false,
+            batch_mode: BatchMode::default(),
+            retry_budget: None,
+            normalizer: UrlNormalizer::new(),
+        }
+    }
+
+    /// Only follow links to the start page's host (and port)
+    pub fn set_same_host(&mut self, same_host: bool) {
+        self.same_host = same_host;
+    }
+
+    /// Set how a failing page affects the rest of the crawl (default: keep
+    /// crawling and report it)
+    pub fn set_batch_mode(&mut self, mode: BatchMode) {
+        self.batch_mode = mode;
+    }
+
+    /// Share `budget` across the crawl; see `run_batch`
+    pub fn set_retry_budget(&mut self, budget: RetryBudget) {
+        self.retry_budget = Some(budget);
+    }
+
+    /// Crawls from `start`, fetching each page with `fetch` and saving it to
+    /// its `mirror_path` under `root`. Links are only extracted from HTML
+    /// pages, and resolved against the URL the page was served from.
+    ///
+    /// Returns each page's saved path or error, in crawl order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `start` isn't a valid URL, or if a page fails in
+    /// `FailFast` mode (see `run_batch`).
+    pub async fn crawl<F, Fut>(
+        &self,
+        start: &str,
+        root: &Path,
+        mut fetch: F,
+    ) -> Result<Vec<Result<PathBuf>>>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = Result<(Vec<u8>, HttpResponseMeta)>>,
+    {
+        let start = url::Url::parse(start).context("Failed to parse URL")?;
+        let mut visited = HashSet::from([self.normalizer.normalize(start.as_str())]);
+        let mut frontier = vec![start.clone()];
+        let mut results = Vec::new();
+
+        for depth in 0..=self.max_depth {
+            if frontier.is_empty() {
+                break;
+            }
+            info!("Crawling {} page(s) at depth {}", frontier.len(), depth);
+            let pages = run_batch(
+                &frontier,
+                self.batch_mode,
+                self.retry_budget.as_ref(),
+                |url| {
+                    let page = fetch(url.to_string());
+                    async move {
+                        let (body, meta) = page
+                            .await
+                            .with_context(|| format!("Failed to download {}", url))?;
+                        let final_url =
+                            url::Url::parse(&meta.final_url).unwrap_or_else(|_| url.clone());
+                        let path = mirror_path(root, &final_url);
+                        save_page(&path, &body).await?;
+                        Ok((final_url, body, meta, path))
+                    }
+                },
+            )
+            .await?;
+
+            let mut next = Vec::new();
+            for page in pages {
+                let (final_url, body, meta, path) = match page {
+                    Ok(page) => page,
+                    Err(e) => {
+                        warn!("{:#}", e);
+                        results.push(Err(e));
+                        continue;
+                    }
+                };
+                visited.insert(self.normalizer.normalize(final_url.as_str()));
+                if depth < self.max_depth && is_html(&meta) {
+                    for link in extract_links(&String::from_utf8_lossy(&body), &final_url) {
+                        if self.same_host && !same_origin_host(&link, &start) {
+                            continue;
+                        }
+                        if visited.insert(self.normalizer.normalize(link.as_str())) {
+                            next.push(link);
+                        }
+                    }
+                }
+                results.push(Ok(path));
+            }
+            frontier = next;
+        }
+        Ok(results)
+    }
+}
+
+/// Returns whether `a` and `b` have the same host and port.
+fn same_origin_host(a: &url::Url, b: &url::Url) -> bool {
+    a.host_str() == b.host_str() && a.port_or_known_default() == b.port_or_known_default()
+}
+
+/// Returns whether the response is HTML, assuming so when the server didn't
+/// send a `Content-Type`.
+fn is_html(meta: &HttpResponseMeta) -> bool {
+    meta.header("Content-Type")
+        .is_none_or(|content_type| content_type.to_ascii_lowercase().contains("html"))
+}
+
+/// Writes `body` to `path`, creating its parent directories.
+async fn save_page(path: &Path, body: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+    tokio::fs::write(path, body)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    info!("Saved {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::Headers;
+
+    /// Serves `tests/data/site` as `https://site.example/`, recording each
+    /// requested URL.
+    async fn fixture_fetch(
+        url: String,
+        requested: &std::cell::RefCell<Vec<String>>,
+    ) -> Result<(Vec<u8>, HttpResponseMeta)> {
+        requested.borrow_mut().push(url.clone());
+        let parsed = url::Url::parse(&url)?;
+        if parsed.host_str() != Some("site.example") {
+            anyhow::bail!("HTTP error: 404");
+        }
+        let mut file = parsed.path().trim_start_matches('/').to_string();
+        if file.is_empty() || file.ends_with('/') {
+            file.push_str("index.html");
+        }
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data/site")
+            .join(file);
+        let body = std::fs::read(&path).context("HTTP error: 404")?;
+        let meta = HttpResponseMeta {
+            status: 200,
+            final_url: url,
+            headers: [("Content-Type".to_string(), "text/html".to_string())]
+                .into_iter()
+                .collect(),
+            trailers: Headers::new(),
+            soft_block: None,
+        };
+        Ok((body, meta))
+    }
+
+    #[test]
+    fn test_extract_links() {
+        let base = url::Url::parse("https://site.example/docs/").unwrap();
+        let html = r#"<p><a href="a.html">A</a> <A class=x HREF='/b?x=1&amp;y=2#top'>B</A>
+            <a href=../c.html>C</a> <a href="a.html#again">A</a>
+            <a href="mailto:me@site.example">mail</a> <link href="style.css">
+            <a href="https://other.example/">other</a></p>"#;
+        let links: Vec<String> = extract_links(html, &base)
+            .iter()
+            .map(url::Url::to_string)
+            .collect();
+        assert_eq!(
+            links,
+            [
+                "https://site.example/docs/a.html",
+                "https://site.example/b?x=1&y=2",
+                "https://site.example/c.html",
+                "https://other.example/",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mirror_path() {
+        let root = Path::new("out");
+        let path = |url: &str| mirror_path(root, &url::Url::parse(url).unwrap());
+        assert_eq!(
+            path("https://site.example/"),
+            Path::new("out/site.example/index.html")
+        );
+        assert_eq!(
+            path("https://site.example/docs/"),
+            Path::new("out/site.example/docs/index.html")
+        );
+        assert_eq!(
+            path("http://site.example:8080/docs/a.html"),
+            Path::new("out/site.example_8080/docs/a.html")
+        );
+        assert_eq!(
+            path("https://site.example/search?q=a/b"),
+            Path::new("out/site.example/search?q=a%2Fb")
+        );
+        assert_eq!(
+            path("https://site.example/../../etc/passwd"),
+            Path::new("out/site.example/etc/passwd")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_crawl_fixture_site() {
+        let dir = tempfile::tempdir().unwrap();
+        let requested = std::cell::RefCell::new(Vec::new());
+        let mut spider = Spider::new(2);
+        spider.set_same_host(true);
+
+        let results = spider
+            .crawl("https://site.example/", dir.path(), |url| {
+                fixture_fetch(url, &requested)
+            })
+            .await
+            .unwrap();
+
+        // Depth 0 and 1 pages, then the depth 2 page; the external link, the
+        // cycle back to the index, and the depth 3 page aren't fetched
+        assert_eq!(
+            requested.into_inner(),
+            [
+                "https://site.example/",
+                "https://site.example/about.html",
+                "https://site.example/docs/",
+                "https://site.example/docs/deep.html",
+            ]
+        );
+        let saved: Vec<PathBuf> = results.into_iter().map(Result::unwrap).collect();
+        let site = dir.path().join("site.example");
+        assert_eq!(
+            saved,
+            [
+                site.join("index.html"),
+                site.join("about.html"),
+                site.join("docs/index.html"),
+                site.join("docs/deep.html"),
+            ]
+        );
+        let deep = std::fs::read_to_string(site.join("docs/deep.html")).unwrap();
+        assert!(deep.contains("deeper.html"));
+        assert!(!site.join("docs/deeper.html").exists());
+    }
+
+    #[tokio::test]
+    async fn test_crawl_reports_broken_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let requested = std::cell::RefCell::new(Vec::new());
+
+        // Without --same-host the external link is followed, and fails
+        let results = Spider::new(1)
+            .crawl("https://site.example/", dir.path(), |url| {
+                fixture_fetch(url, &requested)
+            })
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 4);
+        let failed: Vec<String> = results
+            .iter()
+            .filter_map(|r| r.as_ref().err())
+            .map(|e| format!("{:#}", e))
+            .collect();
+        assert_eq!(failed.len(), 1);
+        assert!(
+            failed[0].starts_with("Failed to download https://other.example/"),
+            "{}",
+            failed[0]
+        );
+    }
+}
@@ -0,0 +1,304 @@
+use crate::rdf::Graph;
+use anyhow::Context;
+use oxttl::{TurtleParser, TurtleSyntaxError};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A Turtle syntax error and the line it was found on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TurtleError {
+    /// 1-based line number
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TurtleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for TurtleError {}
+
+impl From<TurtleSyntaxError> for TurtleError {
+    fn from(error: TurtleSyntaxError) -> Self {
+        TurtleError {
+            line: error.location().start.line as usize + 1,
+            message: error.message().to_string(),
+        }
+    }
+}
+
+/// Parses a Turtle document, or N-Triples since they are a subset, into a
+/// graph that keeps its prefix declarations, using oxttl.
+///
+/// Relative IRIs are resolved against `@base` per RFC 3986, and each
+/// anonymous `[ ... ]` blank node and collection item gets a fresh label
+/// that can't clash with the labels in the document.
+///
+/// # Errors
+///
+/// Returns the first syntax error, with its line number.
+pub fn parse_turtle(input: &str) -> Result<Graph, TurtleError> {
+    let mut parser = TurtleParser::new().for_slice(input);
+    let mut graph = Graph::new();
+    for triple in parser.by_ref() {
+        graph.insert(triple?);
+    }
+    for (prefix, namespace) in parser.prefixes() {
+        graph
+            .add_prefix(prefix, namespace)
+            .expect("oxttl resolves prefixes to absolute IRIs");
+    }
+    Ok(graph)
+}
+
+/// Splits the Turtle or N-Triples file `input` into Turtle shards of at
+/// most `max_triples_per_file` triples each, for tools with file-size
+/// limits. Shards are written to `{out_prefix}-001.ttl`, `-002.ttl`, and so
+/// on, in the input's triple order, and each repeats all of its prefix
+/// declarations. Returns the shard paths; an empty input writes none.
+///
+/// Blank node labels are kept, but a loader scopes them to each file, so a
+/// blank node whose triples land in different shards becomes separate
+/// nodes.
+///
+/// # Errors
+///
+/// Returns an error if `max_triples_per_file` is 0, or if the input can't
+/// be read or parsed or a shard can't be written.
+pub fn split_rdf(
+    input: &Path,
+    max_triples_per_file: usize,
+    out_prefix: &Path,
+) -> anyhow::Result<Vec<PathBuf>> {
+    if max_triples_per_file == 0 {
+        anyhow::bail!("The triple limit per file must be at least 1");
+    }
+    let content = std::fs::read_to_string(input)
+        .with_context(|| format!("Failed to read {}", input.display()))?;
+    let graph =
+        parse_turtle(&content).with_context(|| format!("Failed to parse {}", input.display()))?;
+    if let Some(parent) = out_prefix.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+
+    let triples: Vec<_> = graph.triples().collect();
+    let mut paths = Vec::new();
+    for (i, chunk) in triples.chunks(max_triples_per_file).enumerate() {
+        let mut shard = Graph::new();
+        for (prefix, namespace) in graph.prefixes() {
+            shard.add_prefix(prefix, namespace)?;
+        }
+        for triple in chunk {
+            shard.insert(triple.into_owned());
+        }
+
+        let mut name = out_prefix.as_os_str().to_owned();
+        name.push(format!("-{:03}.ttl", i + 1));
+        let path = PathBuf::from(name);
+        std::fs::write(&path, shard.to_turtle(true)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdf::{RDF_TYPE, Term};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_parse_round_trips_writer_output() {
+        let mut graph = Graph::new();
+        graph.add_prefix("ex", "http://example.org/").unwrap();
+        let s = Term::iri("http://example.org/s");
+        let triples = [
+            (RDF_TYPE, Term::iri("http://example.org/Thing")),
+            (
+                "http://example.org/label",
+                Term::lang_literal("Line one\n\"two\" \\ \u{7}", "en"),
+            ),
+            ("http://example.org/knows", Term::blank("friend")),
+            (
+                "http://example.org/knows",
+                Term::iri("http://example.org/a%20b"),
+            ),
+        ];
+        for (predicate, object) in triples {
+            graph.add(s.clone(), predicate, object).unwrap();
+        }
+
+        let ntriples = graph.to_ntriples().unwrap();
+        for pretty in [true, false] {
+            let parsed = parse_turtle(&graph.to_turtle(pretty).unwrap()).unwrap();
+            assert_eq!(parsed.to_ntriples().unwrap(), ntriples);
+        }
+        let parsed = parse_turtle(&graph.to_turtle(true).unwrap()).unwrap();
+        assert!(parsed.prefixes().eq(graph.prefixes()));
+        assert_eq!(
+            parse_turtle(&ntriples.join("\n"))
+                .unwrap()
+                .to_ntriples()
+                .unwrap(),
+            ntriples
+        );
+    }
+
+    #[test]
+    fn test_parse_turtle_shorthand() {
+        let turtle = r#"
+            PREFIX ex: <http://example.org/>
+            @base <http://example.org/> .
+            # a comment
+            <s> ex:p 1, -2.5, 3e2, true ;
+                ex:q '''multi
+line''' , "typed"^^ex:T ;
+                ex:r [ ex:name "anon" ] ;
+            .
+            _:genid1 ex:name "labelled" .
+        "#;
+        let graph = parse_turtle(turtle).unwrap();
+        let lines = graph.to_ntriples().unwrap();
+        assert_eq!(lines.len(), 9);
+        assert_eq!(
+            lines[0],
+            "<http://example.org/s> <http://example.org/p> \
+             \"1\"^^<http://www.w3.org/2001/XMLSchema#integer> ."
+        );
+        assert!(lines[4].contains("\"multi\\nline\""));
+        assert!(lines[5].ends_with("\"typed\"^^<http://example.org/T> ."));
+        assert!(lines.contains(&"_:genid1 <http://example.org/name> \"labelled\" .".to_string()));
+
+        // The anonymous node must stay distinct from `_:genid1`
+        let named: HashSet<_> = graph
+            .triples()
+            .filter(|triple| triple.predicate.as_str() == "http://example.org/name")
+            .map(|triple| triple.subject)
+            .collect();
+        assert_eq!(named.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_resolves_relative_iris_against_base() {
+        let graph = parse_turtle(
+            "@base <http://example.org/a/b/c> .\n\
+             <../x> <#frag> <?q> .\n\
+             <//other.example/y> <> </root> .\n",
+        )
+        .unwrap();
+        assert_eq!(
+            graph.to_ntriples().unwrap(),
+            [
+                "<http://example.org/a/x> <http://example.org/a/b/c#frag> \
+                 <http://example.org/a/b/c?q> .",
+                "<http://other.example/y> <http://example.org/a/b/c> \
+                 <http://example.org/root> ."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_collections_and_exact_booleans() {
+        let graph = parse_turtle(
+            "@prefix ex: <http://example.org/> .\n\
+             ex:s ex:list (1 ex:a) ; ex:empty () ; ex:flag false .\n",
+        )
+        .unwrap();
+        let lines = graph.to_ntriples().unwrap();
+        assert_eq!(lines.len(), 7, "{:#?}", lines);
+        let rdf = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+        assert!(lines.iter().any(|l| l.ends_with(&format!(
+            "<{}first> \"1\"^^<http://www.w3.org/2001/XMLSchema#integer> .",
+            rdf
+        ))));
+        assert!(lines.contains(&format!(
+            "<http://example.org/s> <http://example.org/empty> <{}nil> .",
+            rdf
+        )));
+        assert!(
+            lines
+                .iter()
+                .any(|l| l.ends_with(&format!("<{}rest> <{}nil> .", rdf, rdf)))
+        );
+        assert!(
+            lines
+                .iter()
+                .any(|l| l.ends_with("\"false\"^^<http://www.w3.org/2001/XMLSchema#boolean> ."))
+        );
+
+        for uppercase in ["TRUE", "FALSE", "True"] {
+            let turtle = format!(
+                "<http://example.org/s> <http://example.org/p> {} .",
+                uppercase
+            );
+            assert!(parse_turtle(&turtle).is_err(), "{}", uppercase);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors_report_line() {
+        let err =
+            parse_turtle("@prefix ex: <http://example.org/> .\n\nex:s ex:p ex:o\nex:t ex:p ex:o .")
+                .unwrap_err();
+        assert_eq!(err.line, 4);
+        assert_eq!(
+            err.to_string(),
+            "line 4: A dot is expected at the end of statements"
+        );
+
+        let err = parse_turtle("<http://example.org/s> foo:p \"x\" .").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 1: The prefix foo: has not been declared"
+        );
+
+        let err = parse_turtle("<http://example.org/s> <http://example.org/p>\n\"open\n\" .")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 2: Line jumps are not allowed in string literals, use \\n"
+        );
+    }
+
+    #[test]
+    fn test_split_rdf_into_shards() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut graph = Graph::new();
+        graph.add_prefix("ex", "http://example.org/").unwrap();
+        for i in 0..5 {
+            graph
+                .add(
+                    Term::iri(&format!("http://example.org/s{}", i)),
+                    "http://example.org/p",
+                    Term::literal(&i.to_string()),
+                )
+                .unwrap();
+        }
+        let input = dir.path().join("graph.ttl");
+        std::fs::write(&input, graph.to_turtle(true).unwrap()).unwrap();
+
+        let prefix = dir.path().join("shards/graph");
+        let paths = split_rdf(&input, 3, &prefix).unwrap();
+        assert_eq!(
+            paths,
+            [
+                dir.path().join("shards/graph-001.ttl"),
+                dir.path().join("shards/graph-002.ttl")
+            ]
+        );
+
+        let mut union = Vec::new();
+        for (path, expected) in paths.iter().zip([3, 2]) {
+            let shard = parse_turtle(&std::fs::read_to_string(path).unwrap()).unwrap();
+            assert_eq!(shard.len(), expected);
+            assert!(shard.prefixes().eq(graph.prefixes()));
+            union.extend(shard.to_ntriples().unwrap());
+        }
+        assert_eq!(union, graph.to_ntriples().unwrap());
+        assert!(split_rdf(&input, 0, &prefix).is_err());
+    }
+}
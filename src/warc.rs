@@ -0,0 +1,180 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+
+/// Appends a WARC 1.1 `response` record for `response` (the raw HTTP response as
+/// received, headers included) to the WARC file at `path`, creating it if
+/// needed.
+///
+/// When `request` is given, a `request` record holding the raw HTTP request
+/// is written first and linked to the response with `WARC-Concurrent-To`.
+///
+/// # Errors
+///
+/// Returns an error if the file can't be opened or written.
+pub async fn append_exchange(
+    path: &Path,
+    target_uri: &str,
+    request: Option<&[u8]>,
+    response: &[u8],
+) -> Result<()> {
+    let date = warc_date(SystemTime::now());
+    let response_id = record_id();
+
+    let mut records = Vec::new();
+    if let Some(request) = request {
+        let concurrent_to = format!("WARC-Concurrent-To: {}\r\n", response_id);
+        write_record(
+            &mut records,
+            "request",
+            &record_id(),
+            &date,
+            target_uri,
+            &concurrent_to,
+            request,
+        );
+    }
+    write_record(
+        &mut records,
+        "response",
+        &response_id,
+        &date,
+        target_uri,
+        "",
+        response,
+    );
+
+    // One write per exchange keeps records from interleaving in append mode
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("Failed to open WARC file: {}", path.display()))?;
+    file.write_all(&records)
+        .await
+        .context("Failed to write WARC record")?;
+    // tokio writes in the background; flush so the record is on disk on return
+    file.flush().await.context("Failed to write WARC record")?;
+    Ok(())
+}
+
+/// Serializes one record into `out`. `extra_headers` are complete header
+/// lines, each ending in CRLF.
+fn write_record(
+    out: &mut Vec<u8>,
+    record_type: &str,
+    record_id: &str,
+    date: &str,
+    target_uri: &str,
+    extra_headers: &str,
+    block: &[u8],
+) {
+    let header = format!(
+        "WARC/1.1\r\n\
+         WARC-Type: {}\r\n\
+         WARC-Record-ID: {}\r\n\
+         WARC-Date: {}\r\n\
+         WARC-Target-URI: {}\r\n\
+         {}\
+         Content-Type: application/http;msgtype={}\r\n\
+         Content-Length: {}\r\n\
+         \r\n",
+        record_type,
+        record_id,
+        date,
+        target_uri,
+        extra_headers,
+        record_type,
+        block.len()
+    );
+    out.extend_from_slice(header.as_bytes());
+    out.extend_from_slice(block);
+    out.extend_from_slice(b"\r\n\r\n");
+}
+
+fn record_id() -> String {
+    format!("<urn:uuid:{}>", uuid::Uuid::new_v4())
+}
+
+/// Formats `time` as the UTC ISO 8601 timestamp WARC expects, e.g.
+/// `2025-10-21T07:28:00Z`.
+fn warc_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Civil-from-days conversion (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_warc_date() {
+        assert_eq!(warc_date(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        let time = UNIX_EPOCH + Duration::from_secs(1_761_031_680);
+        assert_eq!(warc_date(time), "2025-10-21T07:28:00Z");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(warc_date(leap_day), "2000-02-29T00:00:00Z");
+    }
+
+    #[tokio::test]
+    async fn test_append_exchange_writes_response_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crawl.warc");
+        let request = b"GET /a.csv HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+
+        append_exchange(&path, "https://example.com/a.csv", None, response)
+            .await
+            .unwrap();
+        let warc = std::fs::read(&path).unwrap();
+        let text = String::from_utf8_lossy(&warc);
+        assert!(
+            text.starts_with("WARC/1.1\r\nWARC-Type: response\r\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains("\r\nWARC-Target-URI: https://example.com/a.csv\r\n"));
+        assert!(text.contains("\r\nContent-Type: application/http;msgtype=response\r\n"));
+        assert!(text.contains(&format!("\r\nContent-Length: {}\r\n", response.len())));
+        let block_start = text.find("\r\n\r\n").unwrap() + 4;
+        assert_eq!(&warc[block_start..], [&response[..], b"\r\n\r\n"].concat());
+
+        // A second exchange with its request is appended after the first
+        append_exchange(&path, "https://example.com/a.csv", Some(request), response)
+            .await
+            .unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text.matches("WARC/1.1\r\n").count(), 3);
+        assert_eq!(text.matches("WARC-Type: request\r\n").count(), 1);
+        let response_id = text
+            .rsplit("WARC-Type: response\r\nWARC-Record-ID: ")
+            .next()
+            .and_then(|rest| rest.split("\r\n").next())
+            .unwrap();
+        assert!(text.contains(&format!("WARC-Concurrent-To: {}\r\n", response_id)));
+    }
+}
@@ -0,0 +1,1576 @@
+use crate::download::{HttpMethod, TorDownloader};
+use crate::rdf::{Graph, RDF_TYPE, RDFS_LABEL, Term, is_language_tag};
+use crate::sparql::{self, SparqlBinding, SparqlValue};
+use crate::turtle::parse_turtle;
+use anyhow::{Context, Result};
+use futures::Stream;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Wikidata SPARQL endpoint
+const SPARQL_ENDPOINT: &str = "https://query.wikidata.org/sparql";
+
+/// Classes queried by default: public company, business, and nonprofit
+/// organization
+pub const DEFAULT_ENTITY_TYPES: &[&str] = &["Q891723", "Q4830453", "Q163740"];
+
+/// Industries queried by default: computer security, cybersecurity, computer
+/// network, cloud computing, cryptocurrency, information technology, and
+/// agriculture
+pub const DEFAULT_INDUSTRIES: &[&str] = &[
+    "Q3510521",
+    "Q21157865",
+    "Q880371",
+    "Q638608",
+    "Q484847",
+    "Q97466080",
+    "Q11451",
+];
+
+/// Prefixes declared in Turtle output, and the only ones mapping terms may
+/// use
+const PREFIXES: &[(&str, &str)] = &[
+    ("wd", "http://www.wikidata.org/entity/"),
+    ("wdt", "http://www.wikidata.org/prop/direct/"),
+    ("rdfs", "http://www.w3.org/2000/01/rdf-schema#"),
+    ("rdf", "http://www.w3.org/1999/02/22-rdf-syntax-ns#"),
+    ("xsd", "http://www.w3.org/2001/XMLSchema#"),
+];
+
+/// A (subject, predicate IRI, object) statement
+type Triple = (Term, String, Term);
+
+/// One result row as terms, keyed by column
+type Row<'a> = HashMap<&'a str, Term>;
+
+/// How a mapped column's values become objects
+#[derive(Debug, Clone)]
+enum ColumnKind {
+    /// Entity IRIs, labelled from another column
+    Entity { label_column: Option<String> },
+    /// Literals written with a language tag or datatype suffix
+    Literal { suffix: String },
+}
+
+#[derive(Debug, Clone)]
+struct ColumnMapping {
+    column: String,
+    predicate: String,
+    kind: ColumnKind,
+}
+
+/// Maps the columns of a SPARQL SELECT result, as CSV or JSON bindings, to
+/// RDF triples.
+///
+/// Each row describes the entity in the subject column, and every other
+/// mapped column with a value adds one triple about it, so rows that repeat
+/// an entity (e.g. once per company it owns) merge into one description.
+/// Columns missing from the result are skipped.
+///
+/// Predicates and classes are Turtle terms: `a`, an `<IRI>`, or a prefixed
+/// name using `wd`, `wdt`, `rdfs`, `rdf`, or `xsd`.
+#[derive(Debug, Clone)]
+pub struct CsvMapping {
+    subject_column: String,
+    types: Vec<String>,
+    columns: Vec<ColumnMapping>,
+}
+
+impl CsvMapping {
+    /// Creates a mapping whose subjects are the entities in `subject_column`
+    pub fn new(subject_column: &str) -> Self {
+        Self {
+            subject_column: subject_column.to_string(),
+            types: Vec::new(),
+            columns: Vec::new(),
+        }
+    }
+
+    /// The layout of `WikidataDownloader::get_main_query` results, typing
+    /// each company with `DEFAULT_ENTITY_TYPES`
+    pub fn companies() -> Self {
+        let types: Vec<String> = DEFAULT_ENTITY_TYPES.iter().map(|s| s.to_string()).collect();
+        Self::companies_of(&types)
+    }
+
+    /// The company layout, typing each company with the item IDs in `types`
+    fn companies_of(types: &[String]) -> Self {
+        let mut mapping = Self::new("company");
+        mapping.types = types.iter().map(|id| format!("wd:{}", id)).collect();
+        mapping.add_literal("companyName", "rdfs:label", "@en");
+        mapping.add_entity("industry", "wdt:P452", None);
+        mapping.add_literal("inception", "wdt:P571", "^^xsd:dateTime");
+        // Ownership relationships: P1830 owner of, P127 owned by
+        mapping.add_entity("owns", "wdt:P1830", Some("ownsName"));
+        mapping.add_entity("ownedBy", "wdt:P127", Some("ownedByName"));
+        mapping
+    }
+
+    /// Type every subject with these classes
+    pub fn set_types(&mut self, types: &[&str]) {
+        self.types = types.iter().map(|t| t.to_string()).collect();
+    }
+
+    /// Map `column` to entity objects of `predicate`. Values are IRIs, or
+    /// Wikidata item IDs. When `label_column` has a value, the entity also
+    /// gets it as an English `rdfs:label`.
+    pub fn add_entity(&mut self, column: &str, predicate: &str, label_column: Option<&str>) {
+        self.columns.push(ColumnMapping {
+            column: column.to_string(),
+            predicate: predicate.to_string(),
+            kind: ColumnKind::Entity {
+                label_column: label_column.map(String::from),
+            },
+        });
+    }
+
+    /// Map `column` to literal objects of `predicate`, written with `suffix`:
+    /// a language tag like `@en`, a datatype like `^^xsd:dateTime`, or `""`
+    pub fn add_literal(&mut self, column: &str, predicate: &str, suffix: &str) {
+        self.columns.push(ColumnMapping {
+            column: column.to_string(),
+            predicate: predicate.to_string(),
+            kind: ColumnKind::Literal {
+                suffix: suffix.to_string(),
+            },
+        });
+    }
+
+    /// Maps each row of `csv` to triples, grouped by subject (see
+    /// `add_row`). Values are typed by the mapping: entity columns hold IRIs
+    /// or item IDs, literal columns take their suffix, and labels are
+    /// English.
+    fn map(&self, csv: &[u8]) -> Result<BTreeMap<String, Vec<Triple>>> {
+        let mut reader = csv::Reader::from_reader(csv);
+        let headers = reader
+            .headers()
+            .context("Failed to read CSV header")?
+            .clone();
+        let index = |column: &str| headers.iter().position(|h| h == column);
+        if index(&self.subject_column).is_none() {
+            anyhow::bail!("CSV has no '{}' column", self.subject_column);
+        }
+
+        // Expand every term up front, so a bad mapping fails before any row
+        let resolved = self.resolve()?;
+        let mut suffixes = Vec::new();
+        for column in &self.columns {
+            suffixes.push(match &column.kind {
+                ColumnKind::Literal { suffix } => parse_suffix(suffix)?,
+                ColumnKind::Entity { .. } => (None, None),
+            });
+        }
+
+        let mut subjects = BTreeMap::new();
+        for result in reader.records() {
+            let record = result.context("Failed to parse CSV")?;
+            let cell = |column: &str| {
+                index(column)
+                    .and_then(|i| record.get(i))
+                    .map(str::trim)
+                    .filter(|v| !v.is_empty())
+            };
+
+            let mut row = Row::new();
+            if let Some(subject) = cell(&self.subject_column) {
+                row.insert(&self.subject_column, Term::Iri(entity_iri(subject)));
+            }
+            for (column, (lang, datatype)) in self.columns.iter().zip(&suffixes) {
+                let Some(value) = cell(&column.column) else {
+                    continue;
+                };
+                let term = match &column.kind {
+                    ColumnKind::Entity { label_column } => {
+                        let label_column = label_column.as_deref();
+                        if let Some((label_column, label)) =
+                            label_column.and_then(|c| Some((c, cell(c)?)))
+                        {
+                            row.insert(label_column, Term::lang_literal(label, "en"));
+                        }
+                        Term::Iri(entity_iri(value))
+                    }
+                    ColumnKind::Literal { .. } => match (lang, datatype) {
+                        (Some(lang), _) => Term::lang_literal(value, lang),
+                        (None, Some(datatype)) => Term::typed_literal(value, datatype),
+                        (None, None) => Term::literal(value),
+                    },
+                };
+                row.insert(&column.column, term);
+            }
+            self.add_row(&row, &resolved, &mut subjects)?;
+        }
+        Ok(subjects)
+    }
+
+    /// Maps SPARQL JSON result rows to triples, grouped by subject (see
+    /// `add_row`). Each value keeps the type the endpoint reported, and
+    /// literal suffixes in the mapping are ignored.
+    fn map_bindings(&self, bindings: &[SparqlBinding]) -> Result<BTreeMap<String, Vec<Triple>>> {
+        let resolved = self.resolve()?;
+        let mut subjects = BTreeMap::new();
+        for binding in bindings {
+            let mut row = Row::new();
+            for (variable, value) in binding {
+                row.insert(variable, binding_term(value)?);
+            }
+            self.add_row(&row, &resolved, &mut subjects)?;
+        }
+        Ok(subjects)
+    }
+
+    /// Expands the classes and each column's predicate to IRIs.
+    fn resolve(&self) -> Result<(Vec<String>, Vec<String>)> {
+        let types = self
+            .types
+            .iter()
+            .map(|class| expand_term(class))
+            .collect::<Result<_>>()?;
+        let predicates = self
+            .columns
+            .iter()
+            .map(|column| expand_term(&column.predicate))
+            .collect::<Result<_>>()?;
+        Ok((types, predicates))
+    }
+
+    /// Adds the triples for one row to its subject's group in `subjects`,
+    /// keyed so groups sort by subject IRI. A group holds its subject's
+    /// triples followed by labels for the entities it refers to. Rows
+    /// without a subject are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the subject is a literal.
+    fn add_row(
+        &self,
+        row: &Row,
+        (types, predicates): &(Vec<String>, Vec<String>),
+        subjects: &mut BTreeMap<String, Vec<Triple>>,
+    ) -> Result<()> {
+        let key = match row.get(self.subject_column.as_str()) {
+            None => return Ok(()),
+            Some(Term::Iri(iri)) => iri.clone(),
+            Some(Term::Blank(label)) => format!("_:{}", label),
+            Some(Term::Literal { value, .. }) => anyhow::bail!(
+                "Subject column '{}' holds the literal \"{}\"",
+                self.subject_column,
+                value
+            ),
+        };
+        let subject = &row[self.subject_column.as_str()];
+
+        let triples = subjects.entry(key).or_default();
+        for class in types {
+            triples.push((subject.clone(), RDF_TYPE.to_string(), Term::iri(class)));
+        }
+        let mut labels = Vec::new();
+        for (column, predicate) in self.columns.iter().zip(predicates) {
+            let Some(object) = row.get(column.column.as_str()) else {
+                continue;
+            };
+            if let (
+                ColumnKind::Entity {
+                    label_column: Some(label_column),
+                },
+                Term::Iri(iri),
+            ) = (&column.kind, object)
+            {
+                let label = row.get(label_column.as_str()).filter(|label| match label {
+                    Term::Literal { value, .. } => entity_iri(value) != *iri,
+                    _ => false,
+                });
+                if let Some(label) = label {
+                    labels.push((object.clone(), label.clone()));
+                }
+            }
+            triples.push((subject.clone(), predicate.clone(), object.clone()));
+        }
+        for (entity, label) in labels {
+            triples.push((entity, RDFS_LABEL.to_string(), label));
+        }
+        Ok(())
+    }
+
+    /// Maps `csv` to a graph, in subject order
+    fn graph(&self, csv: &[u8]) -> Result<Graph> {
+        to_graph(self.map(csv)?)
+    }
+}
+
+/// The result of `WikidataDownloader::download_construct`
+#[derive(Debug, Clone)]
+pub enum ConstructOutput {
+    /// The response parsed as Turtle
+    Graph(Graph),
+    /// The unparsed response, returned in lenient mode when it isn't valid
+    /// Turtle
+    Raw(Vec<u8>),
+}
+
+/// Downloads entities of chosen Wikidata classes and industries through Tor,
+/// and converts the results to RDF.
+///
+/// By default it queries the security and technology companies described by
+/// `DEFAULT_ENTITY_TYPES` and `DEFAULT_INDUSTRIES`.
+pub struct WikidataDownloader {
+    downloader: TorDownloader,
+    endpoint: String,
+    data_dir: PathBuf,
+    pretty: bool,
+    lenient: bool,
+    entity_types: Vec<String>,
+    industries: Vec<String>,
+}
+
+impl WikidataDownloader {
+    /// Creates a downloader that writes its results to `data_dir`, creating
+    /// it if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `data_dir` can't be created or Tor can't start.
+    pub async fn new(data_dir: PathBuf) -> Result<Self> {
+        let downloader = TorDownloader::new()
+            .await
+            .context("Failed to initialize Tor downloader")?;
+        Self::with_downloader(downloader, data_dir)
+    }
+
+    /// Creates a downloader that sends its queries through `downloader`, e.g.
+    /// one configured with a profile, and writes its results to `data_dir`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `data_dir` can't be created.
+    pub fn with_downloader(downloader: TorDownloader, data_dir: PathBuf) -> Result<Self> {
+        // Ensure data directory exists
+        fs::create_dir_all(&data_dir)?;
+
+        Ok(Self {
+            downloader,
+            endpoint: SPARQL_ENDPOINT.to_string(),
+            data_dir,
+            pretty: true,
+            lenient: false,
+            entity_types: DEFAULT_ENTITY_TYPES.iter().map(|s| s.to_string()).collect(),
+            industries: DEFAULT_INDUSTRIES.iter().map(|s| s.to_string()).collect(),
+        })
+    }
+
+    /// Send queries to this SPARQL endpoint instead of Wikidata's, e.g. a
+    /// mirror. Defaults to `https://query.wikidata.org/sparql`.
+    pub fn set_endpoint(&mut self, endpoint: &str) {
+        self.endpoint = endpoint.to_string();
+    }
+
+    /// Write grouped, human-readable Turtle (the default), or one statement
+    /// per line when `false`
+    pub fn set_pretty(&mut self, pretty: bool) {
+        self.pretty = pretty;
+    }
+
+    /// When `true`, `download_construct` returns a response it can't parse
+    /// as raw bytes with a warning instead of failing. Defaults to `false`.
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    /// Query instances of these classes (or their subclasses), given as
+    /// Wikidata item IDs such as `Q5`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `types` is empty or an ID isn't a `Q` number.
+    pub fn set_entity_types(&mut self, types: &[&str]) -> Result<()> {
+        if types.is_empty() {
+            anyhow::bail!("At least one entity type is required");
+        }
+        self.entity_types = parse_item_ids(types)?;
+        Ok(())
+    }
+
+    /// Only query entities in one of these industries (P452), given as
+    /// Wikidata item IDs. An empty list queries every entity of the chosen
+    /// types, with or without an industry.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an ID isn't a `Q` number.
+    pub fn set_industries(&mut self, industries: &[&str]) -> Result<()> {
+        self.industries = parse_item_ids(industries)?;
+        Ok(())
+    }
+
+    /// Returns the graph pattern shared by the count and main queries.
+    fn entity_pattern(&self) -> String {
+        let types = values(&self.entity_types);
+        if self.industries.is_empty() {
+            return format!(
+                r#"  VALUES ?type {{ {} }}
+  ?company wdt:P31/wdt:P279* ?type ;
+           rdfs:label ?companyName .
+  FILTER(LANG(?companyName) = "en")
+  OPTIONAL {{ ?company wdt:P452 ?industry }}"#,
+                types
+            );
+        }
+        format!(
+            r#"  VALUES ?type {{ {} }}
+  VALUES ?industry {{ {} }}
+  ?company wdt:P31/wdt:P279* ?type ;
+           wdt:P452 ?industry ;
+           rdfs:label ?companyName .
+  FILTER(LANG(?companyName) = "en")"#,
+            types,
+            values(&self.industries)
+        )
+    }
+
+    /// Get count query SPARQL
+    pub fn get_count_query(&self) -> String {
+        format!(
+            "SELECT (COUNT(DISTINCT ?company) as ?count)\nWHERE {{\n{}\n}}",
+            self.entity_pattern()
+        )
+    }
+
+    /// Get main query SPARQL
+    pub fn get_main_query(&self) -> String {
+        format!(
+            r#"SELECT DISTINCT ?company ?companyName ?industry ?inception ?owns ?ownsName ?ownedBy ?ownedByName
+WHERE {{
+{}
+
+  OPTIONAL {{ ?company wdt:P571 ?inception }}
+
+  OPTIONAL {{
+    ?company wdt:P1830 ?owns .
+    OPTIONAL {{
+      ?owns rdfs:label ?ownsName .
+      FILTER(LANG(?ownsName) = "en")
+    }}
+  }}
+
+  OPTIONAL {{
+    ?company wdt:P127 ?ownedBy .
+    OPTIONAL {{
+      ?ownedBy rdfs:label ?ownedByName .
+      FILTER(LANG(?ownedByName) = "en")
+    }}
+  }}
+}}
+ORDER BY ?companyName"#,
+            self.entity_pattern()
+        )
+    }
+
+    /// Execute a SPARQL query against the configured endpoint and return the
+    /// response body
+    async fn execute_sparql_query(&mut self, query: &str, accept: &str) -> Result<Vec<u8>> {
+        let endpoint = self.endpoint.clone();
+        self.execute_custom_query(&endpoint, query, accept).await
+    }
+
+    /// POST `query` to the SPARQL `endpoint` through Tor and return the
+    /// response body, requesting the result format `accept` (e.g.
+    /// `text/csv` or `application/sparql-results+json`).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the endpoint returns an
+    /// error status.
+    pub async fn execute_custom_query(
+        &mut self,
+        endpoint: &str,
+        query: &str,
+        accept: &str,
+    ) -> Result<Vec<u8>> {
+        let (headers, body) = query_request(query, accept);
+        info!("Executing SPARQL query against {} through Tor...", endpoint);
+        let (response, _) = self
+            .downloader
+            .request(endpoint, HttpMethod::Post, &headers, Some(body.as_bytes()))
+            .await
+            .context("Failed to execute SPARQL query")?;
+
+        Ok(response)
+    }
+
+    /// Run a SELECT `query` against `endpoint` and convert the CSV result to
+    /// Turtle with `mapping`, pretty-printed unless `set_pretty(false)`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails, or the result can't be mapped
+    /// (see `csv_to_turtle_with`).
+    pub async fn query_to_turtle(
+        &mut self,
+        endpoint: &str,
+        query: &str,
+        mapping: &CsvMapping,
+    ) -> Result<String> {
+        let csv = self
+            .execute_custom_query(endpoint, query, "text/csv")
+            .await?;
+        let graph = mapping.graph(&csv)?;
+        info!("Mapped {} triples", graph.len());
+        graph.to_turtle(self.pretty)
+    }
+
+    /// Get the count of matching entities
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails or the response has no count.
+    pub async fn get_company_count(&mut self) -> Result<usize> {
+        let query = self.get_count_query();
+        self.count_entities(&query).await
+    }
+
+    /// Run a `SELECT (COUNT(...) as ?count)` query against the configured
+    /// endpoint (see `set_endpoint`) and return the count.
+    ///
+    /// This is the count-only path: it scopes a query without downloading or
+    /// converting any results.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails or the response has no count.
+    pub async fn count_entities(&mut self, count_query: &str) -> Result<usize> {
+        let response = self
+            .execute_sparql_query(count_query, "application/sparql-results+json")
+            .await?;
+        Self::parse_count_response(&response)
+    }
+
+    /// Extract the `?count` value from a SPARQL JSON count response
+    fn parse_count_response(response: &[u8]) -> Result<usize> {
+        let bindings =
+            sparql::parse_bindings(response).context("Failed to parse count response")?;
+        bindings
+            .first()
+            .and_then(|binding| binding.get("count"))
+            .context("No count found in response")?
+            .value
+            .parse()
+            .context("Failed to parse count value")
+    }
+
+    /// Run a SELECT query against the configured endpoint (see
+    /// `set_endpoint`) and return its rows as typed bindings, keyed by
+    /// variable name.
+    ///
+    /// The whole result is held in memory, so this suits small result sets;
+    /// use `select_stream` for large ones.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails or the response isn't a SPARQL
+    /// JSON SELECT result.
+    pub async fn select(&mut self, query: &str) -> Result<Vec<SparqlBinding>> {
+        let response = self
+            .execute_sparql_query(query, "application/sparql-results+json")
+            .await?;
+        Self::parse_select_response(&response)
+    }
+
+    /// Run a SELECT query against the configured endpoint like `select`, but
+    /// yield its rows one at a time instead of collecting them.
+    ///
+    /// Rows are parsed with `sparql::binding_stream` straight off the Tor
+    /// response (see `TorDownloader::request_stream`), so only the row being
+    /// parsed and one read buffer are held in memory, however large the
+    /// result set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails; the stream yields an error for a
+    /// row that doesn't parse or if the connection fails partway.
+    pub async fn select_stream(
+        &mut self,
+        query: &str,
+    ) -> Result<impl Stream<Item = Result<SparqlBinding>> + use<>> {
+        let (headers, body) = query_request(query, "application/sparql-results+json");
+        info!(
+            "Streaming SPARQL query results from {} through Tor...",
+            self.endpoint
+        );
+        let response = self
+            .downloader
+            .request_stream(
+                &self.endpoint,
+                HttpMethod::Post,
+                &headers,
+                Some(body.as_bytes()),
+            )
+            .await
+            .context("Failed to execute SPARQL query")?;
+        Ok(sparql::binding_stream(response))
+    }
+
+    /// Extract the rows of a SPARQL JSON SELECT response
+    fn parse_select_response(response: &[u8]) -> Result<Vec<SparqlBinding>> {
+        sparql::parse_bindings(response).context("Failed to parse SELECT response")
+    }
+
+    /// Run a CONSTRUCT query against the configured endpoint and parse the
+    /// graph it returns as Turtle.
+    ///
+    /// Servers sometimes return slightly non-conformant Turtle. In lenient
+    /// mode (see `set_lenient`) such a response is returned as
+    /// `ConstructOutput::Raw` so it can be inspected or fixed by hand.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails, or, unless lenient, if the
+    /// response isn't valid Turtle.
+    pub async fn download_construct(&mut self, query: &str) -> Result<ConstructOutput> {
+        let response = self.execute_sparql_query(query, "text/turtle").await?;
+        self.parse_construct_response(response)
+    }
+
+    /// Parse a CONSTRUCT response, falling back to the raw bytes when lenient
+    fn parse_construct_response(&self, response: Vec<u8>) -> Result<ConstructOutput> {
+        let parsed = std::str::from_utf8(&response)
+            .context("CONSTRUCT response isn't UTF-8")
+            .and_then(|turtle| {
+                parse_turtle(turtle).context("Failed to parse CONSTRUCT response as Turtle")
+            });
+        match parsed {
+            Ok(graph) => {
+                info!("Downloaded {} triples", graph.len());
+                Ok(ConstructOutput::Graph(graph))
+            }
+            Err(e) if self.lenient => {
+                warn!("{:#}; returning the raw response", e);
+                Ok(ConstructOutput::Raw(response))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Look up candidate entities by label with the `wbsearchentities` API,
+    /// returning `(qid, label)` pairs in relevance order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the response can't be parsed.
+    pub async fn search_entity(
+        &mut self,
+        label: &str,
+        lang: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let url = format!(
+            "https://www.wikidata.org/w/api.php?action=wbsearchentities&search={}&language={}&uselang={}&type=item&format=json",
+            urlencoding::encode(label),
+            urlencoding::encode(lang),
+            urlencoding::encode(lang)
+        );
+        let headers = vec![
+            "Accept: application/json".to_string(),
+            "User-Agent: OSINT-Research-Bot/1.0".to_string(),
+        ];
+
+        info!("Searching Wikidata for '{}' through Tor...", label);
+        let (response, _) = self
+            .downloader
+            .request(&url, HttpMethod::Get, &headers, None)
+            .await
+            .context("Failed to search Wikidata entities")?;
+
+        Self::parse_search_response(&response)
+    }
+
+    /// Extract `(qid, label)` candidates from a `wbsearchentities` response
+    fn parse_search_response(response: &[u8]) -> Result<Vec<(String, String)>> {
+        #[derive(Deserialize)]
+        struct SearchResponse {
+            #[serde(default)]
+            search: Vec<SearchMatch>,
+        }
+
+        #[derive(Deserialize)]
+        struct SearchMatch {
+            id: String,
+            #[serde(default)]
+            label: Option<String>,
+        }
+
+        let response: SearchResponse =
+            serde_json::from_slice(response).context("Failed to parse entity search response")?;
+
+        Ok(response
+            .search
+            .into_iter()
+            .map(|m| {
+                let label = m.label.unwrap_or_else(|| m.id.clone());
+                (m.id, label)
+            })
+            .collect())
+    }
+
+    /// Download the main query's results as CSV
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails or the CSV can't be written.
+    pub async fn download_companies_csv(&mut self) -> Result<PathBuf> {
+        let query = self.get_main_query();
+        let response = self.execute_sparql_query(&query, "text/csv").await?;
+
+        let csv_path = self.data_dir.join("security_companies.csv");
+        fs::write(&csv_path, response).context("Failed to write CSV file")?;
+
+        info!(
+            "Downloaded {} bytes to {}",
+            fs::metadata(&csv_path)?.len(),
+            csv_path.display()
+        );
+
+        Ok(csv_path)
+    }
+
+    /// Convert CSV to RDF Turtle format, typing each entity with
+    /// `DEFAULT_ENTITY_TYPES`
+    ///
+    /// IMPORTANT: This CSV to RDF conversion is a necessary workaround for Wikidata's
+    /// SPARQL endpoint limitations. While Wikidata technically supports CONSTRUCT queries
+    /// that can return RDF directly, in practice:
+    ///
+    /// 1. CONSTRUCT queries are significantly slower than SELECT queries
+    /// 2. CONSTRUCT queries often timeout for larger result sets
+    /// 3. SELECT queries with CSV output are much more performant
+    ///
+    /// Therefore, we use SELECT → CSV → RDF transformation as a pragmatic solution
+    /// that provides better performance and reliability when working with Wikidata.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CSV can't be read or parsed.
+    pub fn csv_to_rdf(csv_path: &Path) -> Result<String> {
+        Self::csv_to_turtle(csv_path, true)
+    }
+
+    /// Convert CSV to Turtle, either `pretty` (each subject's triples in one
+    /// block, with `;` predicate lists and `,` object lists) or as one
+    /// complete N-Triples statement per line
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CSV can't be read or parsed.
+    pub fn csv_to_turtle(csv_path: &Path, pretty: bool) -> Result<String> {
+        Self::csv_to_turtle_with(csv_path, &CsvMapping::companies(), pretty)
+    }
+
+    /// Convert any SELECT CSV to Turtle, mapping its columns with `mapping`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CSV can't be read or parsed, lacks the
+    /// subject column, or the mapping uses an unknown prefix.
+    pub fn csv_to_turtle_with(
+        csv_path: &Path,
+        mapping: &CsvMapping,
+        pretty: bool,
+    ) -> Result<String> {
+        let csv = fs::read(csv_path).context("Failed to read CSV file")?;
+        mapping.graph(&csv)?.to_turtle(pretty)
+    }
+
+    /// Convert the CSV to N-Triples, one triple per line with full IRIs,
+    /// typing each entity with `DEFAULT_ENTITY_TYPES`.
+    ///
+    /// Unlike the Turtle output, every line is a self-contained statement, so
+    /// results from separate runs can be merged line by line.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CSV can't be read or parsed.
+    pub fn csv_to_ntriples(csv_path: &Path) -> Result<Vec<String>> {
+        Self::csv_to_ntriples_with(csv_path, &CsvMapping::companies())
+    }
+
+    /// Convert any SELECT CSV to N-Triples, mapping its columns with
+    /// `mapping`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CSV can't be read or parsed, lacks the
+    /// subject column, or the mapping uses an unknown prefix.
+    pub fn csv_to_ntriples_with(csv_path: &Path, mapping: &CsvMapping) -> Result<Vec<String>> {
+        let csv = fs::read(csv_path).context("Failed to read CSV file")?;
+        mapping.graph(&csv)?.to_ntriples()
+    }
+
+    /// Append N-Triples to `nt_path`, skipping any already present.
+    ///
+    /// The existing file is read, merged with `triples`, and rewritten in
+    /// place. Returns the number of triples that were new.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read or written.
+    pub fn append_ntriples(nt_path: &Path, triples: &[String]) -> Result<usize> {
+        let existing = match fs::read_to_string(nt_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).context("Failed to read existing N-Triples file"),
+        };
+
+        let mut seen = HashSet::new();
+        let mut merged = Vec::new();
+        for line in existing.lines().map(str::trim) {
+            if !line.is_empty() && !line.starts_with('#') && seen.insert(line.to_string()) {
+                merged.push(line.to_string());
+            }
+        }
+
+        let before = merged.len();
+        for triple in triples {
+            if seen.insert(triple.clone()) {
+                merged.push(triple.clone());
+            }
+        }
+        let added = merged.len() - before;
+
+        let mut content = merged.join("\n");
+        content.push('\n');
+        fs::write(nt_path, content).context("Failed to write N-Triples file")?;
+
+        Ok(added)
+    }
+
+    /// Complete workflow: count the matching entities, download them as CSV,
+    /// and convert that to Turtle
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a query fails, more than 10,000 entities match,
+    /// or a file can't be written.
+    pub async fn download_and_convert(&mut self) -> Result<PathBuf> {
+        info!("=== Downloading entities from Wikidata ===");
+        info!("Entity types: {}", self.entity_types.join(", "));
+        if self.industries.is_empty() {
+            info!("Industries: any");
+        } else {
+            info!("Industries: {}", self.industries.join(", "));
+        }
+
+        // Step 1: Get count
+        info!("Step 1: Counting entities...");
+        let count = self.get_company_count().await?;
+        info!("Total matching entities: {}", count);
+
+        if count > 10000 {
+            anyhow::bail!("Too many entities ({}). This might timeout.", count);
+        }
+
+        // Step 2: Download CSV
+        info!("Step 2: Downloading entity data...");
+        let csv_path = self.download_companies_csv().await?;
+
+        // Count rows
+        let csv_content = fs::read_to_string(&csv_path)?;
+        let row_count = count_csv_records(csv_content.as_bytes())?;
+        info!("Downloaded {} rows", row_count);
+
+        // Step 3: Convert to RDF
+        info!("Step 3: Converting to RDF...");
+        let companies = CsvMapping::companies_of(&self.entity_types).map(csv_content.as_bytes())?;
+        let entities = companies.len();
+        let rdf_content = to_graph(companies)?.to_turtle(self.pretty)?;
+
+        let ttl_path = self.data_dir.join("security_companies.ttl");
+        fs::write(&ttl_path, rdf_content)?;
+
+        info!("=== Download Complete ===");
+        info!("CSV file: {}", csv_path.display());
+        info!("RDF file: {}", ttl_path.display());
+        info!("Total entities: {}", entities);
+        info!("All data was downloaded through Tor for privacy.");
+
+        Ok(ttl_path)
+    }
+
+    /// Download the CSV and merge its triples into the N-Triples file at
+    /// `nt_path`, creating it if needed.
+    ///
+    /// Running several focused queries this way accumulates one growing,
+    /// de-duplicated graph instead of overwriting the previous results.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails or a file can't be read or
+    /// written.
+    pub async fn download_and_append(&mut self, nt_path: &Path) -> Result<PathBuf> {
+        let csv_path = self.download_companies_csv().await?;
+        let mapping = CsvMapping::companies_of(&self.entity_types);
+        let triples = Self::csv_to_ntriples_with(&csv_path, &mapping)?;
+        let added = Self::append_ntriples(nt_path, &triples)?;
+        info!(
+            "Appended {} new triples ({} already present) to {}",
+            added,
+            triples.len() - added,
+            nt_path.display()
+        );
+
+        Ok(nt_path.to_path_buf())
+    }
+
+    /// Runs the complete workflow and bundles the raw CSV, the generated RDF,
+    /// and the SPARQL query into a gzip-compressed tarball at `out_path`.
+    ///
+    /// A `manifest.json` member records the row and entity counts and the
+    /// creation time so the results can be reproduced later.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `download_and_convert` fails or the archive can't
+    /// be written.
+    pub async fn download_and_convert_archive(&mut self, out_path: &Path) -> Result<PathBuf> {
+        let ttl_path = self.download_and_convert().await?;
+        let csv_path = self.data_dir.join("security_companies.csv");
+
+        let csv = fs::read(&csv_path).context("Failed to read CSV file")?;
+        let entities = CsvMapping::companies_of(&self.entity_types)
+            .map(&csv)?
+            .len();
+        Self::write_archive(
+            out_path,
+            &csv_path,
+            &ttl_path,
+            &self.get_main_query(),
+            entities,
+        )?;
+        info!("Archive written to {}", out_path.display());
+
+        Ok(out_path.to_path_buf())
+    }
+
+    /// Write the CSV, RDF, query text, and manifest into a `.tar.gz` archive
+    fn write_archive(
+        out_path: &Path,
+        csv_path: &Path,
+        ttl_path: &Path,
+        query: &str,
+        entities: usize,
+    ) -> Result<()> {
+        let csv_content = fs::read(csv_path).context("Failed to read CSV file")?;
+        let rdf_content = fs::read(ttl_path).context("Failed to read RDF file")?;
+
+        let manifest = serde_json::json!({
+            "created_unix": SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            "csv_rows": count_csv_records(&csv_content)?,
+            "companies": entities,
+            "members": ["security_companies.csv", "security_companies.ttl", "query.sparql"],
+        });
+        let manifest = serde_json::to_vec_pretty(&manifest)?;
+
+        let file = fs::File::create(out_path).context("Failed to create archive")?;
+        let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        let mut archive = tar::Builder::new(encoder);
+
+        let members: [(&str, &[u8]); 4] = [
+            ("security_companies.csv", &csv_content),
+            ("security_companies.ttl", &rdf_content),
+            ("query.sparql", query.as_bytes()),
+            ("manifest.json", &manifest),
+        ];
+        for (name, data) in members {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive
+                .append_data(&mut header, name, data)
+                .with_context(|| format!("Failed to add {} to archive", name))?;
+        }
+
+        archive.into_inner()?.finish()?;
+        Ok(())
+    }
+}
+
+/// Converts SPARQL JSON result rows (see `sparql::parse_bindings`) to RDF,
+/// using `mapping` for the subject, classes, and predicates.
+///
+/// Unlike the CSV path, each value keeps the type the endpoint reported:
+/// `uri`s become resources, `bnode`s blank nodes, and literals keep their
+/// language tag or `xsd` datatype. So an inception date is only typed as
+/// `xsd:dateTime` when Wikidata says it is one.
+///
+/// # Errors
+///
+/// Returns an error if the mapping uses an unknown prefix, a value has an
+/// unknown type, or a subject is a literal.
+pub fn json_bindings_to_rdf(bindings: &[SparqlBinding], mapping: &CsvMapping) -> Result<Graph> {
+    to_graph(mapping.map_bindings(bindings)?)
+}
+
+/// Converts a SPARQL JSON results value to a term.
+fn binding_term(value: &SparqlValue) -> Result<Term> {
+    match value.value_type.as_str() {
+        "uri" => Ok(Term::iri(&value.value)),
+        "bnode" => Ok(Term::blank(&value.value)),
+        // `typed-literal` is from the pre-1.1 format, still sent by some stores
+        "literal" | "typed-literal" => Ok(match (&value.lang, &value.datatype) {
+            (Some(lang), _) => Term::lang_literal(&value.value, lang),
+            (None, Some(datatype)) => Term::typed_literal(&value.value, datatype),
+            (None, None) => Term::literal(&value.value),
+        }),
+        other => anyhow::bail!("Unknown SPARQL value type '{}'", other),
+    }
+}
+
+/// Returns the headers and form body that POST `query` to a SPARQL
+/// endpoint, asking for results as `accept`.
+fn query_request(query: &str, accept: &str) -> (Vec<String>, String) {
+    let headers = vec![
+        format!("Accept: {}", accept),
+        "Accept-Encoding: gzip".to_string(),
+        "User-Agent: OSINT-Research-Bot/1.0".to_string(),
+        "Content-Type: application/x-www-form-urlencoded".to_string(),
+    ];
+    (headers, format!("query={}", urlencoding::encode(query)))
+}
+
+/// Builds a graph declaring `PREFIXES` from grouped triples.
+fn to_graph(subjects: BTreeMap<String, Vec<Triple>>) -> Result<Graph> {
+    let mut graph = Graph::new();
+    for (prefix, namespace) in PREFIXES {
+        graph.add_prefix(prefix, namespace)?;
+    }
+    for (subject, predicate, object) in subjects.into_values().flatten() {
+        graph.add(subject, &predicate, object)?;
+    }
+    Ok(graph)
+}
+
+/// Returns the IRI of an entity value: the value itself if it's an IRI,
+/// otherwise the Wikidata entity with that item ID.
+fn entity_iri(value: &str) -> String {
+    if value.contains("://") {
+        value.to_string()
+    } else {
+        format!("{}{}", PREFIXES[0].1, value)
+    }
+}
+
+/// Expands a mapping term (`a`, a prefixed name, or an `<IRI>`) to an IRI.
+fn expand_term(term: &str) -> Result<String> {
+    if term == "a" {
+        return Ok(RDF_TYPE.to_string());
+    }
+    if let Some(iri) = term.strip_prefix('<').and_then(|t| t.strip_suffix('>')) {
+        return Ok(iri.to_string());
+    }
+    let (prefix, local) = term
+        .split_once(':')
+        .with_context(|| format!("Invalid RDF term '{}'", term))?;
+    let (_, namespace) = PREFIXES
+        .iter()
+        .find(|(p, _)| *p == prefix)
+        .with_context(|| format!("Unknown prefix '{}' in '{}'", prefix, term))?;
+    Ok(format!("{}{}", namespace, local))
+}
+
+/// Parses a literal suffix into its language tag or datatype IRI.
+fn parse_suffix(suffix: &str) -> Result<(Option<String>, Option<String>)> {
+    if suffix.is_empty() {
+        return Ok((None, None));
+    }
+    if let Some(lang) = suffix.strip_prefix('@') {
+        if !is_language_tag(lang) {
+            anyhow::bail!("Invalid language tag '{}'", lang);
+        }
+        return Ok((Some(lang.to_string()), None));
+    }
+    match suffix.strip_prefix("^^") {
+        Some(datatype) => Ok((None, Some(expand_term(datatype)?))),
+        None => anyhow::bail!(
+            "Invalid literal suffix '{}': expected @lang or ^^datatype",
+            suffix
+        ),
+    }
+}
+
+/// Checks that each of `ids` is a Wikidata item ID like `Q42`.
+fn parse_item_ids(ids: &[&str]) -> Result<Vec<String>> {
+    ids.iter()
+        .map(|id| {
+            let id = id.trim();
+            let is_item = id
+                .strip_prefix('Q')
+                .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+            if !is_item {
+                anyhow::bail!("Invalid Wikidata item ID '{}': expected e.g. Q42", id);
+            }
+            Ok(id.to_string())
+        })
+        .collect()
+}
+
+/// Counts the records of `csv` after the header. Quoted fields may span
+/// lines, so this reads records rather than counting newlines.
+fn count_csv_records(csv: &[u8]) -> Result<usize> {
+    let mut reader = csv::Reader::from_reader(csv);
+    let mut record = csv::ByteRecord::new();
+    let mut count = 0;
+    while reader
+        .read_byte_record(&mut record)
+        .context("Failed to read CSV record")?
+    {
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Formats `ids` as the terms of a SPARQL `VALUES` block.
+fn values(ids: &[String]) -> String {
+    ids.iter()
+        .map(|id| format!("wd:{}", id))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_count_response() -> Result<()> {
+        let response = br#"{
+  "head": {"vars": ["count"]},
+  "results": {"bindings": [
+    {"count": {"datatype": "http://www.w3.org/2001/XMLSchema#integer", "type": "literal", "value": "1234"}}
+  ]}
+}"#;
+        assert_eq!(WikidataDownloader::parse_count_response(response)?, 1234);
+
+        let empty = br#"{"head": {"vars": ["count"]}, "results": {"bindings": []}}"#;
+        assert!(WikidataDownloader::parse_count_response(empty).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_select_response() -> Result<()> {
+        // Two-row SELECT result as returned by the SPARQL endpoint
+        let response = include_bytes!("../tests/data/sparql_select_results.json");
+        let rows = WikidataDownloader::parse_select_response(response)?;
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            rows[0]["company"].value,
+            "http://www.wikidata.org/entity/Q123"
+        );
+        assert_eq!(rows[0]["company"].value_type, "uri");
+        assert_eq!(rows[0]["companyName"].lang.as_deref(), Some("en"));
+        assert_eq!(rows[1]["companyName"].value, "Brace {and} \"Quote\" Ltd");
+        assert!(!rows[1].contains_key("inception"));
+
+        assert!(WikidataDownloader::parse_select_response(b"<html>").is_err());
+        Ok(())
+    }
+
+    /// A downloader whose connections reach a mock SPARQL endpoint. Every
+    /// request is answered with the JSON `response`, and its decoded query
+    /// is sent to the returned receiver.
+    fn mock_endpoint(
+        response: &'static [u8],
+    ) -> (TorDownloader, tokio::sync::mpsc::UnboundedReceiver<String>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (queries_tx, queries) = tokio::sync::mpsc::unbounded_channel();
+        let downloader = TorDownloader::with_connector(move |_host, _port| {
+            let (client, mut server) = tokio::io::duplex(64 * 1024);
+            let queries_tx = queries_tx.clone();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                let head_end = loop {
+                    if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break end + 4;
+                    }
+                    let n = server.read(&mut buf).await?;
+                    request.extend_from_slice(&buf[..n]);
+                };
+                let head = String::from_utf8_lossy(&request[..head_end]).to_lowercase();
+                let len: usize = head
+                    .split("\r\ncontent-length: ")
+                    .nth(1)
+                    .and_then(|rest| rest.split("\r\n").next())
+                    .and_then(|len| len.parse().ok())
+                    .unwrap_or_default();
+                while request.len() < head_end + len {
+                    let n = server.read(&mut buf).await?;
+                    request.extend_from_slice(&buf[..n]);
+                }
+
+                let form = String::from_utf8_lossy(&request[head_end..]);
+                let query = form.strip_prefix("query=").unwrap_or(&form);
+                let query = urlencoding::decode(&query.replace('+', " "))
+                    .map(|query| query.into_owned())
+                    .unwrap_or_default();
+                let _ = queries_tx.send(query);
+
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\n\
+                     Content-Type: application/sparql-results+json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    response.len()
+                );
+                server.write_all(head.as_bytes()).await?;
+                server.write_all(response).await
+            });
+            async { Ok(client) }
+        });
+        (downloader, queries)
+    }
+
+    #[tokio::test]
+    async fn test_count_only_sends_only_the_count_query() -> Result<()> {
+        let response = br#"{"head": {"vars": ["count"]}, "results": {"bindings": [
+            {"count": {"datatype": "http://www.w3.org/2001/XMLSchema#integer", "type": "literal", "value": "42"}}
+        ]}}"#;
+        let (mock, mut queries) = mock_endpoint(response);
+        let temp_dir = tempfile::tempdir()?;
+        let mut downloader =
+            WikidataDownloader::with_downloader(mock, temp_dir.path().to_path_buf())?;
+        downloader.set_endpoint("http://sparql.example/sparql");
+
+        assert_eq!(downloader.get_company_count().await?, 42);
+        assert_eq!(queries.recv().await, Some(downloader.get_count_query()));
+        drop(downloader);
+        assert_eq!(queries.recv().await, None, "only the count query is sent");
+        assert_eq!(
+            fs::read_dir(temp_dir.path())?.count(),
+            0,
+            "no CSV/RDF written"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_select_returns_typed_rows() -> Result<()> {
+        let (mock, mut queries) =
+            mock_endpoint(include_bytes!("../tests/data/sparql_select_results.json"));
+        let temp_dir = tempfile::tempdir()?;
+        let mut downloader =
+            WikidataDownloader::with_downloader(mock, temp_dir.path().to_path_buf())?;
+        downloader.set_endpoint("http://sparql.example/sparql");
+
+        let query = "SELECT ?company ?companyName WHERE { ?company wdt:P31 wd:Q4830453 }";
+        let rows = downloader.select(query).await?;
+        assert_eq!(queries.recv().await.as_deref(), Some(query));
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["company"].value_type, "uri");
+        assert_eq!(rows[0]["companyName"].lang.as_deref(), Some("en"));
+        assert_eq!(rows[1]["companyName"].value, "Brace {and} \"Quote\" Ltd");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_select_stream_yields_rows_from_the_download() -> Result<()> {
+        use futures::StreamExt;
+
+        let (mock, mut queries) =
+            mock_endpoint(include_bytes!("../tests/data/sparql_select_results.json"));
+        let temp_dir = tempfile::tempdir()?;
+        let mut downloader =
+            WikidataDownloader::with_downloader(mock, temp_dir.path().to_path_buf())?;
+        downloader.set_endpoint("http://sparql.example/sparql");
+
+        let query = "SELECT ?company ?companyName WHERE { ?company wdt:P31 wd:Q4830453 }";
+        let rows = downloader.select_stream(query).await?;
+        futures::pin_mut!(rows);
+        assert_eq!(queries.recv().await.as_deref(), Some(query));
+
+        let first = rows.next().await.context("no first row")??;
+        assert_eq!(
+            first["company"].value,
+            "http://www.wikidata.org/entity/Q123"
+        );
+        let second = rows.next().await.context("no second row")??;
+        assert_eq!(second["companyName"].value, "Brace {and} \"Quote\" Ltd");
+        assert!(rows.next().await.is_none());
+        // Nothing is buffered to disk
+        assert_eq!(fs::read_dir(temp_dir.path())?.count(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lenient_construct_returns_malformed_turtle_raw() -> Result<()> {
+        // `rdfs:` is used without being declared
+        let malformed: &[u8] = b"@prefix wd: <http://www.wikidata.org/entity/> .\n\
+                                 wd:Q1 rdfs:label \"Acme\"@en .\n";
+        let query = "CONSTRUCT { ?s rdfs:label ?l } WHERE { ?s rdfs:label ?l }";
+        let temp_dir = tempfile::tempdir()?;
+        let downloader = |response| {
+            let (mock, _) = mock_endpoint(response);
+            let mut downloader =
+                WikidataDownloader::with_downloader(mock, temp_dir.path().to_path_buf())?;
+            downloader.set_endpoint("http://sparql.example/sparql");
+            anyhow::Ok(downloader)
+        };
+
+        let mut strict = downloader(malformed)?;
+        let err = strict.download_construct(query).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("Failed to parse CONSTRUCT response"));
+
+        let mut lenient = downloader(malformed)?;
+        lenient.set_lenient(true);
+        match lenient.download_construct(query).await? {
+            ConstructOutput::Raw(raw) => assert_eq!(raw, malformed),
+            ConstructOutput::Graph(_) => panic!("malformed Turtle parsed"),
+        }
+
+        let mut lenient = downloader(b"<http://example.org/s> <http://example.org/p> \"o\" .\n")?;
+        lenient.set_lenient(true);
+        match lenient.download_construct(query).await? {
+            ConstructOutput::Graph(graph) => assert_eq!(graph.len(), 1),
+            ConstructOutput::Raw(_) => panic!("valid Turtle returned raw"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_search_response() -> Result<()> {
+        let response = br#"{
+  "searchinfo": {"search": "Palantir"},
+  "search": [
+    {"id": "Q2047336", "title": "Q2047336", "label": "Palantir Technologies",
+     "description": "American software company", "match": {"type": "label", "language": "en", "text": "Palantir"}},
+    {"id": "Q1060476", "label": "palantir", "description": "fictional artifact"},
+    {"id": "Q99999999"}
+  ],
+  "success": 1
+}"#;
+
+        let candidates = WikidataDownloader::parse_search_response(response)?;
+        assert_eq!(
+            candidates,
+            vec![
+                ("Q2047336".to_string(), "Palantir Technologies".to_string()),
+                ("Q1060476".to_string(), "palantir".to_string()),
+                ("Q99999999".to_string(), "Q99999999".to_string()),
+            ]
+        );
+
+        let no_matches = br#"{"searchinfo": {"search": "zzzz"}, "search": [], "success": 1}"#;
+        assert!(WikidataDownloader::parse_search_response(no_matches)?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_queries_use_configured_classes() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let mut downloader = WikidataDownloader::with_downloader(
+            TorDownloader::without_tor(),
+            temp_dir.path().to_path_buf(),
+        )?;
+
+        let default_query = downloader.get_main_query();
+        assert!(default_query.contains("VALUES ?type { wd:Q891723 wd:Q4830453 wd:Q163740 }"));
+        assert!(default_query.contains("VALUES ?industry { wd:Q3510521 wd:Q21157865"));
+        assert!(default_query.ends_with("ORDER BY ?companyName"));
+
+        downloader.set_entity_types(&["Q7397", "Q1668024"])?;
+        downloader.set_industries(&[])?;
+        let count_query = downloader.get_count_query();
+        assert!(count_query.starts_with("SELECT (COUNT(DISTINCT ?company) as ?count)"));
+        assert!(count_query.contains("VALUES ?type { wd:Q7397 wd:Q1668024 }"));
+        assert!(!count_query.contains("VALUES ?industry"));
+        assert!(count_query.contains("OPTIONAL { ?company wdt:P452 ?industry }"));
+
+        assert!(downloader.set_entity_types(&[]).is_err());
+        assert!(downloader.set_industries(&["wd:Q11451"]).is_err());
+        assert!(downloader.set_entity_types(&["Q"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_triples_use_given_types() -> Result<()> {
+        let csv = b"company,companyName\nhttp://www.wikidata.org/entity/Q42,Douglas Adams\n";
+        let rdf = CsvMapping::companies_of(&["Q5".to_string()])
+            .graph(csv)?
+            .to_turtle(true)?;
+        assert!(
+            rdf.contains("wd:Q42 a wd:Q5 ;\n\trdfs:label \"Douglas Adams\"@en .\n"),
+            "{}",
+            rdf
+        );
+        assert!(!rdf.contains("wdt:P452"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_labels_and_dates_are_serialized_safely() -> Result<()> {
+        let csv = "company,companyName,inception
+                   http://www.wikidata.org/entity/Q1,\"Two\nLines\tTabbed\",1990-00-00T00:00:00Z\n\
+                   http://www.wikidata.org/entity/Q2,Dated,2001-05-17T00:00:00Z\n";
+        let rdf = CsvMapping::companies()
+            .graph(csv.as_bytes())?
+            .to_turtle(false)?;
+        assert!(
+            rdf.contains(
+                "<http://www.wikidata.org/entity/Q1> <http://www.w3.org/2000/01/rdf-schema#label> \
+                 \"Two\\nLines\\tTabbed\"@en .\n"
+            ),
+            "{}",
+            rdf
+        );
+        assert!(rdf.contains(
+            "<http://www.wikidata.org/entity/Q1> <http://www.wikidata.org/prop/direct/P571> \
+             \"1990-00-00T00:00:00Z\" .\n"
+        ));
+        assert!(rdf.contains(
+            "<http://www.wikidata.org/entity/Q2> <http://www.wikidata.org/prop/direct/P571> \
+             \"2001-05-17T00:00:00Z\"^^<http://www.w3.org/2001/XMLSchema#dateTime> .\n"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_bindings_keep_reported_types() -> Result<()> {
+        let response = include_bytes!("../tests/data/sparql_select_results.json");
+        let mut bindings = sparql::parse_bindings(response)?;
+        // An inception the endpoint didn't type stays a plain literal
+        bindings[1].insert(
+            "inception".to_string(),
+            SparqlValue {
+                value_type: "literal".to_string(),
+                value: "circa 1990".to_string(),
+                lang: None,
+                datatype: None,
+            },
+        );
+
+        let rdf = json_bindings_to_rdf(&bindings, &CsvMapping::companies())?.to_turtle(true)?;
+        assert!(
+            rdf.contains(
+                "wd:Q123 a wd:Q891723 , wd:Q4830453 , wd:Q163740 ;\n\t\
+                 rdfs:label \"Test Corp\"@en ;\n"
+            ),
+            "{}",
+            rdf
+        );
+        assert!(rdf.contains("\twdt:P571 \"2020-01-01T00:00:00Z\"^^xsd:dateTime"));
+        assert!(rdf.contains("\trdfs:label \"Brace {and} \\\"Quote\\\" Ltd\"@en ;\n"));
+        assert!(rdf.contains("\twdt:P571 \"circa 1990\""));
+        assert_eq!(rdf.matches(" a wd:Q891723 , ").count(), 2);
+
+        let mut mapping = CsvMapping::new("company");
+        mapping.add_entity("companyName", "wdt:P1448", None);
+        bindings[0].get_mut("company").unwrap().value_type = "bnode".to_string();
+        bindings[0].get_mut("company").unwrap().value = "b0".to_string();
+        let rdf = json_bindings_to_rdf(&bindings[..1], &mapping)?.to_turtle(true)?;
+        assert!(
+            rdf.contains("_:b0 wdt:P1448 \"Test Corp\"@en .\n"),
+            "{}",
+            rdf
+        );
+
+        bindings[0].get_mut("company").unwrap().value_type = "literal".to_string();
+        assert!(json_bindings_to_rdf(&bindings[..1], &mapping).is_err());
+        bindings[0].get_mut("company").unwrap().value_type = "triple".to_string();
+        assert!(json_bindings_to_rdf(&bindings[..1], &mapping).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_csv_mapping() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let csv_path = temp_dir.path().join("people.csv");
+        fs::write(
+            &csv_path,
+            "person,name,employer,employerName,born\n\
+             http://www.wikidata.org/entity/Q7,\"Ada \"\"Countess\"\" L\",Q9,Acme,1815\n\
+             https://example.org/bob,Bob,,,\n\
+             ,Nobody,,,\n",
+        )?;
+
+        let mut mapping = CsvMapping::new("person");
+        mapping.set_types(&["wd:Q5"]);
+        mapping.add_literal("name", "<http://xmlns.com/foaf/0.1/name>", "");
+        mapping.add_entity("employer", "wdt:P108", Some("employerName"));
+        mapping.add_literal("born", "wdt:P569", "^^xsd:gYear");
+        mapping.add_literal("missing", "wdt:P1", "");
+
+        let rdf = WikidataDownloader::csv_to_turtle_with(&csv_path, &mapping, true)?;
+        assert!(
+            rdf.contains(
+                "wd:Q7 a wd:Q5 ;\n\t\
+                 <http://xmlns.com/foaf/0.1/name> \"Ada \\\"Countess\\\" L\" ;\n\t\
+                 wdt:P108 wd:Q9 ;\n\t\
+                 wdt:P569 \"1815\"^^xsd:gYear .\n"
+            ),
+            "{}",
+            rdf
+        );
+        assert!(rdf.contains("wd:Q9 rdfs:label \"Acme\"@en .\n"));
+        assert!(rdf.contains("<https://example.org/bob> a wd:Q5 ;\n"));
+        assert!(!rdf.contains("Nobody"));
+
+        let triples = WikidataDownloader::csv_to_ntriples_with(&csv_path, &mapping)?;
+        assert!(
+            triples.contains(
+                &"<http://www.wikidata.org/entity/Q7> <http://www.wikidata.org/prop/direct/P569> \
+              \"1815\"^^<http://www.w3.org/2001/XMLSchema#gYear> ."
+                    .to_string()
+            )
+        );
+
+        mapping.add_entity("employer", "foaf:knows", None);
+        assert!(WikidataDownloader::csv_to_turtle_with(&csv_path, &mapping, true).is_err());
+        assert!(
+            WikidataDownloader::csv_to_turtle_with(&csv_path, &CsvMapping::new("item"), true)
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_archive_members() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let csv_path = temp_dir.path().join("security_companies.csv");
+        let ttl_path = temp_dir.path().join("security_companies.ttl");
+        fs::write(
+            &csv_path,
+            "company,companyName\nhttp://www.wikidata.org/entity/Q123,\"Test\nCorp\"\n",
+        )?;
+        fs::write(
+            &ttl_path,
+            "wd:Q123 a wd:Q891723, wd:Q4830453, wd:Q163740 .\n",
+        )?;
+
+        let archive_path = temp_dir.path().join("results.tar.gz");
+        WikidataDownloader::write_archive(&archive_path, &csv_path, &ttl_path, "SELECT * {}", 1)?;
+
+        let mut archive =
+            tar::Archive::new(flate2::read::GzDecoder::new(fs::File::open(&archive_path)?));
+        let mut members = std::collections::HashMap::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().to_string();
+            let mut content = String::new();
+            std::io::Read::read_to_string(&mut entry, &mut content)?;
+            members.insert(name, content);
+        }
+
+        assert!(members.contains_key("security_companies.csv"));
+        assert!(members.contains_key("security_companies.ttl"));
+        assert_eq!(members["query.sparql"], "SELECT * {}");
+
+        let manifest: serde_json::Value = serde_json::from_str(&members["manifest.json"])?;
+        assert_eq!(manifest["csv_rows"], 1);
+        assert_eq!(manifest["companies"], 1);
+
+        Ok(())
+    }
+}